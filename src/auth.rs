@@ -1,23 +1,167 @@
-use oauth2::basic::BasicClient;
+use chrono::{DateTime, Duration, Utc};
+use oauth2::basic::{BasicClient, BasicErrorResponseType, BasicTokenResponse};
 use oauth2::reqwest::async_http_client;
 use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
-    RedirectUrl, Scope, TokenUrl,
+    RedirectUrl, RefreshToken, RequestTokenError, Scope, TokenResponse, TokenUrl,
 };
+use serde::{Deserialize, Serialize};
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 use url::Url;
 use crate::error::{TodoError, TodoResult};
+use crate::{files, paths};
 use tokio::fs;
 
-pub async fn login() -> TodoResult<()> {
-    let client = BasicClient::new(
+const TOKEN_FILE: &str = "token.json";
+const DEFAULT_TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v3/token";
+
+/// Tokens are refreshed this long before they expire, so a request never
+/// starts with a token that runs out mid-flight.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+fn oauth_client() -> TodoResult<BasicClient> {
+    let token_url = std::env::var("GOOGLE_TOKEN_URL").unwrap_or_else(|_| DEFAULT_TOKEN_URL.to_string());
+    Ok(BasicClient::new(
         ClientId::new(std::env::var("GOOGLE_CLIENT_ID").map_err(|_| TodoError::ConfigError("GOOGLE_CLIENT_ID not set".to_string()))?),
         Some(ClientSecret::new(std::env::var("GOOGLE_CLIENT_SECRET").map_err(|_| TodoError::ConfigError("GOOGLE_CLIENT_SECRET not set".to_string()))?)),
         AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".to_string()).map_err(|e| TodoError::AuthError(e.to_string()))?,
-        Some(TokenUrl::new("https://www.googleapis.com/oauth2/v3/token".to_string()).map_err(|e| TodoError::AuthError(e.to_string()))?),
-    )
-    .set_redirect_uri(RedirectUrl::new("http://localhost:8080".to_string()).map_err(|e| TodoError::AuthError(e.to_string()))?);
+        Some(TokenUrl::new(token_url).map_err(|e| TodoError::AuthError(e.to_string()))?),
+    ))
+}
+
+/// The contents of `token.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredToken {
+    access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    /// Absent in files written before expiry was tracked, and for tokens
+    /// that never expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    /// Lifetime reported by older files that saved the raw token response.
+    #[serde(default, skip_serializing)]
+    expires_in: Option<i64>,
+}
+
+impl StoredToken {
+    /// Builds the stored form of a token response. Refresh responses usually
+    /// leave out the refresh token, in which case `previous` is kept.
+    fn from_response(response: &BasicTokenResponse, previous: Option<String>, now: DateTime<Utc>) -> Self {
+        Self {
+            access_token: response.access_token().secret().clone(),
+            refresh_token: response.refresh_token().map(|token| token.secret().clone()).or(previous),
+            expires_at: response.expires_in()
+                .and_then(|lifetime| Duration::from_std(lifetime).ok())
+                .map(|lifetime| now + lifetime),
+            expires_in: None,
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at - Duration::seconds(EXPIRY_MARGIN_SECS) <= now)
+    }
+}
+
+/// Hands out valid access tokens, refreshing and persisting them as needed.
+pub struct TokenManager {
+    client: BasicClient,
+    path: PathBuf,
+}
+
+impl TokenManager {
+    pub fn new(client: BasicClient, path: &Path) -> Self {
+        Self {
+            client,
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn from_env() -> TodoResult<Self> {
+        Ok(Self::new(oauth_client()?, &token_path()?))
+    }
+
+    /// Returns an access token that is valid for at least another minute.
+    pub async fn access_token(&self) -> TodoResult<String> {
+        let token = self.load().await?;
+        if !token.is_expired(Utc::now()) {
+            return Ok(token.access_token);
+        }
+        self.exchange(token).await
+    }
+
+    /// Gets a new access token even though the saved one has not expired,
+    /// for when a server rejects it.
+    pub async fn refresh(&self) -> TodoResult<String> {
+        let token = self.load().await?;
+        self.exchange(token).await
+    }
+
+    async fn exchange(&self, token: StoredToken) -> TodoResult<String> {
+        let refresh_token = token.refresh_token
+            .ok_or_else(|| TodoError::AuthError("Access token expired and cannot be refreshed, run `todo login` again".to_string()))?;
+        let response = self.client
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.clone()))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| match e {
+                RequestTokenError::ServerResponse(ref response) if *response.error() == BasicErrorResponseType::InvalidGrant => {
+                    TodoError::AuthError("Refresh token was revoked or expired, run `todo login` again".to_string())
+                }
+                e => TodoError::AuthError(format!("Failed to refresh access token: {}", e)),
+            })?;
+
+        let refreshed = StoredToken::from_response(&response, Some(refresh_token), Utc::now());
+        self.save(&refreshed).await?;
+        Ok(refreshed.access_token)
+    }
+
+    async fn load(&self) -> TodoResult<StoredToken> {
+        let data = fs::read_to_string(&self.path).await
+            .map_err(|_| TodoError::AuthError("Not logged in, run `todo login` first".to_string()))?;
+        let mut token: StoredToken = serde_json::from_str(&data)?;
+
+        // Older files hold the raw response; its lifetime counts from when the file was written.
+        if let (None, Some(expires_in)) = (token.expires_at, token.expires_in) {
+            let written: DateTime<Utc> = fs::metadata(&self.path).await?.modified()?.into();
+            token.expires_at = Some(written + Duration::seconds(expires_in));
+        }
+        Ok(token)
+    }
+
+    async fn save(&self, token: &StoredToken) -> TodoResult<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        files::write_private(&self.path, serde_json::to_string(token)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// `token.json` in the config directory. A token left in the working
+/// directory by older versions is moved there, since credentials should not
+/// linger in copies.
+fn token_path() -> TodoResult<PathBuf> {
+    let path = paths::config_dir()?.join(TOKEN_FILE);
+    let legacy = Path::new(TOKEN_FILE);
+    if !path.exists() && legacy.is_file() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::rename(legacy, &path).or_else(|_| {
+            std::fs::copy(legacy, &path)?;
+            std::fs::remove_file(legacy)
+        })?;
+        eprintln!("Moved {} to {}", legacy.display(), path.display());
+    }
+    Ok(path)
+}
+
+pub async fn login() -> TodoResult<()> {
+    let client = oauth_client()?
+        .set_redirect_uri(RedirectUrl::new("http://localhost:8080".to_string()).map_err(|e| TodoError::AuthError(e.to_string()))?);
 
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
@@ -42,49 +186,141 @@ pub async fn login() -> TodoResult<()> {
         .await
         .map_err(|e| TodoError::AuthError(e.to_string()))?;
 
-    fs::write("token.json", serde_json::to_string(&token)?).await?;
+    TokenManager::new(client, &token_path()?)
+        .save(&StoredToken::from_response(&token, None, Utc::now()))
+        .await?;
 
     println!("Successfully logged in and saved token.");
     Ok(())
 }
 
+/// Waits for the browser to be redirected back with the authorization
+/// code. A connection that fails is reported rather than waited past.
 fn get_authorization_code() -> TodoResult<(AuthorizationCode, CsrfToken)> {
     let listener = TcpListener::bind("127.0.0.1:8080")?;
-    for stream in listener.incoming() {
-        if let Ok(mut stream) = stream {
-            let mut reader = BufReader::new(&stream);
-            let mut request_line = String::new();
-            reader.read_line(&mut request_line)?;
-
-            let redirect_url = request_line.split_whitespace().nth(1).ok_or_else(|| TodoError::AuthError("Invalid redirect URL".to_string()))?;
-            let url = Url::parse(&("http://localhost".to_string() + redirect_url)).map_err(|e| TodoError::AuthError(e.to_string()))?;
-
-            let code_pair = url
-                .query_pairs()
-                .find(|pair| pair.0 == "code")
-                .ok_or_else(|| TodoError::AuthError("No code in the response".to_string()))?;
-
-            let state_pair = url
-                .query_pairs()
-                .find(|pair| pair.0 == "state")
-                .ok_or_else(|| TodoError::AuthError("No state in the response".to_string()))?;
-
-            let message = "You can now close this window.";
-            let response = format!(
-                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
-                message.len(),
-                message
-            );
-            stream.write_all(response.as_bytes())?;
-
-            return Ok((AuthorizationCode::new(code_pair.1.into_owned()), CsrfToken::new(state_pair.1.into_owned())));
-        }
-    }
-    Err(TodoError::AuthError("Failed to get authorization code".to_string()))
+    let (mut stream, _) = listener.accept()?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let redirect_url = request_line.split_whitespace().nth(1).ok_or_else(|| TodoError::AuthError("Invalid redirect URL".to_string()))?;
+    let url = Url::parse(&("http://localhost".to_string() + redirect_url)).map_err(|e| TodoError::AuthError(e.to_string()))?;
+
+    let code_pair = url
+        .query_pairs()
+        .find(|pair| pair.0 == "code")
+        .ok_or_else(|| TodoError::AuthError("No code in the response".to_string()))?;
+
+    let state_pair = url
+        .query_pairs()
+        .find(|pair| pair.0 == "state")
+        .ok_or_else(|| TodoError::AuthError("No state in the response".to_string()))?;
+
+    let message = "You can now close this window.";
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        message.len(),
+        message
+    );
+    stream.write_all(response.as_bytes())?;
+
+    Ok((AuthorizationCode::new(code_pair.1.into_owned()), CsrfToken::new(state_pair.1.into_owned())))
 }
 
 pub async fn logout() -> TodoResult<()> {
-    fs::remove_file("token.json").await?;
+    fs::remove_file(token_path()?).await?;
     println!("Logged out successfully");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn test_client(server: &MockServer) -> BasicClient {
+        BasicClient::new(
+            ClientId::new("client".to_string()),
+            Some(ClientSecret::new("secret".to_string())),
+            AuthUrl::new(format!("{}/auth", server.uri())).unwrap(),
+            Some(TokenUrl::new(format!("{}/token", server.uri())).unwrap()),
+        )
+    }
+
+    async fn write_token(path: &Path, access_token: &str, expires_at: DateTime<Utc>) {
+        let token = StoredToken {
+            access_token: access_token.to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: Some(expires_at),
+            expires_in: None,
+        };
+        fs::write(path, serde_json::to_string(&token).unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_token_refresh() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "fresh",
+                "token_type": "Bearer",
+                "expires_in": 3600
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(TOKEN_FILE);
+        let manager = TokenManager::new(test_client(&server), &path);
+
+        // A valid token is used as is.
+        write_token(&path, "current", Utc::now() + Duration::hours(1)).await;
+        assert_eq!(manager.access_token().await.unwrap(), "current");
+
+        // An expired one is refreshed and saved, keeping the refresh token.
+        write_token(&path, "stale", Utc::now() - Duration::seconds(1)).await;
+        assert_eq!(manager.access_token().await.unwrap(), "fresh");
+        let saved = manager.load().await.unwrap();
+        assert_eq!(saved.access_token, "fresh");
+        assert_eq!(saved.refresh_token.as_deref(), Some("refresh"));
+        assert!(!saved.is_expired(Utc::now()));
+        assert_eq!(manager.access_token().await.unwrap(), "fresh");
+    }
+
+    #[tokio::test]
+    async fn test_revoked_refresh_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "invalid_grant",
+                "error_description": "Token has been expired or revoked."
+            })))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(TOKEN_FILE);
+        let manager = TokenManager::new(test_client(&server), &path);
+
+        assert!(matches!(manager.access_token().await, Err(TodoError::AuthError(message)) if message.contains("todo login")));
+
+        write_token(&path, "stale", Utc::now() - Duration::hours(1)).await;
+        assert!(matches!(manager.access_token().await, Err(TodoError::AuthError(message)) if message.contains("revoked")));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_token_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(TOKEN_FILE);
+        fs::write(&path, r#"{"access_token":"old","token_type":"bearer","expires_in":3599,"refresh_token":"r"}"#).await.unwrap();
+
+        let manager = TokenManager::new(test_client(&MockServer::start().await), &path);
+        let token = manager.load().await.unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("r"));
+        assert!(!token.is_expired(Utc::now()));
+        assert_eq!(manager.access_token().await.unwrap(), "old");
+    }
+}
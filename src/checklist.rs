@@ -0,0 +1,233 @@
+use std::collections::BTreeSet;
+use crate::error::{TodoError, TodoResult};
+use crate::models::{Item, List};
+use crate::tags;
+use crate::tree::{self, Node};
+
+/// One line of a list edited as a Markdown checklist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// How many levels the line is nested under the lines above it.
+    pub depth: usize,
+    pub completed: bool,
+    pub description: String,
+    pub tags: BTreeSet<String>,
+}
+
+impl Line {
+    fn parse(text: &str, completed: bool, depth: usize) -> Self {
+        let item = Item::from_input(text);
+        Self { depth, completed, description: item.description, tags: item.tags }
+    }
+
+    /// How the item of `node` reads back after a round trip through `render`.
+    fn of(node: &Node) -> Self {
+        let item = node.item;
+        Self::parse(&format!("{}{}", item.description, tags::format(&item.tags)), item.completed, node.depth())
+    }
+
+    fn same_text(&self, other: &Line) -> bool {
+        self.description == other.description && self.tags == other.tags
+    }
+}
+
+/// What applying an edited checklist changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+}
+
+impl Summary {
+    pub fn is_empty(&self) -> bool {
+        *self == Summary::default()
+    }
+}
+
+/// Renders the live items of `list` as a checklist with instructions in
+/// `# ` comment lines. Subtasks are indented under their parents.
+pub fn render(list: &List) -> String {
+    let mut out = format!(
+        "# {}\n#\n# One item per line, `- [ ] open` or `- [x] done`. Edit a line to change\n\
+         # its item, delete it to move the item to the trash, add one to create an\n\
+         # item and reorder lines to reorder items. Indent a line to make it a subtask\n\
+         # of the line above. Lines starting with `# ` are ignored.\n\n",
+        list.name
+    );
+    let live: Vec<Item> = list.items.iter().filter(|item| item.trashed.is_none()).cloned().collect();
+    for node in tree::walk(&live) {
+        let item = node.item;
+        out.push_str(&format!(
+            "{}- [{}] {}{}\n",
+            "  ".repeat(node.depth()), if item.completed { "x" } else { " " }, item.description, tags::format(&item.tags)
+        ));
+    }
+    out
+}
+
+/// Reads a checklist written by `render` and edited by the user. Lines
+/// without a `- ` bullet or a checkbox are taken as open items, and a line
+/// indented further than the one above is its subtask.
+pub fn parse(text: &str) -> TodoResult<Vec<Line>> {
+    let mut lines = Vec::new();
+    // Indentation of the lines the current line may be nested under.
+    let mut indents: Vec<usize> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let indent: usize = line.chars().take_while(|c| c.is_whitespace()).map(|c| if c == '\t' { 4 } else { 1 }).sum();
+        let line = line.trim();
+        if line.is_empty() || line == "#" || line.starts_with("# ") {
+            continue;
+        }
+        while indents.last().is_some_and(|&last| last >= indent) {
+            indents.pop();
+        }
+        let depth = indents.len();
+        indents.push(indent);
+        let line = line.strip_prefix(['-', '*']).map(str::trim_start).unwrap_or(line);
+        let (completed, text) = match line.get(..3) {
+            Some("[ ]") => (false, &line[3..]),
+            Some("[x]" | "[X]") => (true, &line[3..]),
+            _ => (false, line),
+        };
+        if text.trim().is_empty() {
+            return Err(TodoError::InvalidEdit(format!("line {} has no text", number + 1)));
+        }
+        lines.push(Line::parse(text, completed, depth));
+    }
+    Ok(lines)
+}
+
+/// Applies edited `lines` to `items`, the live items the checklist was
+/// rendered from. Returns the new live items in order, nested as the lines
+/// are indented, and the items whose lines were deleted.
+///
+/// Unchanged lines keep their items. A line that moved keeps its item if its
+/// text is unchanged, and an edited line takes over the item of the line it
+/// replaced, so IDs and creation dates survive most edits.
+pub fn apply(items: &[Item], lines: Vec<Line>) -> (Vec<Item>, Vec<Item>, Summary) {
+    let nodes = tree::walk(items);
+    let old: Vec<Line> = nodes.iter().map(Line::of).collect();
+    let items: Vec<&Item> = nodes.iter().map(|node| node.item).collect();
+    let (n, m) = (old.len(), lines.len());
+
+    // Longest common subsequence of identical lines.
+    let mut common = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if old[i] == lines[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    // Walk it, collecting the runs of deleted and inserted lines between
+    // unchanged ones.
+    let mut source: Vec<Option<usize>> = vec![None; m];
+    let mut used = vec![false; n];
+    let mut hunks: Vec<(Vec<usize>, Vec<usize>)> = vec![(Vec::new(), Vec::new())];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == lines[j] {
+            source[j] = Some(i);
+            used[i] = true;
+            hunks.push((Vec::new(), Vec::new()));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || common[i][j + 1] >= common[i + 1][j]) {
+            hunks.last_mut().unwrap().1.push(j);
+            j += 1;
+        } else {
+            hunks.last_mut().unwrap().0.push(i);
+            i += 1;
+        }
+    }
+
+    // Moved lines, possibly checked or unchecked on the way.
+    for j in 0..m {
+        if source[j].is_none() {
+            if let Some(i) = (0..n).find(|&i| !used[i] && old[i].same_text(&lines[j])) {
+                source[j] = Some(i);
+                used[i] = true;
+            }
+        }
+    }
+    // Edited lines, paired in order within each run.
+    for (deleted, inserted) in hunks {
+        let deleted = deleted.into_iter().filter(|&i| !used[i]).collect::<Vec<_>>();
+        let inserted = inserted.into_iter().filter(|&j| source[j].is_none()).collect::<Vec<_>>();
+        for (i, j) in deleted.into_iter().zip(inserted) {
+            source[j] = Some(i);
+            used[i] = true;
+        }
+    }
+
+    let mut summary = Summary::default();
+    let mut result = Vec::with_capacity(m);
+    // IDs of the items the current line may be nested under, by depth.
+    let mut parents: Vec<String> = Vec::new();
+    for (line, source) in lines.into_iter().zip(source) {
+        parents.truncate(line.depth);
+        let parent = parents.last().cloned();
+        let item = match source {
+            Some(i) => {
+                if old[i] != line {
+                    summary.changed += 1;
+                }
+                Item { completed: line.completed, description: line.description, tags: line.tags, parent, ..items[i].clone() }
+            }
+            None => {
+                summary.added += 1;
+                Item { completed: line.completed, tags: line.tags, parent, ..Item::new(&line.description) }
+            }
+        };
+        parents.push(item.id.clone());
+        result.push(item);
+    }
+    let removed: Vec<Item> = items.iter().zip(used).filter(|(_, used)| !used).map(|(item, _)| (*item).clone()).collect();
+    summary.removed = removed.len();
+    (result, removed, summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_round_trip() {
+        let items: Vec<Item> = ["Deploy #ops", "Write notes", "Call Bob", "Fix bug"].iter().map(|d| Item::from_input(d)).collect();
+        let list = List { name: "Work".to_string(), items: items.clone(), trashed: None, created_at: None, updated_at: None };
+        let text = render(&list);
+        assert!(text.contains("- [ ] Deploy #ops\n"));
+
+        let (unchanged, removed, summary) = apply(&items, parse(&text).unwrap());
+        assert_eq!((unchanged, removed), (items.clone(), Vec::new()));
+        assert!(summary.is_empty());
+
+        // Add an item, move "Fix bug" up and check it, reword "Write notes"
+        // and delete "Call Bob".
+        let edited = "# Work\nBuy cake\n* [X] Fix bug\n- [ ] Deploy #ops\n- [ ] Write release notes +docs\n";
+        let (result, removed, summary) = apply(&items, parse(edited).unwrap());
+        assert_eq!(summary, Summary { added: 1, changed: 2, removed: 1 });
+        assert_eq!(removed[0].id, items[2].id);
+        let ids: Vec<&str> = result.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(ids[1..], [items[3].id.as_str(), items[0].id.as_str(), items[1].id.as_str()]);
+        assert_eq!(result[0].description, "Buy cake");
+        assert!(result[1].completed);
+        assert_eq!((result[3].description.as_str(), result[3].tags.len()), ("Write release notes", 1));
+
+        assert!(matches!(parse("- [ ]  \n"), Err(TodoError::InvalidEdit(_))));
+
+        // Indenting a line makes it a subtask of the line above.
+        let edited = "- [ ] Deploy #ops\n  - [ ] Write notes\n\t- [ ] Call Bob\n- [ ] Fix bug\n";
+        let (nested, _, summary) = apply(&items, parse(edited).unwrap());
+        assert_eq!(summary, Summary { added: 0, changed: 2, removed: 0 });
+        let parents: Vec<Option<&str>> = nested.iter().map(|item| item.parent.as_deref()).collect();
+        assert_eq!(parents, [None, Some(items[0].id.as_str()), Some(items[1].id.as_str()), None]);
+        let text = render(&List { items: nested.clone(), ..list });
+        assert!(text.ends_with("- [ ] Deploy #ops\n  - [ ] Write notes\n    - [ ] Call Bob\n- [ ] Fix bug\n"));
+        assert!(apply(&nested, parse(&text).unwrap()).2.is_empty());
+    }
+}
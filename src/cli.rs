@@ -1,12 +1,39 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use crate::models::{Placement, Priority};
+use crate::output::Format;
+use crate::storage::{Backend, Scope};
+use crate::sync::SyncTarget;
+use crate::tags;
 
 #[derive(Parser)]
 #[command(name = "todo")]
 pub struct Cli {
+    /// Directory for the local database, instead of TODO_DATA_DIR or the platform default
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+    /// Use the nearest project's database, failing outside of a project
+    #[arg(long, global = true, conflicts_with = "global")]
+    pub local: bool,
+    /// Use the user-global database even inside a project
+    #[arg(long, global = true)]
+    pub global: bool,
     #[command(subcommand)] 
     pub command: Command,
 }
 
+impl Cli {
+    pub fn scope(&self) -> Scope {
+        if self.local {
+            Scope::Local
+        } else if self.global {
+            Scope::Global
+        } else {
+            Scope::Nearest
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Command {
     Show {
@@ -16,26 +43,314 @@ pub enum Command {
         completed: bool,
         #[arg(short, long)]
         incomplete: bool,
+        /// Order of items within each list; ties keep their stored order
+        #[arg(long, value_enum)]
+        sort: Option<SortKey>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: Format,
+        /// Show the notes of items under them
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only show items with this tag; repeat to require several
+        #[arg(long = "tag", value_name = "TAG", value_parser = parse_tag)]
+        tags: Vec<String>,
+        /// Only show items matching a filter, e.g. `status:open and (tag:work or priority>=high) and due<7d`
+        #[arg(long = "where", value_name = "FILTER")]
+        filter: Option<String>,
+        /// Only show items matching a saved filter
+        #[arg(long, value_name = "NAME")]
+        view: Option<String>,
+        /// Save the --where filter under a name for --view
+        #[arg(long, value_name = "NAME", requires = "filter")]
+        save_view: Option<String>,
         list_name: Option<String>,
     },
     Add {
         list_name: String,
         item: String,
+        /// Due date, e.g. `tomorrow`, `next fri 17:00`, `+3d` or `2024-05-01`
+        #[arg(long, allow_hyphen_values = true)]
+        due: Option<String>,
+        /// Date the task becomes relevant, same formats as --due
+        #[arg(long, allow_hyphen_values = true)]
+        start: Option<String>,
+        #[arg(short, long, value_enum)]
+        priority: Option<Priority>,
+        /// Add the task as a subtask of this item
+        #[arg(long)]
+        parent: Option<String>,
+        /// Repeat the task, e.g. `daily`, `weekly on mon,fri`, `monthly on the 15th`, `every 3 days after completion` or an RRULE
+        #[arg(long, value_name = "RULE")]
+        repeat: Option<String>,
+    },
+    /// Change the text, dates, priority or repetition of items; pass `none` to clear one
+    Edit {
+        list_name: String,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter; text and --editor need a single item
+        #[arg(required_unless_present = "editor")]
+        items: Option<String>,
+        /// New description; tag words in it are added to the item's tags
+        #[arg(conflicts_with = "editor")]
+        text: Option<String>,
+        #[arg(long, allow_hyphen_values = true, requires = "items")]
+        due: Option<String>,
+        #[arg(long, allow_hyphen_values = true, requires = "items")]
+        start: Option<String>,
+        #[arg(short, long, value_enum, requires = "items")]
+        priority: Option<Priority>,
+        /// How the items repeat, same rules as `todo add --repeat`
+        #[arg(long, value_name = "RULE", requires = "items")]
+        repeat: Option<String>,
+        /// Edit the item in $EDITOR, or the whole list as a checklist without an item
+        #[arg(short, long)]
+        editor: bool,
+    },
+    /// Move items to another list
+    Move {
+        list_name: String,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
+        /// List to move the items to; it is created if missing
+        target: String,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Copy items to a list, which may be the same one
+    Copy {
+        list_name: String,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
+        /// List to copy the items to; it is created if missing
+        target: String,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Move items to another position in their list
+    Reorder {
+        list_name: String,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
+        /// New 1-based position of the first item
+        #[arg(required_unless_present_any = ["top", "bottom"], conflicts_with_all = ["top", "bottom"])]
+        position: Option<usize>,
+        /// Move to the top of the list
+        #[arg(long, conflicts_with = "bottom")]
+        top: bool,
+        /// Move to the bottom of the list
+        #[arg(long)]
+        bottom: bool,
+    },
+    /// Show every field of an item, including its notes
+    View {
+        list_name: String,
+        /// ID, ID prefix, position or subtask path like `3.2`
+        item: String,
+    },
+    /// Edit the Markdown notes of an item in $EDITOR
+    Note {
+        list_name: String,
+        /// ID, ID prefix, position or subtask path like `3.2`
+        item: String,
+        /// New notes instead of opening the editor; an empty string clears them
+        text: Option<String>,
+    },
+    /// Show when an item or list was created, changed and completed
+    History {
+        list_name: String,
+        /// ID, ID prefix, position or subtask path like `3.2`; the whole list without one
+        item: Option<String>,
+    },
+    /// Rename a list
+    RenameList {
+        list_name: String,
+        new_name: String,
     },
     Complete {
         list_name: String,
-        item_number: usize,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
+        /// Complete the subtasks of the items too
+        #[arg(long)]
+        cascade: bool,
     },
     Incomplete {
         list_name: String,
-        item_number: usize,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
     },
+    /// Move items, a list or every list to the trash
     Remove {
         list_name: Option<String>,
-        item_number: Option<usize>,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: Option<String>,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+        /// Show what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show removed lists and items
+    Trash {
+        #[command(subcommand)]
+        action: Option<TrashAction>,
     },
-    Push,
-    Pull,
+    /// Bring a list or item back from the trash
+    Restore {
+        list_name: String,
+        /// Trashed item ID, unique ID prefix or 1-based position in `todo trash`
+        item: Option<String>,
+    },
+    /// Send local changes to a remote; fails on conflicting items unless
+    /// --ours or --theirs is given
+    Push {
+        #[arg(value_enum, default_value_t)]
+        target: SyncTarget,
+        /// Keep the local version of every conflicting item
+        #[arg(long, conflicts_with = "theirs")]
+        ours: bool,
+        /// Keep the remote version of every conflicting item
+        #[arg(long)]
+        theirs: bool,
+    },
+    /// Fetch remote changes; fails on conflicting items unless --ours or
+    /// --theirs is given
+    Pull {
+        #[arg(value_enum, default_value_t)]
+        target: SyncTarget,
+        /// Keep the local version of every conflicting item
+        #[arg(long, conflicts_with = "theirs")]
+        ours: bool,
+        /// Keep the remote version of every conflicting item
+        #[arg(long)]
+        theirs: bool,
+    },
+    /// Merge local and remote changes, asking how to settle conflicting items
+    Sync {
+        #[arg(value_enum, default_value_t)]
+        target: SyncTarget,
+        /// Keep the local version of every conflicting item
+        #[arg(long, conflicts_with = "theirs")]
+        ours: bool,
+        /// Keep the remote version of every conflicting item
+        #[arg(long)]
+        theirs: bool,
+    },
+    /// Find items in every list by their description or tags
+    Search {
+        #[arg(required_unless_present = "reindex")]
+        query: Vec<String>,
+        /// Match the query's characters in order, allowing gaps
+        #[arg(long, conflicts_with = "regex")]
+        fuzzy: bool,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Show at most this many results
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Rebuild the search index, e.g. after editing the database by hand
+        #[arg(long)]
+        reindex: bool,
+    },
+    /// Add tags to items
+    Tag {
+        list_name: String,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
+        #[arg(required = true, value_parser = parse_tag)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from items
+    Untag {
+        list_name: String,
+        /// Items: ID, ID prefix, position or subtask path like `3.2`, ranges like `1-4,7`, `all`, `done`, `open` or a filter
+        items: String,
+        #[arg(required = true, value_parser = parse_tag)]
+        tags: Vec<String>,
+    },
+    /// List every tag with the number of items carrying it
+    Tags,
+    /// Revert the most recent change to the local database
+    Undo,
+    /// Reapply the most recently undone change
+    Redo,
     Login,
     Logout,
-}
\ No newline at end of file
+    /// Browse and edit lists in a full-screen terminal interface
+    Tui,
+    /// Create a project task database in the current directory
+    Init,
+    /// Copy every list from one storage backend to another
+    Migrate {
+        #[arg(long, value_enum)]
+        from: Backend,
+        #[arg(long, value_enum)]
+        to: Backend,
+        /// Source location, defaults to the backend's configured path
+        #[arg(long)]
+        from_path: Option<PathBuf>,
+        /// Target location, defaults to the backend's configured path
+        #[arg(long)]
+        to_path: Option<PathBuf>,
+        /// Overwrite a target that already contains lists
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Where `todo move` and `todo copy` put items, by default at the bottom.
+#[derive(Args)]
+pub struct PlacementArgs {
+    /// 1-based position of the first item in the target list
+    #[arg(long, conflicts_with = "top")]
+    pub position: Option<usize>,
+    /// Put the items at the top of the target list
+    #[arg(long)]
+    pub top: bool,
+}
+
+impl PlacementArgs {
+    pub fn placement(&self) -> Placement {
+        match (self.top, self.position) {
+            (true, _) => Placement::Top,
+            (false, Some(position)) => Placement::At(position),
+            (false, None) => Placement::Bottom,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// Permanently delete everything in the trash
+    Empty {
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+        /// Show what would be deleted without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SortKey {
+    /// Most urgent first
+    Priority,
+    /// Earliest due date first, undated items last
+    Due,
+    /// Oldest first, items from before creation times were kept last
+    Created,
+    /// Most recently changed first
+    Updated,
+    /// Most recently completed first, open items last
+    Completed,
+    /// By description, ignoring case
+    Alpha,
+}
+
+/// Accepts a tag with or without its `#`, `+` or `@` marker.
+fn parse_tag(tag: &str) -> Result<String, String> {
+    tags::normalize(tag).ok_or_else(|| format!("'{}' is not a valid tag, tags start with a letter", tag))
+}
@@ -1,34 +1,187 @@
-use crate::cli::Command;
+use crate::cli::{Command, SortKey, TrashAction};
 use crate::db::Database;
-use crate::models::Item;
-use crate::error::TodoResult;
+use crate::{checklist, dates, editor};
+use crate::models::{Item, List, Placement, Priority};
+use crate::output::{self, Format, ListView};
+use crate::error::{TodoError, TodoResult};
+use crate::filter::Expr;
+use crate::paths;
+use crate::search;
+use crate::selection::Selection;
+use crate::recurrence::Recurrence;
+use crate::tags;
+use crate::tree;
+use crate::storage::{self, Backend, Location, Scope};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
 use crate::auth;
 use crate::sync;
+use crate::tui;
+use crate::views::Views;
 
-pub async fn execute_command(command: Command) -> TodoResult<()> {
-    let db = Database::new().await?;
+pub async fn execute_command(command: Command, scope: Scope) -> TodoResult<()> {
+    match command {
+        Command::Migrate { from, to, from_path, to_path, force } => {
+            return migrate_storage(from, to, from_path, to_path, force).await;
+        }
+        Command::Init => {
+            let project = paths::init_project(&std::env::current_dir()?)?;
+            println!("Created a project task database in {}", project.display());
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let location = Location::find(scope, &std::env::current_dir()?)?;
+    let db = Database::open(&location).await?;
 
     match command {
-        Command::Show { all, completed, incomplete, list_name } => {
-            show_tasks(&db, all, completed, incomplete, list_name).await?;
+        Command::Show { all, completed, incomplete, sort, format, verbose, tags, filter, view, save_view, list_name } => {
+            let now = Local::now();
+            let mut exprs = Vec::new();
+            if let Some(filter) = &filter {
+                exprs.push(Expr::parse(filter, &now)?);
+            }
+            if let Some(view) = &view {
+                exprs.push(Expr::parse(&Views::open()?.get(view)?, &now)?);
+            }
+            if let (Some(name), Some(filter)) = (&save_view, &filter) {
+                Views::open()?.save(name, filter)?;
+                eprintln!("Saved view '{}'", name);
+            }
+
+            // Inside a project, plain `todo show` lists the global database too.
+            let global;
+            let mut sources = vec![(&location, &db)];
+            if scope == Scope::Nearest && location != Location::Global {
+                global = Database::open(&Location::Global).await?;
+                sources.push((&Location::Global, &global));
+            }
+            let filter = ShowFilter { all, completed, incomplete, tags, exprs };
+            show_tasks(&sources, &filter, sort, format, verbose, list_name).await?;
+        }
+        Command::Add { list_name, item, due, start, priority, parent, repeat } => {
+            add_task(&db, &list_name, &item, due.as_deref(), start.as_deref(), priority, parent.as_deref(), repeat.as_deref()).await?;
+        }
+        Command::Edit { list_name, items: None, .. } => {
+            edit_list(&db, &list_name).await?;
+        }
+        Command::Edit { list_name, items: Some(items), text, due, start, priority, repeat, editor } => {
+            let mut selection = Selection::parse(&items, &Local::now())?;
+            let text = if text.is_some() || editor {
+                let item = single_item(&db, &list_name, &selection).await?;
+                selection = Selection::item(&item.id);
+                Some(match text {
+                    Some(text) => TextEdit::Add(text),
+                    None => TextEdit::Replace(edit_item_text(&item)?),
+                })
+            } else {
+                None
+            };
+            edit_task(&db, &list_name, &selection, text, due.as_deref(), start.as_deref(), priority, repeat.as_deref()).await?;
+        }
+        Command::Move { list_name, items, target, placement } => {
+            let selection = Selection::parse(&items, &Local::now())?;
+            transfer_tasks(&db, &list_name, &selection, &target, placement.placement(), false).await?;
+        }
+        Command::Copy { list_name, items, target, placement } => {
+            let selection = Selection::parse(&items, &Local::now())?;
+            transfer_tasks(&db, &list_name, &selection, &target, placement.placement(), true).await?;
+        }
+        Command::Reorder { list_name, items, position, top, bottom } => {
+            let placement = match (top, bottom, position) {
+                (true, _, _) => Placement::Top,
+                (_, true, _) => Placement::Bottom,
+                (_, _, position) => Placement::At(position.unwrap_or(1)),
+            };
+            let selection = Selection::parse(&items, &Local::now())?;
+            for (item, path) in db.move_items(&list_name, &selection, &list_name, placement).await? {
+                println!("Task {} in list '{}' moved to position {}", item.short_id(), list_name, path);
+            }
+        }
+        Command::View { list_name, item } => {
+            view_task(&db, &list_name, &item).await?;
+        }
+        Command::Note { list_name, item, text } => {
+            edit_notes(&db, &list_name, &item, text).await?;
+        }
+        Command::History { list_name, item } => {
+            show_history(&db, &list_name, item.as_deref()).await?;
+        }
+        Command::RenameList { list_name, new_name } => {
+            db.rename_list(&list_name, &new_name).await?;
+            println!("Renamed list '{}' to '{}'", list_name, new_name);
+        }
+        Command::Complete { list_name, items, cascade } => {
+            let selection = Selection::parse(&items, &Local::now())?;
+            set_status(&db, &list_name, &selection, true, cascade).await?;
+        }
+        Command::Incomplete { list_name, items } => {
+            let selection = Selection::parse(&items, &Local::now())?;
+            set_status(&db, &list_name, &selection, false, false).await?;
+        }
+        Command::Remove { list_name, items, yes, dry_run } => {
+            let selection = items.map(|items| Selection::parse(&items, &Local::now())).transpose()?;
+            remove_task(&db, list_name, selection, yes, dry_run).await?;
         }
-        Command::Add { list_name, item } => {
-            add_task(&db, &list_name, &item).await?;
+        Command::Trash { action: None } => {
+            show_trash(&db).await?;
         }
-        Command::Complete { list_name, item_number } => {
-            complete_task(&db, &list_name, item_number).await?;
+        Command::Trash { action: Some(TrashAction::Empty { yes, dry_run }) } => {
+            empty_trash(&db, yes, dry_run).await?;
         }
-        Command::Incomplete { list_name, item_number } => {
-            incomplete_task(&db, &list_name, item_number).await?;
+        Command::Restore { list_name, item } => {
+            let restored = db.restore(&list_name, item.as_deref()).await?;
+            println!("Restored {}", restored);
         }
-        Command::Remove { list_name, item_number } => {
-            remove_task(&db, list_name, item_number).await?;
+        Command::Push { target, ours, theirs } => {
+            sync::push(&db, target, conflict_strategy(ours, theirs, sync::ConflictStrategy::Fail)).await?;
         }
-        Command::Push => {
-            sync::push(&db).await?;
+        Command::Pull { target, ours, theirs } => {
+            sync::pull(&db, target, conflict_strategy(ours, theirs, sync::ConflictStrategy::Fail)).await?;
         }
-        Command::Pull => {
-            sync::pull(&db).await?;
+        Command::Sync { target, ours, theirs } => {
+            sync::sync_with(&db, target, conflict_strategy(ours, theirs, sync::ConflictStrategy::Ask)).await?;
+        }
+        Command::Search { query, fuzzy, regex, limit, reindex } => {
+            let mode = if fuzzy {
+                search::Mode::Fuzzy
+            } else if regex {
+                search::Mode::Regex
+            } else {
+                search::Mode::Words
+            };
+            search_tasks(&db, &query.join(" "), mode, limit, reindex).await?;
+        }
+        Command::Tag { list_name, items, tags } => {
+            let selection = Selection::parse(&items, &Local::now())?;
+            let action = |items| format!("tag {} in '{}'", items, list_name);
+            let results = db.update_items(&list_name, &selection, action, |item| item.tags.extend(tags.iter().cloned())).await?;
+            report(&results, |item| describe_tags(item, &list_name));
+        }
+        Command::Untag { list_name, items, tags } => {
+            let selection = Selection::parse(&items, &Local::now())?;
+            let action = |items| format!("untag {} in '{}'", items, list_name);
+            let results = db.update_items(&list_name, &selection, action, |item| item.tags.retain(|tag| !tags.contains(tag))).await?;
+            report(&results, |item| describe_tags(item, &list_name));
+        }
+        Command::Tags => {
+            show_tags(&db).await?;
+        }
+        Command::Undo => {
+            match db.undo().await? {
+                Some(action) => println!("Undid: {}", action),
+                None => println!("Nothing to undo"),
+            }
+        }
+        Command::Redo => {
+            match db.redo().await? {
+                Some(action) => println!("Redid: {}", action),
+                None => println!("Nothing to redo"),
+            }
         }
         Command::Login => {
             auth::login().await?;
@@ -36,99 +189,607 @@ pub async fn execute_command(command: Command) -> TodoResult<()> {
         Command::Logout => {
             auth::logout().await?;
         }
+        Command::Tui => {
+            tui::run(&db).await?;
+        }
+        Command::Migrate { .. } | Command::Init => unreachable!("handled before opening the database"),
+    }
+
+    Ok(())
+}
+
+/// Which items `todo show` displays.
+struct ShowFilter {
+    all: bool,
+    completed: bool,
+    incomplete: bool,
+    /// Items must carry every one of these.
+    tags: Vec<String>,
+    /// Items must match every one of these.
+    exprs: Vec<Expr>,
+}
+
+impl ShowFilter {
+    fn matches(&self, list: &List, item: &Item, now: DateTime<Utc>) -> bool {
+        let status = (self.all || (!self.completed && !self.incomplete)) ||
+            (self.completed && item.completed) ||
+            (self.incomplete && !item.completed);
+        status && self.tags.iter().all(|tag| item.tags.contains(tag))
+            && self.exprs.iter().all(|expr| expr.matches(list, item, now))
+    }
+
+    /// Whether only some items are shown, making lists without any noise.
+    fn is_narrowing(&self) -> bool {
+        !self.tags.is_empty() || !self.exprs.is_empty()
+    }
+}
+
+/// Shows the lists of every database in `sources`, labelled with where
+/// they come from when there is more than one.
+async fn show_tasks(sources: &[(&Location, &Database)], filter: &ShowFilter, sort: Option<SortKey>, format: Format, verbose: bool, list_name: Option<String>) -> TodoResult<()> {
+    let labelled = sources.len() > 1;
+    let mut lists = Vec::new();
+    for (location, db) in sources {
+        let source = labelled.then(|| location.label());
+        match &list_name {
+            Some(name) => match db.get_list(name).await {
+                Ok(list) => lists.push((source, list)),
+                Err(TodoError::ListNotFound(_)) if labelled => {}
+                Err(e) => return Err(e),
+            },
+            None => lists.extend(db.get_lists().await?.into_iter().map(|list| (source, list))),
+        }
+    }
+    if let (Some(name), true) = (&list_name, lists.is_empty()) {
+        return Err(TodoError::ListNotFound(name.clone()));
     }
 
+    let now = Utc::now();
+    let views: Vec<ListView> = lists.iter()
+        .map(|(source, list)| {
+            // Sorting orders siblings; paths stay those of the stored order so they can still be used to address items.
+            let order = sort.map(compare_items);
+            let items = tree::walk_by(&list.items, order.as_ref().map(|order| order as &tree::Compare))
+                .into_iter()
+                .filter(|node| filter.matches(list, node.item, now))
+                .collect();
+            ListView { name: &list.name, source: *source, items }
+        })
+        // Filters search across lists; the ones without a match are noise.
+        .filter(|view| !filter.is_narrowing() || !view.items.is_empty())
+        .collect();
+
+    let highlight = std::io::stdout().is_terminal();
+    print!("{}", output::render(format, &views, now, highlight, verbose)?);
+
     Ok(())
 }
 
-async fn show_tasks(db: &Database, all: bool, completed: bool, incomplete: bool, list_name: Option<String>) -> TodoResult<()> {
-    let lists = if let Some(name) = list_name {
-        vec![db.get_list(&name).await?]
+/// The strategy chosen with `--ours` or `--theirs`, `default` without either.
+fn conflict_strategy(ours: bool, theirs: bool, default: sync::ConflictStrategy) -> sync::ConflictStrategy {
+    if ours {
+        sync::ConflictStrategy::Ours
+    } else if theirs {
+        sync::ConflictStrategy::Theirs
     } else {
-        db.get_lists().await?
-    };
+        default
+    }
+}
 
-    for list in lists {
-        println!("List: {}", list.name);
-        for (i, item) in list.items.iter().enumerate() {
-            if (all || (!completed && !incomplete)) ||
-               (completed && item.completed) ||
-               (incomplete && !item.completed) {
-                println!("  {}. [{}] {}", i + 1, if item.completed { "x" } else { " " }, item.description);
-            }
+fn describe_tags(item: &Item, list_name: &str) -> String {
+    if item.tags.is_empty() {
+        format!("Task {} in list '{}' has no tags", item.short_id(), list_name)
+    } else {
+        format!("Task {} in list '{}' is now tagged{}", item.short_id(), list_name, tags::format(&item.tags))
+    }
+}
+
+/// Prints a line for every item a bulk change changed, followed by a count
+/// when the selection was not a single item that changed.
+fn report(results: &[(Item, bool)], describe: impl Fn(&Item) -> String) {
+    let changed = results.iter().filter(|(_, changed)| *changed).count();
+    for (item, _) in results.iter().filter(|(_, changed)| *changed) {
+        println!("{}", describe(item));
+    }
+    match (results.len(), changed) {
+        (0, _) => println!("No items selected"),
+        (_, 0) => println!("Nothing changed"),
+        (1, 1) => {}
+        (selected, changed) if changed == selected => println!("Changed {} items", changed),
+        (selected, changed) => println!("Changed {} of {} selected items", changed, selected),
+    }
+}
+
+async fn search_tasks(db: &Database, query: &str, mode: search::Mode, limit: usize, reindex: bool) -> TodoResult<()> {
+    if reindex {
+        db.reindex().await?;
+        if query.is_empty() {
+            println!("Rebuilt the search index");
+            return Ok(());
+        }
+    }
+
+    let hits = db.search(query, mode).await?;
+    if hits.is_empty() {
+        println!("No matches for '{}'", query);
+    }
+    for hit in hits.iter().take(limit) {
+        println!("{}:{}  {} [{}] {}{}", hit.list, hit.path, hit.short_id(), if hit.completed { "x" } else { " " }, hit.description, tags::format(&hit.tags));
+        if let Some(note) = &hit.note {
+            println!("    {}", note);
         }
-        println!();
     }
+    if hits.len() > limit {
+        println!("... and {} more, raise --limit to see them", hits.len() - limit);
+    }
+    Ok(())
+}
 
+/// Prints every tag on a live item with the number of items carrying it.
+async fn show_tags(db: &Database) -> TodoResult<()> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for list in db.get_lists().await? {
+        for tag in list.items.into_iter().flat_map(|item| item.tags) {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+    if counts.is_empty() {
+        println!("No tags");
+    }
+    for (tag, count) in counts {
+        println!("#{} ({})", tag, count);
+    }
     Ok(())
 }
 
-async fn add_task(db: &Database, list_name: &str, item_description: &str) -> TodoResult<()> {
+/// Orders items by `key`. Sorts using it are stable, so items that compare
+/// equal keep their stored order.
+fn compare_items(key: SortKey) -> impl Fn(&Item, &Item) -> Ordering {
+    move |a, b| match key {
+        SortKey::Priority => b.priority.cmp(&a.priority),
+        SortKey::Due => (a.due.is_none(), a.due).cmp(&(b.due.is_none(), b.due)),
+        SortKey::Created => (a.created_at.is_none(), a.created_at).cmp(&(b.created_at.is_none(), b.created_at)),
+        SortKey::Updated => b.updated_at.cmp(&a.updated_at),
+        SortKey::Completed => b.completed_at.cmp(&a.completed_at),
+        SortKey::Alpha => a.description.to_lowercase().cmp(&b.description.to_lowercase()),
+    }
+}
+
+/// Parses a date argument in the user's time zone. `none` clears the date.
+fn parse_date_arg(input: &str, default_time: NaiveTime) -> TodoResult<Option<DateTime<Utc>>> {
+    if input.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    Ok(Some(dates::parse_datetime(input, &Local::now(), default_time)?.with_timezone(&Utc)))
+}
+
+/// Parses a `--repeat` rule, where `none` stops an item repeating.
+fn parse_repeat_arg(input: &str) -> TodoResult<Option<Recurrence>> {
+    if input.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    Ok(Some(input.parse()?))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn add_task(db: &Database, list_name: &str, item_description: &str, due: Option<&str>, start: Option<&str>, priority: Option<Priority>, parent: Option<&str>, repeat: Option<&str>) -> TodoResult<()> {
+    let mut item = Item::from_input(item_description);
+    let parent = match parent {
+        Some(parent) => Some(db.get_item(list_name, parent).await?),
+        None => None,
+    };
+    item.parent = parent.as_ref().map(|parent| parent.id.clone());
+    item.priority = priority.unwrap_or_default();
+    if let Some(due) = due {
+        item.due = parse_date_arg(due, dates::end_of_day())?;
+    }
+    if let Some(start) = start {
+        item.start = parse_date_arg(start, dates::start_of_day())?;
+    }
+    if let Some(repeat) = repeat {
+        item.recurrence = parse_repeat_arg(repeat)?;
+    }
+
     // Check if the list exists, if not, create it
     if db.get_list(list_name).await.is_err() {
         db.create_list(list_name).await?;
         println!("Created new list '{}'", list_name);
     }
 
-    let item = Item {
-        description: item_description.to_string(),
-        completed: false,
-    };
+    let short_id = item.short_id().to_string();
     db.add_item(list_name, item).await?;
-    println!("Task added to list '{}'", list_name);
+    match parent {
+        Some(parent) => println!("Task {} added to list '{}' as a subtask of '{}'", short_id, list_name, parent.description),
+        None => println!("Task {} added to list '{}'", short_id, list_name),
+    }
     Ok(())
 }
 
-async fn complete_task(db: &Database, list_name: &str, item_number: usize) -> TodoResult<()> {
-    db.update_item_status(list_name, item_number, true).await?;
-    println!("Task {} in list '{}' marked as completed", item_number, list_name);
+/// Moves or copies items to `target`, which is created like `todo add` does.
+async fn transfer_tasks(db: &Database, list_name: &str, selection: &Selection, target: &str, placement: Placement, copy: bool) -> TodoResult<()> {
+    let created = db.get_list(target).await.is_err();
+    let placed = if copy {
+        db.copy_items(list_name, selection, target, placement).await?
+    } else {
+        db.move_items(list_name, selection, target, placement).await?
+    };
+    if created {
+        println!("Created new list '{}'", target);
+    }
+    for (item, path) in placed {
+        if copy {
+            println!("Task '{}' copied to list '{}' at position {} as {}", item.description, target, path, item.short_id());
+        } else {
+            println!("Task {} moved to list '{}' at position {}", item.short_id(), target, path);
+        }
+    }
     Ok(())
 }
 
-async fn incomplete_task(db: &Database, list_name: &str, item_number: usize) -> TodoResult<()> {
-    db.update_item_status(list_name, item_number, false).await?;
-    println!("Task {} in list '{}' marked as incomplete", item_number, list_name);
+/// A new description for an item. Tag words in it are added to the item's
+/// tags, or replace them when the tags were edited along with the text.
+enum TextEdit {
+    Add(String),
+    Replace(String),
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn edit_task(db: &Database, list_name: &str, selection: &Selection, text: Option<TextEdit>, due: Option<&str>, start: Option<&str>, priority: Option<Priority>, repeat: Option<&str>) -> TodoResult<()> {
+    let due = due.map(|due| parse_date_arg(due, dates::end_of_day())).transpose()?;
+    let start = start.map(|start| parse_date_arg(start, dates::start_of_day())).transpose()?;
+    let repeat = repeat.map(parse_repeat_arg).transpose()?;
+
+    let action = |items| format!("edit {} in '{}'", items, list_name);
+    let results = db.update_items(list_name, selection, action, |item| {
+        match &text {
+            Some(TextEdit::Add(text)) => {
+                let edited = Item::from_input(text);
+                item.description = edited.description;
+                item.tags.extend(edited.tags);
+            }
+            Some(TextEdit::Replace(text)) => {
+                let edited = Item::from_input(text);
+                item.description = edited.description;
+                item.tags = edited.tags;
+            }
+            None => {}
+        }
+        if let Some(due) = due {
+            item.due = due;
+        }
+        if let Some(start) = start {
+            item.start = start;
+        }
+        if let Some(priority) = priority {
+            item.priority = priority;
+        }
+        if let Some(repeat) = &repeat {
+            item.recurrence = repeat.clone();
+        }
+    }).await?;
+    report(&results, |item| format!(
+        "Task {} in list '{}' updated: {}{}{}{}{}",
+        item.short_id(), list_name, item.description, tags::format(&item.tags), output::format_priority(item), output::format_dates(item, Utc::now()),
+        output::format_recurrence(item)
+    ));
+    Ok(())
+}
+
+/// The one item `selection` picks, for changes that only make sense for a
+/// single item.
+async fn single_item(db: &Database, list_name: &str, selection: &Selection) -> TodoResult<Item> {
+    let mut items = db.select_items(list_name, selection).await?;
+    if items.len() != 1 {
+        return Err(TodoError::InvalidEdit(format!("the text can only be changed for one item at a time, {} selected", items.len())));
+    }
+    Ok(items.remove(0))
+}
+
+/// Opens an item's description and tags in the user's editor and returns
+/// the edited text on one line.
+fn edit_item_text(item: &Item) -> TodoResult<String> {
+    let text = editor::edit(&format!("{}{}\n", item.description, tags::format(&item.tags)), "txt")?;
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return Err(TodoError::InvalidEdit("the description is empty, nothing was changed".to_string()));
+    }
+    Ok(text)
+}
+
+/// Opens a list in the user's editor as a checklist and applies the result.
+async fn edit_list(db: &Database, list_name: &str) -> TodoResult<()> {
+    let list = db.get_list(list_name).await?;
+    let text = editor::edit(&checklist::render(&list), "md")?;
+    let summary = db.edit_list(list_name, checklist::parse(&text)?).await?;
+    if summary.is_empty() {
+        println!("List '{}' is unchanged", list_name);
+    } else {
+        println!(
+            "Updated list '{}': {} added, {} changed, {} moved to the trash",
+            list_name, summary.added, summary.changed, summary.removed
+        );
+    }
+    Ok(())
+}
+
+async fn set_status(db: &Database, list_name: &str, selection: &Selection, completed: bool, cascade: bool) -> TodoResult<()> {
+    let (results, spawned) = db.update_items_status(list_name, selection, completed, cascade).await?;
+    let state = if completed { "completed" } else { "incomplete" };
+    report(&results, |item| format!("Task {} in list '{}' marked as {}", item.short_id(), list_name, state));
+    for item in spawned {
+        println!("Next occurrence of '{}' added to list '{}' as {}{}", item.description, list_name, item.short_id(), output::format_dates(&item, Utc::now()));
+    }
+    Ok(())
+}
+
+/// Prints every field of an item and its notes.
+async fn view_task(db: &Database, list_name: &str, item_ref: &str) -> TodoResult<()> {
+    let item = db.get_item(list_name, item_ref).await?;
+    let list = db.get_list(list_name).await?;
+    let nodes = tree::walk(&list.items);
+    if let Some(node) = nodes.iter().find(|node| node.item.id == item.id) {
+        let parent = node.parent.and_then(|parent| list.items.iter().find(|item| item.id == parent));
+        print!("{}", output::render_detail(list_name, node, parent, Utc::now()));
+    }
+    Ok(())
+}
+
+/// Replaces an item's notes with `text`, or with what the user writes in
+/// their editor.
+async fn edit_notes(db: &Database, list_name: &str, item_ref: &str, text: Option<String>) -> TodoResult<()> {
+    let item = db.get_item(list_name, item_ref).await?;
+    let notes = match text {
+        Some(text) => text,
+        None => editor::edit(&item.notes, "md")?,
+    };
+    let notes = notes.trim_end().to_string();
+    if notes == item.notes {
+        println!("Notes of task {} in list '{}' are unchanged", item.short_id(), list_name);
+        return Ok(());
+    }
+    let cleared = notes.is_empty();
+    let item = db.update_item(list_name, &item.id, |item| item.notes = notes).await?;
+    let state = if cleared { "cleared" } else { "updated" };
+    println!("Notes of task {} in list '{}' {}", item.short_id(), list_name, state);
+    Ok(())
+}
+
+/// Prints the timestamps of an item or list and the recorded changes to
+/// it.
+async fn show_history(db: &Database, list_name: &str, item_ref: Option<&str>) -> TodoResult<()> {
+    let (times, events) = match item_ref {
+        Some(item_ref) => {
+            let (item, events) = db.item_history(list_name, item_ref).await?;
+            println!("Task {} '{}' in list '{}'", item.short_id(), item.description, list_name);
+            ([("Created", item.created_at), ("Updated", item.updated_at), ("Completed", item.completed_at)].to_vec(), events)
+        }
+        None => {
+            let (list, events) = db.list_history(list_name).await?;
+            println!("List '{}'", list.name);
+            ([("Created", list.created_at), ("Updated", list.updated_at)].to_vec(), events)
+        }
+    };
+    for (label, time) in times.into_iter().filter(|(_, time)| time.is_some()) {
+        println!("  {:<10} {}", format!("{}:", label), time.map(output::format_date).unwrap_or_default());
+    }
+
+    println!();
+    if events.is_empty() {
+        println!("No recorded changes");
+    }
+    for event in events {
+        println!("  {:<20}  {}", output::format_date(event.at), event.changes.join(", "));
+    }
     Ok(())
 }
 
-async fn remove_task(db: &Database, list_name: Option<String>, item_number: Option<usize>) -> TodoResult<()> {
-    match (list_name, item_number) {
-        (Some(list), Some(item)) => {
-            db.remove_item(&list, item).await?;
-            println!("Task {} removed from list '{}'", item, list);
+async fn remove_task(db: &Database, list_name: Option<String>, selection: Option<Selection>, yes: bool, dry_run: bool) -> TodoResult<()> {
+    // The exact items shown for confirmation are the ones removed.
+    let selected = match (&list_name, &selection) {
+        (Some(list), Some(selection)) => Some(db.select_subtrees(list, selection).await?),
+        _ => None,
+    };
+    let targets = match (&list_name, &selected) {
+        (Some(list), Some(items)) => items.iter().map(|item| format!("'{}' from list '{}'", item.description, list)).collect(),
+        (Some(list), None) => vec![describe_list(&db.get_list(list).await?)],
+        (None, None) => db.get_lists().await?.iter().map(describe_list).collect(),
+        _ => {
+            println!("Invalid combination of arguments");
+            return Ok(());
+        }
+    };
+    if targets.is_empty() {
+        println!("Nothing to remove");
+        return Ok(());
+    }
+
+    println!("The following will be moved to the trash:");
+    for target in &targets {
+        println!("  {}", target);
+    }
+    if !confirm("Move them to the trash?", yes, dry_run)? {
+        return Ok(());
+    }
+
+    match (list_name, selected) {
+        (Some(list), Some(items)) => {
+            let ids = items.iter().map(|item| item.id.clone()).collect();
+            for item in db.remove_items(&list, &Selection::Refs(ids)).await? {
+                println!("Task {} moved from list '{}' to the trash", item.short_id(), list);
+            }
         }
         (Some(list), None) => {
             db.remove_list(&list).await?;
-            println!("List '{}' removed", list);
+            println!("List '{}' moved to the trash", list);
         }
-        (None, None) => {
+        _ => {
             db.remove_all_lists().await?;
-            println!("All lists removed");
+            println!("All lists moved to the trash");
+        }
+    }
+    Ok(())
+}
+
+fn describe_list(list: &List) -> String {
+    format!("list '{}' with {} items", list.name, list.items.len())
+}
+
+/// Asks before a destructive change. Returns false on a dry run or when the
+/// user declines; without a terminal to ask on, `--yes` is required.
+fn confirm(question: &str, yes: bool, dry_run: bool) -> TodoResult<bool> {
+    if dry_run {
+        println!("Dry run, nothing was changed");
+        return Ok(false);
+    }
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(TodoError::ConfirmationRequired("pass --yes to confirm without a terminal".to_string()));
+    }
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let confirmed = matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+    if !confirmed {
+        println!("Cancelled");
+    }
+    Ok(confirmed)
+}
+
+async fn show_trash(db: &Database) -> TodoResult<()> {
+    let trash = db.trash().await?;
+    if trash.is_empty() {
+        println!("The trash is empty");
+        return Ok(());
+    }
+
+    for list in trash {
+        match list.trashed {
+            Some(trashed) => println!("List: {} (removed {})", list.name, output::format_date(trashed)),
+            None => println!("List: {}", list.name),
+        }
+        // Only trashed items are numbered, as that is how `todo restore` addresses them.
+        let mut position = 0;
+        for item in &list.items {
+            let checkbox = if item.completed { "x" } else { " " };
+            match item.trashed {
+                Some(trashed) => {
+                    position += 1;
+                    println!("  {}. {} [{}] {} (removed {})", position, item.short_id(), checkbox, item.description, output::format_date(trashed));
+                }
+                None => println!("  -  {} [{}] {}", item.short_id(), checkbox, item.description),
+            }
         }
-        _ => println!("Invalid combination of arguments"),
+        println!();
     }
     Ok(())
 }
 
+async fn empty_trash(db: &Database, yes: bool, dry_run: bool) -> TodoResult<()> {
+    let trash = db.trash().await?;
+    if trash.is_empty() {
+        println!("The trash is empty");
+        return Ok(());
+    }
+
+    println!("The following will be deleted permanently:");
+    for list in &trash {
+        if list.trashed.is_some() {
+            println!("  {}", describe_list(list));
+        } else {
+            for item in &list.items {
+                println!("  '{}' from list '{}'", item.description, list.name);
+            }
+        }
+    }
+    if !confirm("Delete them permanently?", yes, dry_run)? {
+        return Ok(());
+    }
+
+    let count = db.empty_trash().await?;
+    println!("Deleted {} lists and items from the trash", count);
+    Ok(())
+}
+
+async fn migrate_storage(from: Backend, to: Backend, from_path: Option<PathBuf>, to_path: Option<PathBuf>, force: bool) -> TodoResult<()> {
+    let from_path = match from_path {
+        Some(path) => path,
+        None => from.configured_path()?,
+    };
+    let to_path = match to_path {
+        Some(path) => path,
+        None => to.configured_path()?,
+    };
+    if from_path == to_path {
+        return Err(TodoError::ConfigError("Source and target of a migration must differ".to_string()));
+    }
+
+    let source = from.open(&from_path).await?;
+    if let Some(dir) = to_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let target = to.open(&to_path).await?;
+    if !force && !target.get_lists().await?.is_empty() {
+        return Err(TodoError::ConfigError(format!("{} already contains lists, use --force to overwrite it", to_path.display())));
+    }
+
+    let count = storage::migrate(source.as_ref(), target.as_ref()).await?;
+    // The target's search index, if any, describes what it held before.
+    if let Some(dir) = to_path.parent() {
+        let _ = std::fs::remove_dir_all(dir.join(search::INDEX_DIR));
+    }
+    println!("Migrated {} lists from {} to {}", count, from_path.display(), to_path.display());
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::Database;
-    
+    use crate::db::test_database;
 
     #[tokio::test]
     async fn test_add_task() {
-        let db = Database::new().await.unwrap();
+        let (db, _dir) = test_database().await;
         let list_name = "Test List";
         let item_description = "Buy milk";
 
-        let result = add_task(&db, list_name, item_description).await;
+        let result = add_task(&db, list_name, item_description, Some("2024-05-01"), None, Some(Priority::High), None, None).await;
 
         assert!(result.is_ok());
 
         let list = db.get_list(list_name).await.unwrap();
         assert_eq!(list.items.len(), 1);
         assert_eq!(list.items[0].description, item_description);
-        assert_eq!(list.items[0].completed, false);
+        assert!(!list.items[0].completed);
+        assert!(!list.items[0].id.is_empty());
+        assert!(list.items[0].due.is_some());
+        assert!(list.items[0].start.is_none());
+        assert_eq!(list.items[0].priority, Priority::High);
+    }
+
+    #[test]
+    fn test_sort_items_is_stable() {
+        let mut items = vec![Item::new("beta"), Item::new("Alpha"), Item::new("gamma"), Item::new("delta")];
+        items[0].priority = Priority::Low;
+        items[2].priority = Priority::Urgent;
+        items[3].priority = Priority::Low;
+        items[1].created_at = None;
+        let paths = |items: &[Item], key: SortKey| {
+            tree::walk_by(items, Some(&compare_items(key))).iter().map(tree::Node::reference).collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(&items, SortKey::Priority), vec!["3", "1", "4", "2"]);
+        assert_eq!(paths(&items, SortKey::Alpha), vec!["2", "1", "4", "3"]);
+        assert_eq!(paths(&items, SortKey::Created), vec!["1", "3", "4", "2"]);
+        // Undated items keep their stored order.
+        assert_eq!(paths(&items, SortKey::Due), vec!["1", "2", "3", "4"]);
+
+        // Subtasks are sorted among their siblings and stay under their parent.
+        items[1].parent = Some(items[3].id.clone());
+        items[2].parent = Some(items[3].id.clone());
+        assert_eq!(paths(&items, SortKey::Priority), vec!["1", "2", "2.2", "2.1"]);
     }
 }
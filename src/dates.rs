@@ -0,0 +1,192 @@
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use crate::error::{TodoError, TodoResult};
+
+/// Time used for due dates given without one, e.g. `tomorrow`.
+pub fn end_of_day() -> NaiveTime {
+    NaiveTime::from_hms_opt(23, 59, 0).unwrap()
+}
+
+/// Time used for start dates given without one.
+pub fn start_of_day() -> NaiveTime {
+    NaiveTime::MIN
+}
+
+/// Parses a user-supplied date relative to `now`.
+///
+/// Understands ISO dates and datetimes (`2024-05-01`, `2024-05-01 17:00`,
+/// RFC 3339), `now`, `today`, `tomorrow`, `yesterday`, weekdays (`fri`,
+/// `next friday`), offsets (`+3d`, `-1w`, `+2h`, `in 3 days`) and an optional
+/// trailing time (`17:00`, `5pm`). Dates without a time get `default_time`.
+pub fn parse_datetime<Tz: TimeZone>(input: &str, now: &DateTime<Tz>, default_time: NaiveTime) -> TodoResult<DateTime<Tz>> {
+    let invalid = || TodoError::InvalidDate(input.to_string());
+    let input = input.trim();
+    let tz = now.timezone();
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Ok(datetime.with_timezone(&tz));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(input, format) {
+            return localize(&tz, naive).ok_or_else(invalid);
+        }
+    }
+
+    let lowered = input.to_lowercase();
+    let mut words: Vec<&str> = lowered.split_whitespace().collect();
+    if words.is_empty() {
+        return Err(invalid());
+    }
+
+    let time = match words.last().and_then(|word| parse_time(word)) {
+        Some(time) => {
+            words.pop();
+            Some(time)
+        }
+        None => None,
+    };
+
+    let today = now.date_naive();
+    let date = match words.as_slice() {
+        [] => today,
+        ["now"] if time.is_none() => return Ok(now.clone()),
+        ["today"] | ["tonight"] => today,
+        ["tomorrow"] => today + Duration::days(1),
+        ["yesterday"] => today - Duration::days(1),
+        ["in", amount, unit] => match parse_offset(amount, unit) {
+            Some(Offset::Exact(duration)) if time.is_none() => return Ok(now.clone() + duration),
+            Some(Offset::Days(offset)) => offset.apply(today).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        },
+        [offset] if offset.starts_with(['+', '-']) => {
+            let (amount, unit) = offset.split_at(offset.trim_end_matches(char::is_alphabetic).len());
+            match parse_offset(amount, unit) {
+                Some(Offset::Exact(duration)) if time.is_none() => return Ok(now.clone() + duration),
+                Some(Offset::Days(offset)) => offset.apply(today).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            }
+        }
+        ["next", weekday] | [weekday] if parse_weekday(weekday).is_some() => {
+            next_weekday(today, parse_weekday(weekday).unwrap())
+        }
+        [date] => NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+
+    localize(&tz, date.and_time(time.unwrap_or(default_time))).ok_or_else(invalid)
+}
+
+enum Offset {
+    Exact(Duration),
+    Days(DayOffset),
+}
+
+enum DayOffset {
+    Days(i64),
+    Months(i64),
+}
+
+impl DayOffset {
+    fn apply(self, date: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            DayOffset::Days(days) => date.checked_add_signed(Duration::days(days)),
+            DayOffset::Months(months) if months >= 0 => date.checked_add_months(Months::new(months as u32)),
+            DayOffset::Months(months) => date.checked_sub_months(Months::new(months.unsigned_abs() as u32)),
+        }
+    }
+}
+
+fn parse_offset(amount: &str, unit: &str) -> Option<Offset> {
+    let amount: i64 = amount.strip_prefix('+').unwrap_or(amount).parse().ok()?;
+    Some(match unit.trim_end_matches('s') {
+        "min" | "minute" => Offset::Exact(Duration::minutes(amount)),
+        "h" | "hour" => Offset::Exact(Duration::hours(amount)),
+        "d" | "day" => Offset::Days(DayOffset::Days(amount)),
+        "w" | "week" => Offset::Days(DayOffset::Days(amount * 7)),
+        "m" | "month" => Offset::Days(DayOffset::Months(amount)),
+        "y" | "year" => Offset::Days(DayOffset::Months(amount * 12)),
+        _ => return None,
+    })
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    if let Ok(time) = NaiveTime::parse_from_str(word, "%H:%M") {
+        return Some(time);
+    }
+    let (clock, pm) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, false)
+    } else {
+        (word.strip_suffix("pm")?, true)
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        None => (clock.parse::<u32>().ok()?, 0),
+    };
+    if !(1..=12).contains(&hour) {
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour % 12 + if pm { 12 } else { 0 }, minute, 0)
+}
+
+pub(crate) fn parse_weekday(word: &str) -> Option<Weekday> {
+    Some(match word {
+        "mon" | "monday" => Weekday::Mon,
+        "tue" | "tues" | "tuesday" => Weekday::Tue,
+        "wed" | "wednesday" => Weekday::Wed,
+        "thu" | "thur" | "thurs" | "thursday" => Weekday::Thu,
+        "fri" | "friday" => Weekday::Fri,
+        "sat" | "saturday" => Weekday::Sat,
+        "sun" | "sunday" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+/// The first `weekday` strictly after `date`.
+fn next_weekday(date: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days_ahead = (weekday.num_days_from_monday() as i64 - date.weekday().num_days_from_monday() as i64 + 6) % 7 + 1;
+    date + Duration::days(days_ahead)
+}
+
+fn localize<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> Option<DateTime<Tz>> {
+    tz.from_local_datetime(&naive).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn parse(input: &str) -> TodoResult<DateTime<Utc>> {
+        // A Wednesday afternoon.
+        parse_datetime(input, &at("2024-05-01T14:30:00Z"), end_of_day())
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(parse("2024-06-10").unwrap(), at("2024-06-10T23:59:00Z"));
+        assert_eq!(parse("2024-06-10 08:15").unwrap(), at("2024-06-10T08:15:00Z"));
+        assert_eq!(parse("2024-06-10T08:15:00+02:00").unwrap(), at("2024-06-10T06:15:00Z"));
+        assert_eq!(parse("now").unwrap(), at("2024-05-01T14:30:00Z"));
+        assert_eq!(parse("today").unwrap(), at("2024-05-01T23:59:00Z"));
+        assert_eq!(parse("Tomorrow").unwrap(), at("2024-05-02T23:59:00Z"));
+        assert_eq!(parse("tomorrow 9am").unwrap(), at("2024-05-02T09:00:00Z"));
+        assert_eq!(parse("17:00").unwrap(), at("2024-05-01T17:00:00Z"));
+        assert_eq!(parse("+3d").unwrap(), at("2024-05-04T23:59:00Z"));
+        assert_eq!(parse("-1w").unwrap(), at("2024-04-24T23:59:00Z"));
+        assert_eq!(parse("+1m").unwrap(), at("2024-06-01T23:59:00Z"));
+        assert_eq!(parse("+2h").unwrap(), at("2024-05-01T16:30:00Z"));
+        assert_eq!(parse("in 2 weeks").unwrap(), at("2024-05-15T23:59:00Z"));
+        assert_eq!(parse("fri").unwrap(), at("2024-05-03T23:59:00Z"));
+        assert_eq!(parse("next fri 17:00").unwrap(), at("2024-05-03T17:00:00Z"));
+        assert_eq!(parse("wednesday").unwrap(), at("2024-05-08T23:59:00Z"));
+        assert_eq!(parse("12:30pm").unwrap(), at("2024-05-01T12:30:00Z"));
+        assert_eq!(parse("12am").unwrap(), at("2024-05-01T00:00:00Z"));
+
+        for bad in ["", "someday", "+3x", "next", "2024-13-01", "13pm", "+2h 17:00"] {
+            assert!(matches!(parse(bad), Err(TodoError::InvalidDate(_))), "{:?} should not parse", bad);
+        }
+    }
+}
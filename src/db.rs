@@ -1,211 +1,776 @@
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
 use mongodb::options::{ClientOptions, ResolverConfig};
-use tokio::sync::Mutex;
-use serde_json;
+use tokio::sync::{Mutex, MutexGuard, OnceCell};
 use mongodb::{Client, Database as MongoDatabase};
-use crate::models::{List, Item};
+use crate::checklist::{self, Line, Summary};
+use crate::models::{Item, List, Placement};
+use crate::selection::{find_item, find_listed_item, Selection};
 use crate::error::{TodoError, TodoResult};
-use std::time::SystemTime;
+use crate::files::FileLock;
+use crate::history::{self, Event, Record};
+use crate::journal::{self, Entry, History, Journal, ListChange};
+use crate::search::{self, Hit, SearchIndex};
+use crate::storage::{self, Location, Operation, Storage};
+use crate::tree;
+use chrono::{DateTime, Utc};
 
 
 pub struct Database {
-    local_db: Arc<Mutex<serde_json::Value>>,
-    pub remote_db: MongoDatabase,
-    dirty: Arc<Mutex<bool>>,
-    #[allow(dead_code)]
-    last_modified: Arc<Mutex<SystemTime>>,
+    storage: Box<dyn Storage>,
+    /// Directory holding the local database and bookkeeping files next to it.
+    state_dir: PathBuf,
+    mongodb_uri: Option<String>,
+    remote_db: OnceCell<MongoDatabase>,
+    journal: Journal,
+    history: history::Log,
+    index: SearchIndex,
+    /// Keeps tasks of this process out while one holds `lock`; the lock
+    /// file only keeps other processes out.
+    in_process: Mutex<()>,
 }
 
 impl Database {
 
+    /// Creates a list unless one exists. A list of that name in the trash
+    /// has to be restored or purged first.
     pub async fn create_list(&self, list_name: &str) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        local_db[list_name] = serde_json::Value::Array(Vec::new());
-        *self.dirty.lock().await = true;
-        self.save_local_db(&local_db).await
-    }
-
-    pub async fn new() -> TodoResult<Self> {
-        let uri = std::env::var("MONGODB_URI")
-            .map_err(|_| TodoError::ConfigError("MONGODB_URI must be set".to_string()))?;
-    
-        let mut options = ClientOptions::parse_with_resolver_config(&uri, ResolverConfig::cloudflare()).await?;
-        options.app_name = Some("Todo App".to_string());
-    
-        let client = Client::with_options(options)?;
-        let remote_db = client.database("todo_app");
-    
-        let local_db = Self::load_local_db().await?;
-        let local_db = Arc::new(Mutex::new(local_db));
-    
-        let dirty = Arc::new(Mutex::new(false));
-        let last_modified = Arc::new(Mutex::new(SystemTime::now()));
-    
-        Ok(Self {
-            local_db,
-            remote_db,
-            dirty,
-            last_modified,
-        })
-    }
-
-    async fn load_local_db() -> TodoResult<serde_json::Value> {
-        let data = tokio::fs::read_to_string("local_db.json").await.unwrap_or_else(|_| "{}".to_string());
-        Ok(serde_json::from_str(&data)?)
-    }
-    
+        self.journaled(format!("create list '{}'", list_name), Some(&[list_name]), |lists| {
+            match lists.iter().find(|list| list.name == list_name) {
+                Some(List { trashed: Some(_), .. }) => Err(TodoError::InTrash(format!(
+                    "list '{}', restore it with `todo restore` or purge it with `todo trash empty`",
+                    list_name
+                ))),
+                Some(_) => Ok(()),
+                None => {
+                    lists.push(new_list(list_name));
+                    Ok(())
+                }
+            }
+        }).await
+    }
+
+    /// Opens the local database at `location`. The remote MongoDB connection
+    /// is only established on first use through `remote_db`, so local
+    /// commands work offline and without `MONGODB_URI`.
+    pub async fn open(location: &Location) -> TodoResult<Self> {
+        let (storage, state_dir) = location.open().await?;
+        Ok(Self::with_storage(storage, state_dir))
+    }
+
+    pub fn with_storage(storage: Box<dyn Storage>, state_dir: PathBuf) -> Self {
+        Self {
+            storage,
+            journal: Journal::new(&state_dir.join("journal.json"), Journal::depth_from_env()),
+            history: history::Log::new(&state_dir.join(history::LOG_FILE)),
+            index: SearchIndex::new(&state_dir.join(search::INDEX_DIR)),
+            state_dir,
+            mongodb_uri: std::env::var("MONGODB_URI").ok(),
+            remote_db: OnceCell::new(),
+            in_process: Mutex::new(()),
+        }
+    }
+
+    /// Location of a bookkeeping file stored alongside the local database.
+    pub fn state_path(&self, file_name: &str) -> PathBuf {
+        self.state_dir.join(file_name)
+    }
+
+    /// Waits until no other `todo` process, or task of this one, holds the
+    /// database and keeps them out until the lock is dropped. Every change
+    /// holds it from its first read until it is stored, so that concurrent
+    /// changes never overwrite each other.
+    pub async fn lock(&self) -> TodoResult<DatabaseLock<'_>> {
+        let guard = self.in_process.lock().await;
+        let file = FileLock::acquire(&self.state_path("database"))?;
+        Ok(DatabaseLock { _guard: guard, _file: file })
+    }
+
+    pub async fn remote_db(&self) -> TodoResult<&MongoDatabase> {
+        self.remote_db.get_or_try_init(|| async {
+            let uri = self.mongodb_uri.as_deref()
+                .ok_or_else(|| TodoError::ConfigError("MONGODB_URI must be set to sync with the remote database".to_string()))?;
+
+            let mut options = ClientOptions::parse_with_resolver_config(uri, ResolverConfig::cloudflare()).await?;
+            options.app_name = Some("Todo App".to_string());
+
+            let client = Client::with_options(options)?;
+            Ok(client.database("todo_app"))
+        }).await
+    }
 
     pub async fn add_item(&self, list_name: &str, item: Item) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        let list = local_db.get_mut(list_name)
-            .and_then(|v| v.as_array_mut())
-            .ok_or_else(|| TodoError::ListNotFound(list_name.to_string()))?;
-        list.push(serde_json::to_value(item)?);
-        *self.dirty.lock().await = true;
-        self.save_local_db(&local_db).await?;
-        println!("Local database updated and marked as dirty.");
-        Ok(())
+        let action = format!("add '{}' to '{}'", item.description, list_name);
+        self.journaled(action, Some(&[list_name]), |lists| {
+            live_list(lists, list_name)?.items.push(item);
+            Ok(())
+        }).await
     }
 
+    /// Every list outside the trash, without trashed items.
     pub async fn get_lists(&self) -> TodoResult<Vec<List>> {
-        let local_db = self.local_db.lock().await;
-        let lists: Vec<List> = local_db.as_object()
-            .ok_or_else(|| TodoError::DatabaseError("Invalid local database format".into()))?
-            .iter()
-            .map(|(name, items)| List {
-                name: name.clone(),
-                items: serde_json::from_value(items.clone()).unwrap_or_default(),
-            })
-            .collect();
-        Ok(lists)
+        Ok(self.storage.get_lists().await?
+            .into_iter()
+            .filter(|list| list.trashed.is_none())
+            .map(without_trash)
+            .collect())
     }
 
+    /// A list outside the trash, without trashed items.
     pub async fn get_list(&self, name: &str) -> TodoResult<List> {
-        let local_db = self.local_db.lock().await;
-        let items = local_db.get(name)
-            .ok_or_else(|| TodoError::ListNotFound(name.to_string()))?;
-        Ok(List {
-            name: name.to_string(),
-            items: serde_json::from_value(items.clone())?,
-        })
-    }
-
-    pub async fn update_item_status(&self, list_name: &str, item_number: usize, completed: bool) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        let list = local_db.get_mut(list_name)
-            .and_then(|v| v.as_array_mut())
-            .ok_or_else(|| TodoError::ListNotFound(list_name.to_string()))?;
-
-        if item_number == 0 || item_number > list.len() {
-            return Err(TodoError::ItemNotFound(format!("Item {} in list {}", item_number, list_name)));
+        match self.storage.get_list(name).await? {
+            List { trashed: Some(_), .. } => Err(TodoError::ListNotFound(name.to_string())),
+            list => Ok(without_trash(list)),
         }
+    }
 
-        let item = list.get_mut(item_number - 1)
-            .and_then(|v| v.as_object_mut())
-            .ok_or_else(|| TodoError::DatabaseError("Invalid local database format".into()))?;
+    /// Every list including the trash, as it is synced.
+    pub async fn get_lists_with_trash(&self) -> TodoResult<Vec<List>> {
+        self.storage.get_lists().await
+    }
 
-        item.insert("completed".to_string(), serde_json::Value::Bool(completed));
-        *self.dirty.lock().await = true;
-        self.save_local_db(&local_db).await?;
+    /// Resolves a user-supplied item reference to the item it names.
+    pub async fn get_item(&self, list_name: &str, item_ref: &str) -> TodoResult<Item> {
+        let mut list = self.get_list(list_name).await?;
+        let index = find_item(&list.items, list_name, item_ref)?;
+        Ok(list.items.swap_remove(index))
+    }
+
+    /// Marks an item as completed or incomplete. Completing a recurring item
+    /// adds its next occurrence.
+    pub async fn update_item_status(&self, list_name: &str, item_ref: &str, completed: bool) -> TodoResult<()> {
+        self.update_items_status(list_name, &Selection::item(item_ref), completed, false).await?;
         Ok(())
     }
 
-    pub async fn remove_item(&self, list_name: &str, item_number: usize) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        let list = local_db.get_mut(list_name)
-            .and_then(|v| v.as_array_mut())
-            .ok_or_else(|| TodoError::ListNotFound(list_name.to_string()))?;
+    /// Marks the selected items as completed or incomplete, with `cascade`
+    /// their subtasks too; see `update_items`. Completing a recurring item
+    /// moves its rule to a new item for the next occurrence, placed after it.
+    /// Returns the items as now and the new occurrences.
+    pub async fn update_items_status(&self, list_name: &str, selection: &Selection, completed: bool, cascade: bool) -> TodoResult<(Vec<(Item, bool)>, Vec<Item>)> {
+        let state = if completed { "completed" } else { "incomplete" };
+        let action = |items| format!("mark {} in '{}' as {}", items, list_name, state);
+        let now = Utc::now();
+        self.update_and_spawn(list_name, selection, cascade, action, |item| {
+            let next = if completed && !item.completed { item.next_occurrence(now) } else { None };
+            if next.is_some() {
+                item.recurrence = None;
+            }
+            item.completed = completed;
+            next
+        }).await
+    }
 
-        if item_number == 0 || item_number > list.len() {
-            return Err(TodoError::ItemNotFound(format!("Item {} in list {}", item_number, list_name)));
-        }
+    /// The live items of a list picked by `selection`, in selection order.
+    pub async fn select_items(&self, list_name: &str, selection: &Selection) -> TodoResult<Vec<Item>> {
+        select(&self.get_list(list_name).await?, selection, false, Utc::now())
+    }
+
+    /// Like `select_items`, with each selected item followed by all its
+    /// subtasks.
+    pub async fn select_subtrees(&self, list_name: &str, selection: &Selection) -> TodoResult<Vec<Item>> {
+        select(&self.get_list(list_name).await?, selection, true, Utc::now())
+    }
 
-        list.remove(item_number - 1);
-        *self.dirty.lock().await = true;
-        self.save_local_db(&local_db).await?;
+    /// Applies `update` to every selected item and stores the changed ones in
+    /// one write, journaled as `action` of a description of them. Returns
+    /// each selected item as it is now and whether `update` changed it.
+    pub async fn update_items(
+        &self,
+        list_name: &str,
+        selection: &Selection,
+        action: impl FnOnce(String) -> String,
+        mut update: impl FnMut(&mut Item),
+    ) -> TodoResult<Vec<(Item, bool)>> {
+        let (results, _) = self.update_and_spawn(list_name, selection, false, action, |item| {
+            update(item);
+            None
+        }).await?;
+        Ok(results)
+    }
+
+    /// Like `update_items`, with `subtrees` also updating the subtasks of
+    /// the selected items, and where `update` may return a new item to add
+    /// right after the one it updated in the same write.
+    async fn update_and_spawn(
+        &self,
+        list_name: &str,
+        selection: &Selection,
+        subtrees: bool,
+        action: impl FnOnce(String) -> String,
+        mut update: impl FnMut(&mut Item) -> Option<Item>,
+    ) -> TodoResult<(Vec<(Item, bool)>, Vec<Item>)> {
+        let now = Utc::now();
+        self.journaled_with(Some(&[list_name]), |lists| {
+            let list = live_list(lists, list_name)?;
+            let mut results = Vec::new();
+            let mut spawned = Vec::new();
+            for item in select(list, selection, subtrees, now)? {
+                let mut updated = item.clone();
+                if let Some(new) = update(&mut updated) {
+                    spawned.push((item.id.clone(), new));
+                }
+                let changed = updated != item;
+                results.push((updated, changed));
+            }
+
+            let changed: Vec<Item> = results.iter().filter(|(_, changed)| *changed).map(|(item, _)| item.clone()).collect();
+            for item in &changed {
+                if let Some(stored) = list.items.iter_mut().find(|stored| stored.id == item.id) {
+                    *stored = item.clone();
+                }
+            }
+            for (after, new) in &spawned {
+                let index = list.items.iter().position(|item| item.id == *after).map_or(list.items.len(), |index| index + 1);
+                list.items.insert(index, new.clone());
+            }
+            let spawned = spawned.into_iter().map(|(_, new)| new).collect();
+            Ok((action(describe_items(&changed)), (results, spawned)))
+        }).await
+    }
+
+    /// Applies `update` to the referenced item and stores the result.
+    pub async fn update_item(&self, list_name: &str, item_ref: &str, update: impl FnOnce(&mut Item)) -> TodoResult<Item> {
+        self.journaled_with(Some(&[list_name]), |lists| {
+            let list = live_list(lists, list_name)?;
+            let index = find_live_item(list, item_ref)?;
+            let item = &mut list.items[index];
+            let action = format!("edit '{}' in '{}'", item.description, list_name);
+            update(item);
+            Ok((action, item.clone()))
+        }).await
+    }
+
+    /// Replaces the live items of a list with checklist `lines` edited from
+    /// `checklist::render`. Items whose lines are gone move to the trash.
+    pub async fn edit_list(&self, list_name: &str, lines: Vec<Line>) -> TodoResult<Summary> {
+        let now = Utc::now();
+        self.journaled(format!("edit list '{}'", list_name), Some(&[list_name]), |lists| {
+            let list = live_list(lists, list_name)?;
+            let (live, trashed): (Vec<Item>, Vec<Item>) = list.items.iter().cloned().partition(|item| item.trashed.is_none());
+            let (items, removed, summary) = checklist::apply(&live, lines);
+            if !summary.is_empty() {
+                list.items = items.into_iter()
+                    .chain(trashed)
+                    .chain(removed.into_iter().map(|item| Item { trashed: Some(now), ..item }))
+                    .collect();
+            }
+            Ok(summary)
+        }).await
+    }
+
+    /// Renames a list, failing if the new name is taken, including by a list
+    /// in the trash.
+    pub async fn rename_list(&self, list_name: &str, new_name: &str) -> TodoResult<()> {
+        let action = format!("rename list '{}' to '{}'", list_name, new_name);
+        self.journaled(action, Some(&[list_name, new_name]), |lists| {
+            live_list(lists, list_name)?;
+            match lists.iter().find(|list| list.name == new_name) {
+                Some(List { trashed: Some(_), .. }) => {
+                    return Err(TodoError::ListExists(format!(
+                        "'{}' is in the trash, purge it with `todo trash empty` first",
+                        new_name
+                    )));
+                }
+                Some(_) => return Err(TodoError::ListExists(new_name.to_string())),
+                None => {}
+            }
+            for list in lists.iter_mut().filter(|list| list.name == list_name) {
+                list.name = new_name.to_string();
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Moves the selected items with their subtasks to `placement` in
+    /// `target`, which may be the same list and is created if missing. Returns the
+    /// moved items with their new paths.
+    pub async fn move_items(&self, list_name: &str, selection: &Selection, target: &str, placement: Placement) -> TodoResult<Vec<(Item, String)>> {
+        let action = if list_name == target {
+            format!("reorder items in '{}'", list_name)
+        } else {
+            format!("move items from '{}' to '{}'", list_name, target)
+        };
+        self.transfer(action, list_name, selection, target, placement, false).await
+    }
+
+    /// Copies the selected items and their subtasks with new IDs to
+    /// `placement` in `target`, creating it if missing. Returns the copies
+    /// with their paths.
+    pub async fn copy_items(&self, list_name: &str, selection: &Selection, target: &str, placement: Placement) -> TodoResult<Vec<(Item, String)>> {
+        let action = format!("copy items from '{}' to '{}'", list_name, target);
+        self.transfer(action, list_name, selection, target, placement, true).await
+    }
+
+    /// Items keep their parent if it moves along or stays in the same list,
+    /// and land among its subtasks; others become top-level items.
+    async fn transfer(&self, action: String, list_name: &str, selection: &Selection, target: &str, placement: Placement, copy: bool) -> TodoResult<Vec<(Item, String)>> {
+        let now = Utc::now();
+        self.journaled(action, Some(&[list_name, target]), |lists| {
+            let source = live_list(lists, list_name)?;
+            let mut picked = select(source, selection, true, now)?;
+            if !copy {
+                source.items.retain(|item| !picked.iter().any(|picked| picked.id == item.id));
+            }
+            let picked_ids: HashSet<String> = picked.iter().map(|item| item.id.clone()).collect();
+            if list_name != target {
+                for item in &mut picked {
+                    item.parent = item.parent.take().filter(|parent| picked_ids.contains(parent));
+                }
+            }
+            if copy {
+                let ids: HashMap<String, String> = picked_ids.into_iter().map(|id| (id, Item::generate_id())).collect();
+                for item in &mut picked {
+                    item.id = ids[&item.id].clone();
+                    item.parent = item.parent.take().map(|parent| ids.get(&parent).cloned().unwrap_or(parent));
+                }
+            }
+            let parent = picked.first().and_then(|item| item.parent.clone());
+
+            let list = match lists.iter().position(|list| list.name == target) {
+                Some(index) if lists[index].trashed.is_some() => {
+                    return Err(TodoError::InTrash(format!("list '{}', restore it with `todo restore` first", target)));
+                }
+                Some(index) => &mut lists[index],
+                None => {
+                    lists.push(new_list(target));
+                    lists.last_mut().unwrap()
+                }
+            };
+            insert_items(list, picked.clone(), placement, parent.as_deref());
+            let live = without_trash(list.clone()).items;
+            let paths: HashMap<&str, String> = tree::walk(&live).into_iter().map(|node| (node.item.id.as_str(), node.reference())).collect();
+            Ok(picked.into_iter()
+                .map(|item| {
+                    let path = paths.get(item.id.as_str()).cloned().unwrap_or_default();
+                    (item, path)
+                })
+                .collect())
+        }).await
+    }
+
+    /// Moves an item to the trash.
+    pub async fn remove_item(&self, list_name: &str, item_ref: &str) -> TodoResult<()> {
+        self.remove_items(list_name, &Selection::item(item_ref)).await?;
         Ok(())
     }
 
+    /// Moves the selected items and their subtasks to the trash in one write
+    /// and returns them.
+    pub async fn remove_items(&self, list_name: &str, selection: &Selection) -> TodoResult<Vec<Item>> {
+        let now = Utc::now();
+        let action = |items| format!("remove {} from '{}'", items, list_name);
+        let (removed, _) = self.update_and_spawn(list_name, selection, true, action, |item| {
+            item.trashed = Some(now);
+            None
+        }).await?;
+        Ok(removed.into_iter().map(|(item, _)| item).collect())
+    }
+
+    /// Moves a list to the trash along with its items.
     pub async fn remove_list(&self, list_name: &str) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        if local_db.as_object_mut().unwrap().remove(list_name).is_none() {
-            return Err(TodoError::ListNotFound(list_name.to_string()));
+        let now = Utc::now();
+        self.journaled(format!("remove list '{}'", list_name), Some(&[list_name]), |lists| {
+            live_list(lists, list_name)?.trashed = Some(now);
+            Ok(())
+        }).await
+    }
+
+    /// Moves every list to the trash.
+    pub async fn remove_all_lists(&self) -> TodoResult<()> {
+        let now = Utc::now();
+        self.journaled("remove all lists".to_string(), None, |lists| {
+            for list in lists.iter_mut().filter(|list| list.trashed.is_none()) {
+                list.trashed = Some(now);
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Lists that are in the trash or hold trashed items. Trashed lists come
+    /// with all their items, other lists only with their trashed items.
+    pub async fn trash(&self) -> TodoResult<Vec<List>> {
+        Ok(self.storage.get_lists().await?
+            .into_iter()
+            .filter_map(|mut list| {
+                if list.trashed.is_none() {
+                    list.items.retain(|item| item.trashed.is_some());
+                    if list.items.is_empty() {
+                        return None;
+                    }
+                }
+                Some(list)
+            })
+            .collect())
+    }
+
+    /// Takes a list, or one of its items, out of the trash and describes
+    /// what was restored. Items are referenced among the
+    /// list's trashed items and come back with the subtasks removed along
+    /// with them. Restoring an item of a trashed list restores the list too.
+    pub async fn restore(&self, list_name: &str, item_ref: Option<&str>) -> TodoResult<String> {
+        self.journaled_with(Some(&[list_name]), |lists| {
+            let list = lists.iter_mut()
+                .find(|list| list.name == list_name)
+                .ok_or_else(|| TodoError::ListNotFound(list_name.to_string()))?;
+            let Some(item_ref) = item_ref else {
+                if list.trashed.is_none() {
+                    return Err(TodoError::NotInTrash(format!("list '{}'", list_name)));
+                }
+                list.trashed = None;
+                let restored = format!("list '{}'", list_name);
+                return Ok((format!("restore {}", restored), restored));
+            };
+
+            let trashed: Vec<Item> = list.items.iter().filter(|item| item.trashed.is_some()).cloned().collect();
+            let item = &trashed[find_listed_item(&trashed, list_name, item_ref)?];
+            let index = list.items.iter().position(|other| other.id == item.id).unwrap_or_default();
+            let indexes: Vec<usize> = std::iter::once(index)
+                .chain(tree::descendants(&list.items, index).into_iter().filter(|&other| list.items[other].trashed == item.trashed))
+                .collect();
+            let restored = match indexes.len() {
+                1 => format!("'{}' to '{}'", item.description, list_name),
+                n => format!("'{}' and {} subtasks to '{}'", item.description, n - 1, list_name),
+            };
+            for index in indexes {
+                list.items[index].trashed = None;
+            }
+            list.trashed = None;
+            Ok((format!("restore {}", restored), restored))
+        }).await
+    }
+
+    /// Permanently deletes everything in the trash and returns how many
+    /// lists and items were deleted.
+    pub async fn empty_trash(&self) -> TodoResult<usize> {
+        self.journaled("empty the trash".to_string(), None, |lists| {
+            let count = lists.len();
+            lists.retain(|list| list.trashed.is_none());
+            let mut count = count - lists.len();
+            for list in lists.iter_mut() {
+                let items = list.items.len();
+                list.items.retain(|item| item.trashed.is_none());
+                count += items - list.items.len();
+            }
+            Ok(count)
+        }).await
+    }
+
+    /// Reverts the most recent journaled operation and returns its
+    /// description, or `None` if there is nothing to undo.
+    pub async fn undo(&self) -> TodoResult<Option<String>> {
+        self.step(true).await
+    }
+
+    /// Reapplies the most recently undone operation and returns its
+    /// description, or `None` if there is nothing to redo.
+    pub async fn redo(&self) -> TodoResult<Option<String>> {
+        self.step(false).await
+    }
+
+    /// Does the work of `undo` if `undo`, or else of `redo`. The entry is
+    /// moved to the other stack ahead of the write, and moved back if the
+    /// write fails.
+    async fn step(&self, undo: bool) -> TodoResult<Option<String>> {
+        let _lock = self.lock().await?;
+        let _journal_lock = self.journal.lock()?;
+        let mut history = self.journal.load().await?;
+        let (from, to) = stacks(&mut history, undo);
+        let Some(entry) = from.pop() else {
+            return Ok(None);
+        };
+        to.push(entry.clone());
+        self.journal.save(&history).await?;
+
+        let verb = if undo { "undo" } else { "redo" };
+        if let Err(e) = self.apply_changes(format!("{} {}", verb, entry.action), &entry.changes, undo).await {
+            let (from, to) = stacks(&mut history, undo);
+            from.extend(to.pop());
+            self.journal.save(&history).await?;
+            return Err(e);
         }
-        *self.dirty.lock().await = true;
-        self.save_local_db(&local_db).await?;
+        Ok(Some(entry.action))
+    }
+
+    /// An item with the recorded changes to it, oldest first.
+    pub async fn item_history(&self, list_name: &str, item_ref: &str) -> TodoResult<(Item, Vec<Event>)> {
+        let item = self.get_item(list_name, item_ref).await?;
+        let events = history::item_events(&self.history.load()?, &item.id);
+        Ok((item, events))
+    }
+
+    /// A list with the recorded changes to it, oldest first.
+    pub async fn list_history(&self, list_name: &str) -> TodoResult<(List, Vec<Event>)> {
+        let list = self.get_list(list_name).await?;
+        let events = history::list_events(&self.history.load()?, list_name);
+        Ok((list, events))
+    }
+
+    /// Reverts `changes` if `undo`, or else reapplies them, leaving other
+    /// lists as they are, and records that in the history as `action`.
+    /// Must be called with the database locked.
+    async fn apply_changes(&self, action: String, changes: &[ListChange], undo: bool) -> TodoResult<()> {
+        let names: Vec<&str> = changes.iter().map(|change| change.name.as_str()).collect();
+        let current = self.storage.find_lists(&names).await?;
+        let lists: Vec<List> = changes.iter()
+            .filter_map(|change| change.apply(current.iter().find(|list| list.name == change.name).cloned(), undo))
+            .collect();
+        let operations = storage::operations(&current, &lists);
+        self.write(&action, Utc::now(), &journal::diff(current, lists.clone()), operations).await?;
+        self.update_index(&names.into_iter().collect(), &lists);
         Ok(())
     }
 
-    pub async fn remove_all_lists(&self) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        *local_db = serde_json::Value::Object(serde_json::Map::new());
-        *self.dirty.lock().await = true;
-        self.save_local_db(&local_db).await?;
+    /// Stores `operations`, which made `changes`, recording them in the
+    /// history ahead of the write and taking the record back if the write
+    /// fails.
+    async fn write(&self, action: &str, at: DateTime<Utc>, changes: &[ListChange], operations: Vec<Operation>) -> TodoResult<()> {
+        let logged = match Record::new(action, at, changes) {
+            Some(record) => Some(self.history.append(&record)?),
+            None => None,
+        };
+        if let Err(e) = self.storage.apply(operations).await {
+            if let Some(offset) = logged {
+                self.history.truncate(offset)?;
+            }
+            return Err(e);
+        }
         Ok(())
     }
 
-    pub async fn set_dirty(&self, value: bool) {
-        *self.dirty.lock().await = value;
+    /// Reindexes the lists named in `changed` for search. As the change is
+    /// stored by then, an index that cannot be updated is marked stale for
+    /// the next search to rebuild rather than failing it.
+    fn update_index(&self, changed: &BTreeSet<&str>, lists: &[List]) {
+        if self.index.update(changed, lists).is_err() {
+            // Should this fail too, `todo search --reindex` still helps.
+            let _ = self.index.invalidate();
+        }
     }
 
-    pub async fn update_local_db(&self, new_db: serde_json::Value) -> TodoResult<()> {
-        let mut local_db = self.local_db.lock().await;
-        *local_db = new_db;
-        self.save_local_db(&local_db).await
+    /// Runs `change` on the lists named in `scope`, or every list if `None`,
+    /// with the database locked, writes what it changed in one go and
+    /// journals it so it can be undone. `change` may only touch lists in
+    /// `scope`; those that do not exist yet it may create. The lists and
+    /// items it changed get their timestamps set, in the same undo step.
+    async fn journaled<T>(&self, action: String, scope: Option<&[&str]>, change: impl FnOnce(&mut Vec<List>) -> TodoResult<T>) -> TodoResult<T> {
+        self.journaled_with(scope, |lists| Ok((action, change(lists)?))).await
     }
 
-    async fn save_local_db(&self, local_db: &serde_json::Value) -> TodoResult<()> {
-        let data = serde_json::to_string_pretty(&local_db)?;
-        tokio::fs::write("local_db.json", data).await?;
-        Ok(())
+    /// Like `journaled`, where `change` also returns the action to journal.
+    async fn journaled_with<T>(&self, scope: Option<&[&str]>, change: impl FnOnce(&mut Vec<List>) -> TodoResult<(String, T)>) -> TodoResult<T> {
+        let lock = self.lock().await?;
+        self.commit(&lock, scope, true, change).await
     }
 
-    
-    
-    pub async fn get_local_db(&self) -> TodoResult<serde_json::Value> {
-        let local_db = self.local_db.lock().await;
-        Ok(local_db.clone())
+    /// Does the work of `journaled_with` under `lock`, leaving timestamps as
+    /// `change` set them unless `stamped`.
+    async fn commit<T>(
+        &self,
+        _lock: &DatabaseLock<'_>,
+        scope: Option<&[&str]>,
+        stamped: bool,
+        change: impl FnOnce(&mut Vec<List>) -> TodoResult<(String, T)>,
+    ) -> TodoResult<T> {
+        let original = match scope {
+            Some(names) => self.storage.find_lists(names).await?,
+            None => self.storage.get_lists().await?,
+        };
+        let mut lists = original.clone();
+        let (action, result) = change(&mut lists)?;
+        let now = Utc::now();
+        if stamped {
+            for list in lists.iter_mut().filter(|list| !original.contains(list)) {
+                stamp(&original, list, now);
+            }
+        }
+        let operations = storage::operations(&original, &lists);
+        if operations.is_empty() {
+            return Ok(result);
+        }
+
+        // Journaled ahead of the write, and taken back if the write fails.
+        let changes = journal::diff(original.clone(), lists.clone());
+        let journaled = self.journal.record(action.clone(), now, changes.clone()).await?;
+        if let Err(e) = self.write(&action, now, &changes, operations).await {
+            if let Some(previous) = journaled {
+                self.journal.save(&previous).await?;
+            }
+            return Err(e);
+        }
+
+        let changed = original.iter().chain(&lists)
+            .filter(|list| original.contains(list) != lists.contains(list))
+            .map(|list| list.name.as_str())
+            .collect();
+        self.update_index(&changed, &lists);
+        Ok(result)
     }
-    
-    
 
-    pub async fn update_last_modified(&self) {
-        *self.last_modified.lock().await = SystemTime::now();
+    /// Finds live items matching `query`, best first, building the search
+    /// index first if there is none.
+    pub async fn search(&self, query: &str, mode: search::Mode) -> TodoResult<Vec<Hit>> {
+        if let Some(hits) = self.index.search(query, mode)? {
+            return Ok(hits);
+        }
+        self.reindex().await?;
+        Ok(self.index.search(query, mode)?.unwrap_or_default())
     }
 
-    #[allow(dead_code)]
-    pub async fn new_local_only() -> TodoResult<Self> {
-        let local_db = Self::load_local_db().await?;
-        let local_db = Arc::new(Mutex::new(local_db));
-        let dirty = Arc::new(Mutex::new(false));
-        let last_modified = Arc::new(Mutex::new(SystemTime::now()));
+    /// Rebuilds the search index from the stored lists.
+    pub async fn reindex(&self) -> TodoResult<()> {
+        self.index.rebuild(&self.storage.get_lists().await?)
+    }
 
-        let client = mongodb::Client::with_uri_str("mongodb://localhost:27017").await?;
-        let remote_db = client.database("dummy_db");
+    /// Replaces every list with the result of a sync, which carries the
+    /// timestamps of whichever side each change came from. The caller holds
+    /// `lock` from reading the lists it merged.
+    pub async fn update_local_db(&self, lock: &DatabaseLock<'_>, lists: Vec<List>) -> TodoResult<()> {
+        self.commit(lock, None, false, |stored| {
+            *stored = lists;
+            Ok(("replace all lists".to_string(), ()))
+        }).await
+    }
 
-        Ok(Self {
-            local_db,
-            remote_db,
-            dirty,
-            last_modified,
-        })
+}
+
+/// The stack `undo` takes entries from and the one it moves them to.
+fn stacks(history: &mut History, undo: bool) -> (&mut Vec<Entry>, &mut Vec<Entry>) {
+    if undo {
+        (&mut history.undo, &mut history.redo)
+    } else {
+        (&mut history.redo, &mut history.undo)
     }
+}
+
+/// Held while the database is locked; see `Database::lock`.
+pub struct DatabaseLock<'a> {
+    _guard: MutexGuard<'a, ()>,
+    _file: FileLock,
+}
+
+/// The list named `name` among `lists`, unless it is in the trash.
+fn live_list<'a>(lists: &'a mut [List], name: &str) -> TodoResult<&'a mut List> {
+    lists.iter_mut()
+        .find(|list| list.name == name && list.trashed.is_none())
+        .ok_or_else(|| TodoError::ListNotFound(name.to_string()))
+}
 
+fn new_list(name: &str) -> List {
+    List { name: name.to_string(), items: Vec::new(), trashed: None, created_at: None, updated_at: None }
 }
 
+/// The live items of `list` picked by `selection`, in selection order, with
+/// `subtrees` each followed by all its subtasks.
+fn select(list: &List, selection: &Selection, subtrees: bool, now: DateTime<Utc>) -> TodoResult<Vec<Item>> {
+    let live = without_trash(list.clone());
+    let mut indexes = Vec::new();
+    for index in selection.resolve(&live, now)? {
+        let subtasks = if subtrees { tree::descendants(&live.items, index) } else { Vec::new() };
+        for index in std::iter::once(index).chain(subtasks) {
+            if !indexes.contains(&index) {
+                indexes.push(index);
+            }
+        }
+    }
+    Ok(indexes.into_iter().map(|index| live.items[index].clone()).collect())
+}
+
+/// Where in `list` the live item that `item_ref` names is.
+fn find_live_item(list: &List, item_ref: &str) -> TodoResult<usize> {
+    let live = without_trash(list.clone());
+    let id = &live.items[find_item(&live.items, &list.name, item_ref)?].id;
+    Ok(list.items.iter().position(|item| item.id == *id).unwrap_or_default())
+}
+
+/// How journal entries name the items an operation affected.
+fn describe_items(items: &[Item]) -> String {
+    match items {
+        [item] => format!("'{}'", item.description),
+        items => format!("{} items", items.len()),
+    }
+}
+
+/// Sets the timestamps of `list`, which changed since `before`, and of its
+/// items that are new or changed. Moving an item to another list or position
+/// only changes the lists.
+fn stamp(before: &[List], list: &mut List, now: DateTime<Utc>) {
+    let previous: HashMap<&str, &Item> = before.iter()
+        .flat_map(|list| &list.items)
+        .map(|item| (item.id.as_str(), item))
+        .collect();
+    for item in &mut list.items {
+        match previous.get(item.id.as_str()) {
+            Some(old) if *old == item => continue,
+            Some(old) if old.completed != item.completed => {
+                item.completed_at = item.completed.then_some(now);
+            }
+            Some(_) => {}
+            None => {
+                item.created_at.get_or_insert(now);
+                if item.completed {
+                    item.completed_at.get_or_insert(now);
+                }
+            }
+        }
+        item.updated_at = Some(now);
+    }
+    list.created_at.get_or_insert(now);
+    list.updated_at = Some(now);
+}
+
+fn without_trash(mut list: List) -> List {
+    list.items.retain(|item| item.trashed.is_none());
+    list
+}
+
+/// Inserts `items` into `list` so that the first of them lands at
+/// `placement` among the live subtasks of `parent`, or the live top-level
+/// items.
+fn insert_items(list: &mut List, items: Vec<Item>, placement: Placement, parent: Option<&str>) {
+    let index = match placement {
+        Placement::Top => 0,
+        Placement::Bottom => list.items.len(),
+        Placement::At(position) => {
+            let live = without_trash(list.clone()).items;
+            let sibling = tree::walk(&live).into_iter()
+                .filter(|node| node.parent == parent)
+                .nth(position.saturating_sub(1))
+                .map(|node| node.item.id.clone());
+            sibling
+                .and_then(|id| list.items.iter().position(|item| item.id == id))
+                .unwrap_or(list.items.len())
+        }
+    };
+    list.items.splice(index..index, items);
+}
+
+/// A database backed by a JSON file in a fresh temporary directory.
+#[cfg(test)]
+pub(crate) async fn test_database() -> (Database, tempfile::TempDir) {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let storage = crate::storage::JsonStorage::open(&dir.path().join("local_db.json")).await.expect("Failed to open storage");
+    (Database::with_storage(Box::new(storage), dir.path().to_path_buf()), dir)
+}
+
+#[tokio::test]
+async fn test_remote_db_requires_uri() {
+    let (mut db, _dir) = test_database().await;
+    db.mongodb_uri = None;
+
+    assert!(matches!(db.remote_db().await, Err(TodoError::ConfigError(_))));
+}
 
 #[tokio::test]
 async fn test_basic_local_operations() {
-    let db = Database::new().await.expect("Failed to create database");
+    let (db, _dir) = test_database().await;
 
     // Test creating a list
     db.create_list("Test List").await.expect("Failed to create list");
 
     // Test adding an item
-    let item = Item {
-        description: "Test Item".to_string(),
-        completed: false,
-    };
+    let item = Item::new("Test Item");
+    let item_id = item.id.clone();
     db.add_item("Test List", item).await.expect("Failed to add item");
 
     // Test getting the list
@@ -213,9 +778,10 @@ async fn test_basic_local_operations() {
     assert_eq!(list.name, "Test List");
     assert_eq!(list.items.len(), 1);
     assert_eq!(list.items[0].description, "Test Item");
+    assert_eq!(list.items[0].id, item_id);
 
     // Test updating item status
-    db.update_item_status("Test List", 1, true).await.expect("Failed to update item status");
+    db.update_item_status("Test List", &item_id[..6], true).await.expect("Failed to update item status");
     let updated_list = db.get_list("Test List").await.expect("Failed to get updated list");
     assert!(updated_list.items[0].completed);
 
@@ -224,3 +790,412 @@ async fn test_basic_local_operations() {
     let lists = db.get_lists().await.expect("Failed to get lists");
     assert_eq!(lists.len(), 0);
 }
+
+#[tokio::test]
+async fn test_undo_and_redo() {
+    let (db, dir) = test_database().await;
+
+    db.create_list("Shop").await.expect("Failed to create list");
+    db.create_list("Work").await.expect("Failed to create list");
+    db.add_item("Shop", Item::new("Milk")).await.expect("Failed to add item");
+    db.add_item("Work", Item::new("Deploy")).await.expect("Failed to add item");
+    db.update_item_status("Shop", "1", true).await.expect("Failed to update item status");
+    db.remove_all_lists().await.expect("Failed to remove lists");
+    assert!(db.get_lists().await.unwrap().is_empty());
+
+    // The journal is read back by a later invocation
+    let storage = crate::storage::JsonStorage::open(&dir.path().join("local_db.json")).await.unwrap();
+    let db = Database::with_storage(Box::new(storage), dir.path().to_path_buf());
+
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("remove all lists"));
+    assert_eq!(db.get_lists().await.unwrap().len(), 2);
+    assert!(db.get_list("Shop").await.unwrap().items[0].completed);
+
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("mark 'Milk' in 'Shop' as completed"));
+    assert!(!db.get_list("Shop").await.unwrap().items[0].completed);
+
+    assert_eq!(db.redo().await.unwrap().as_deref(), Some("mark 'Milk' in 'Shop' as completed"));
+    assert!(db.get_list("Shop").await.unwrap().items[0].completed);
+
+    // A new change discards what could still be redone
+    db.remove_list("Work").await.expect("Failed to remove list");
+    assert_eq!(db.redo().await.unwrap(), None);
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("remove list 'Work'"));
+    assert_eq!(db.get_list("Work").await.unwrap().items[0].description, "Deploy");
+}
+
+#[tokio::test]
+async fn test_trash() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Shop").await.unwrap();
+    db.create_list("Work").await.unwrap();
+    db.add_item("Shop", Item::new("Milk")).await.unwrap();
+    db.add_item("Shop", Item::new("Bread")).await.unwrap();
+    db.add_item("Work", Item::new("Deploy")).await.unwrap();
+
+    // Trashed entries are hidden, and positions skip trashed items
+    db.remove_item("Shop", "1").await.unwrap();
+    assert_eq!(db.get_list("Shop").await.unwrap().items.len(), 1);
+    assert_eq!(db.get_item("Shop", "1").await.unwrap().description, "Bread");
+    db.remove_list("Work").await.unwrap();
+    assert!(matches!(db.get_list("Work").await, Err(TodoError::ListNotFound(_))));
+    assert!(matches!(db.create_list("Work").await, Err(TodoError::InTrash(_))));
+    assert_eq!(db.get_lists().await.unwrap().len(), 1);
+
+    let trash = db.trash().await.unwrap();
+    assert_eq!(trash.len(), 2);
+    assert_eq!(trash[0].items[0].description, "Milk");
+    assert!(trash[1].trashed.is_some());
+
+    assert_eq!(db.restore("Shop", Some("1")).await.unwrap(), "'Milk' to 'Shop'");
+    assert_eq!(db.get_list("Shop").await.unwrap().items[0].description, "Milk");
+    assert!(matches!(db.restore("Shop", None).await, Err(TodoError::NotInTrash(_))));
+
+    db.remove_all_lists().await.unwrap();
+    assert!(db.get_lists().await.unwrap().is_empty());
+    db.restore("Work", None).await.unwrap();
+    assert_eq!(db.get_list("Work").await.unwrap().items[0].description, "Deploy");
+
+    db.remove_item("Work", "1").await.unwrap();
+    assert_eq!(db.empty_trash().await.unwrap(), 2);
+    assert!(db.trash().await.unwrap().is_empty());
+    let lists = db.get_lists_with_trash().await.unwrap();
+    assert_eq!(lists.len(), 1);
+    assert!(lists[0].items.is_empty());
+}
+
+#[tokio::test]
+async fn test_search_index_follows_changes() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Work").await.unwrap();
+    db.add_item("Work", Item::new("Deploy release")).await.unwrap();
+    let found = |hits: Vec<Hit>| hits.into_iter().map(|hit| hit.description).collect::<Vec<_>>();
+
+    // The first search builds the index, later changes keep it current
+    assert_eq!(found(db.search("deploy", search::Mode::Words).await.unwrap()), vec!["Deploy release"]);
+    db.add_item("Work", Item::new("Deploy docs")).await.unwrap();
+    db.remove_item("Work", "1").await.unwrap();
+    assert_eq!(found(db.search("deploy", search::Mode::Words).await.unwrap()), vec!["Deploy docs"]);
+    db.update_item("Work", "1", |item| item.notes = "Needs a changelog".to_string()).await.unwrap();
+    let hits = db.search("changelog", search::Mode::Words).await.unwrap();
+    assert_eq!(hits[0].note.as_deref(), Some("Needs a changelog"));
+    db.undo().await.unwrap();
+
+    db.undo().await.unwrap();
+    assert_eq!(db.search("deploy", search::Mode::Words).await.unwrap().len(), 2);
+    db.remove_list("Work").await.unwrap();
+    assert!(db.search("deploy", search::Mode::Words).await.unwrap().is_empty());
+
+    // A change goes through even if the index cannot follow it, and the
+    // next search rebuilds the index.
+    db.restore("Work", None).await.unwrap();
+    let segment = db.state_path(search::INDEX_DIR).join("%57ork.json");
+    std::fs::remove_file(&segment).ok();
+    std::fs::create_dir_all(segment.join("blocked")).unwrap();
+    db.add_item("Work", Item::new("Deploy again")).await.unwrap();
+    assert_eq!(db.search("deploy", search::Mode::Words).await.unwrap().len(), 3);
+}
+
+/// Storage that fails every write while `failing` is set.
+#[cfg(test)]
+struct FlakyStorage {
+    inner: crate::storage::JsonStorage,
+    failing: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Storage for FlakyStorage {
+    async fn get_lists(&self) -> TodoResult<Vec<List>> {
+        self.inner.get_lists().await
+    }
+
+    async fn get_list(&self, name: &str) -> TodoResult<List> {
+        self.inner.get_list(name).await
+    }
+
+    async fn find_lists(&self, names: &[&str]) -> TodoResult<Vec<List>> {
+        self.inner.find_lists(names).await
+    }
+
+    async fn replace_all(&self, lists: Vec<List>) -> TodoResult<()> {
+        self.inner.replace_all(lists).await
+    }
+
+    async fn apply(&self, operations: Vec<Operation>) -> TodoResult<()> {
+        if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(TodoError::DatabaseError("disk full".to_string()));
+        }
+        self.inner.apply(operations).await
+    }
+}
+
+#[tokio::test]
+async fn test_failed_writes_are_not_journaled() {
+    use std::sync::atomic::Ordering;
+    let dir = tempfile::tempdir().unwrap();
+    let inner = crate::storage::JsonStorage::open(&dir.path().join("local_db.json")).await.unwrap();
+    let failing = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let storage = FlakyStorage { inner, failing: failing.clone() };
+    let db = Database::with_storage(Box::new(storage), dir.path().to_path_buf());
+    db.create_list("Home").await.unwrap();
+    let milk = Item::new("Buy milk");
+    db.add_item("Home", milk.clone()).await.unwrap();
+    db.undo().await.unwrap();
+    let journal = db.journal.load().await.unwrap();
+    let history = db.history.load().unwrap();
+
+    failing.store(true, Ordering::SeqCst);
+    assert!(db.add_item("Home", Item::new("Call Bob")).await.is_err());
+    assert!(db.redo().await.is_err());
+    assert!(db.undo().await.is_err());
+    assert_eq!(db.journal.load().await.unwrap(), journal);
+    assert_eq!(db.history.load().unwrap(), history);
+
+    failing.store(false, Ordering::SeqCst);
+    assert_eq!(db.redo().await.unwrap().as_deref(), Some("add 'Buy milk' to 'Home'"));
+    let items = db.get_list("Home").await.unwrap().items;
+    assert_eq!(items.iter().map(|item| &item.id).collect::<Vec<_>>(), vec![&milk.id]);
+}
+
+#[tokio::test]
+async fn test_edit_and_rename_list() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Work").await.unwrap();
+    for description in ["Deploy", "Write notes", "Call Bob"] {
+        db.add_item("Work", Item::new(description)).await.unwrap();
+    }
+    let original = db.get_list("Work").await.unwrap();
+
+    let lines = checklist::parse("- [x] Deploy\n- [ ] Write release notes\n").unwrap();
+    let summary = db.edit_list("Work", lines).await.unwrap();
+    assert_eq!((summary.changed, summary.removed), (2, 1));
+    let edited = db.get_list("Work").await.unwrap();
+    assert_eq!(edited.items[1].id, original.items[1].id);
+    assert_eq!(edited.items[1].description, "Write release notes");
+    assert_eq!(db.trash().await.unwrap()[0].items[0].description, "Call Bob");
+
+    db.create_list("Home").await.unwrap();
+    assert!(matches!(db.rename_list("Work", "Home").await, Err(TodoError::ListExists(_))));
+    assert!(matches!(db.rename_list("Missing", "Other").await, Err(TodoError::ListNotFound(_))));
+    db.rename_list("Work", "Office").await.unwrap();
+    assert_eq!(db.get_list("Office").await.unwrap().items, edited.items);
+    assert!(db.get_list("Work").await.is_err());
+
+    db.undo().await.unwrap();
+    assert_eq!(db.get_list("Work").await.unwrap().items, edited.items);
+    assert!(db.get_list("Office").await.is_err());
+}
+
+#[tokio::test]
+async fn test_move_copy_and_reorder() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Inbox").await.unwrap();
+    db.create_list("Work").await.unwrap();
+    for description in ["a", "b", "c", "d", "e"] {
+        db.add_item("Inbox", Item::new(description)).await.unwrap();
+    }
+    db.add_item("Work", Item::new("w")).await.unwrap();
+    db.remove_item("Inbox", "3").await.unwrap();
+    let select = |input: &str| Selection::parse(input, &chrono::Local::now()).unwrap();
+    let descriptions = |list: List| list.items.into_iter().map(|item| item.description).collect::<Vec<_>>();
+
+    // Positions count live items only, as shown by `todo show`.
+    let moved = db.move_items("Inbox", &select("2-3,1"), "Work", Placement::Top).await.unwrap();
+    assert_eq!(moved.iter().map(|(item, position)| (item.description.as_str(), position.as_str())).collect::<Vec<_>>(), vec![("b", "1"), ("d", "2"), ("a", "3")]);
+    assert_eq!(descriptions(db.get_list("Inbox").await.unwrap()), vec!["e"]);
+    assert_eq!(descriptions(db.get_list("Work").await.unwrap()), vec!["b", "d", "a", "w"]);
+
+    let copied = db.copy_items("Work", &select("4"), "Inbox", Placement::At(1)).await.unwrap();
+    assert_ne!(copied[0].0.id, db.get_item("Work", "4").await.unwrap().id);
+    assert_eq!(descriptions(db.get_list("Inbox").await.unwrap()), vec!["w", "e"]);
+
+    db.move_items("Work", &select("1"), "Work", Placement::At(3)).await.unwrap();
+    assert_eq!(descriptions(db.get_list("Work").await.unwrap()), vec!["d", "a", "b", "w"]);
+    db.move_items("Work", &select("w"), "Work", Placement::At(1)).await.unwrap_err();
+    db.move_items("Work", &select("4"), "Work", Placement::At(1)).await.unwrap();
+    db.move_items("Work", &select("2"), "Work", Placement::Bottom).await.unwrap();
+    assert_eq!(descriptions(db.get_list("Work").await.unwrap()), vec!["w", "a", "b", "d"]);
+    assert!(matches!(db.move_items("Work", &select("2-9"), "Inbox", Placement::Top).await, Err(TodoError::ItemNotFound(_))));
+    assert!(db.get_list("Someday").await.is_err());
+    db.move_items("Work", &select("1"), "Someday", Placement::Top).await.unwrap();
+    assert_eq!(descriptions(db.get_list("Someday").await.unwrap()), vec!["w"]);
+    db.undo().await.unwrap();
+    assert!(db.get_list("Someday").await.is_err());
+
+    db.undo().await.unwrap();
+    assert_eq!(descriptions(db.get_list("Work").await.unwrap()), vec!["w", "d", "a", "b"]);
+}
+
+#[tokio::test]
+async fn test_bulk_updates() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Home").await.unwrap();
+    for description in ["Buy milk #errands", "Call Bob", "Post letter #errands"] {
+        db.add_item("Home", Item::from_input(description)).await.unwrap();
+    }
+    db.update_item_status("Home", "2", true).await.unwrap();
+    let select = |input: &str| Selection::parse(input, &chrono::Local::now()).unwrap();
+
+    let (results, _) = db.update_items_status("Home", &select("all"), true, false).await.unwrap();
+    assert_eq!(results.iter().map(|(_, changed)| *changed).collect::<Vec<_>>(), vec![true, false, true]);
+
+    // One change, one undo step.
+    db.undo().await.unwrap();
+    assert_eq!(db.select_items("Home", &select("done")).await.unwrap().len(), 1);
+
+    let removed = db.remove_items("Home", &select("#errands")).await.unwrap();
+    assert_eq!(removed.len(), 2);
+    assert_eq!(db.get_list("Home").await.unwrap().items.len(), 1);
+    assert_eq!(db.redo().await.unwrap(), None);
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("remove 2 items from 'Home'"));
+    assert!(db.remove_items("Home", &select("open and #missing")).await.unwrap().is_empty());
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("mark 'Call Bob' in 'Home' as completed"));
+}
+
+#[tokio::test]
+async fn test_subtasks() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Home").await.unwrap();
+    let trip = Item::new("Plan trip");
+    let hotel = Item { parent: Some(trip.id.clone()), ..Item::new("Book hotel") };
+    let visa = Item { parent: Some(hotel.id.clone()), ..Item::new("Get visa") };
+    for item in [trip.clone(), Item::new("Call Bob"), hotel.clone(), visa.clone()] {
+        db.add_item("Home", item).await.unwrap();
+    }
+    let select = |input: &str| Selection::parse(input, &chrono::Local::now()).unwrap();
+    let descriptions = |items: Vec<Item>| items.into_iter().map(|item| item.description).collect::<Vec<_>>();
+
+    assert_eq!(db.get_item("Home", "1.1.1").await.unwrap().id, visa.id);
+    assert_eq!(db.get_item("Home", "2").await.unwrap().description, "Call Bob");
+
+    let (results, _) = db.update_items_status("Home", &select("1"), true, true).await.unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("mark 3 items in 'Home' as completed"));
+    db.update_items_status("Home", &select("1"), true, false).await.unwrap();
+    assert_eq!(db.select_items("Home", &select("done")).await.unwrap().len(), 1);
+
+    // Removing an item takes its subtasks along, and restoring brings them back.
+    assert_eq!(descriptions(db.remove_items("Home", &select("1.1")).await.unwrap()), vec!["Book hotel", "Get visa"]);
+    assert_eq!(db.restore("Home", Some("1")).await.unwrap(), "'Book hotel' and 1 subtasks to 'Home'");
+    assert_eq!(db.get_item("Home", "1.1.1").await.unwrap().id, visa.id);
+
+    // Moving a subtree keeps it nested; moved to the top of another list, its
+    // root becomes a top-level item. Copies are nested under the copies.
+    let moved = db.move_items("Home", &select("1.1"), "Trip", Placement::Top).await.unwrap();
+    assert_eq!(moved.iter().map(|(_, path)| path.as_str()).collect::<Vec<_>>(), vec!["1", "1.1"]);
+    let hotel_moved = db.get_item("Trip", "1").await.unwrap();
+    assert_eq!((hotel_moved.id, hotel_moved.parent), (hotel.id.clone(), None));
+    assert_eq!(db.get_item("Trip", "1.1").await.unwrap().parent.as_deref(), Some(hotel.id.as_str()));
+    let copied = db.copy_items("Trip", &select("1"), "Home", Placement::Bottom).await.unwrap();
+    assert_eq!(copied[1].0.parent.as_deref(), Some(copied[0].0.id.as_str()));
+    assert_eq!(copied[1].1, "3.1");
+
+    // Reordering a subtask moves it among its siblings.
+    db.add_item("Home", Item { parent: Some(trip.id.clone()), ..Item::new("Pack") }).await.unwrap();
+    db.add_item("Home", Item { parent: Some(trip.id.clone()), ..Item::new("Buy sunscreen") }).await.unwrap();
+    let reordered = db.move_items("Home", &select("1.2"), "Home", Placement::At(1)).await.unwrap();
+    assert_eq!(reordered[0].1, "1.1");
+    let list = db.get_list("Home").await.unwrap();
+    let outline: Vec<String> = tree::walk(&list.items).iter().map(|node| format!("{} {}", node.reference(), node.item.description)).collect();
+    assert_eq!(outline, vec!["1 Plan trip", "1.1 Buy sunscreen", "1.2 Pack", "2 Call Bob", "3 Book hotel", "3.1 Get visa"]);
+}
+
+#[tokio::test]
+async fn test_recurring_items() {
+    let (db, _dir) = test_database().await;
+    db.create_list("Home").await.unwrap();
+    let due = Utc::now() + chrono::Duration::days(10);
+    let bins = Item { due: Some(due), recurrence: Some("RRULE:FREQ=DAILY;COUNT=2".parse().unwrap()), ..Item::new("Take out bins") };
+    db.add_item("Home", bins.clone()).await.unwrap();
+    db.add_item("Home", Item::new("Call Bob")).await.unwrap();
+
+    db.update_item_status("Home", &bins.id, true).await.unwrap();
+    let list = db.get_list("Home").await.unwrap();
+    let descriptions: Vec<(&str, bool)> = list.items.iter().map(|item| (item.description.as_str(), item.completed)).collect();
+    assert_eq!(descriptions, vec![("Take out bins", true), ("Take out bins", false), ("Call Bob", false)]);
+    let (done, next) = (&list.items[0], &list.items[1]);
+    assert_eq!(done.recurrence, None);
+    let local = |date: Option<chrono::DateTime<Utc>>| date.unwrap().with_timezone(&chrono::Local);
+    assert_eq!(local(next.due).date_naive(), local(Some(due)).date_naive().succ_opt().unwrap());
+    assert_eq!(local(next.due).time(), local(Some(due)).time());
+    assert_eq!(next.recurrence.as_ref().map(ToString::to_string).as_deref(), Some("FREQ=DAILY;COUNT=1"));
+
+    // Completing and spawning is one step; the last occurrence spawns nothing.
+    assert_eq!(db.undo().await.unwrap().as_deref(), Some("mark 'Take out bins' in 'Home' as completed"));
+    assert_eq!(db.get_list("Home").await.unwrap().items.len(), 2);
+    db.redo().await.unwrap();
+    let next_id = db.get_list("Home").await.unwrap().items[1].id.clone();
+    let (_, spawned) = db.update_items_status("Home", &Selection::item(&next_id), true, false).await.unwrap();
+    assert!(spawned.is_empty());
+}
+
+#[tokio::test]
+async fn test_timestamps_and_history() {
+    let (db, dir) = test_database().await;
+    db.create_list("Home").await.unwrap();
+    let list = db.get_list("Home").await.unwrap();
+    assert!(list.created_at.is_some());
+    assert_eq!(list.updated_at, list.created_at);
+
+    let milk = Item::new("Buy milk");
+    db.add_item("Home", milk.clone()).await.unwrap();
+    // Stamped in the same write as the change, so the backup of the file
+    // is the version from before it.
+    let before_bob = std::fs::read_to_string(dir.path().join("local_db.json")).unwrap();
+    db.add_item("Home", Item::new("Call Bob")).await.unwrap();
+    assert_eq!(std::fs::read_to_string(dir.path().join("local_db.json.bak")).unwrap(), before_bob);
+    let added = db.get_item("Home", &milk.id).await.unwrap();
+    assert_eq!(added.created_at, milk.created_at);
+    assert!(added.updated_at.is_some());
+    assert_eq!(added.completed_at, None);
+
+    db.update_item_status("Home", &milk.id, true).await.unwrap();
+    let done = db.get_item("Home", &milk.id).await.unwrap();
+    assert!(done.completed_at.is_some());
+    assert!(done.updated_at > added.updated_at);
+    assert_eq!(db.get_list("Home").await.unwrap().updated_at, done.updated_at);
+    // Untouched items keep their timestamps.
+    let bob = db.get_item("Home", "2").await.unwrap();
+    assert!(bob.updated_at < done.updated_at);
+
+    // Undoing the change takes its timestamps back too.
+    db.undo().await.unwrap();
+    assert_eq!(db.get_item("Home", &milk.id).await.unwrap(), added);
+    db.redo().await.unwrap();
+    db.move_items("Home", &Selection::item(&milk.id), "Done", Placement::Bottom).await.unwrap();
+    db.update_item_status("Done", &milk.id, false).await.unwrap();
+    let reopened = db.get_item("Done", &milk.id).await.unwrap();
+    assert_eq!(reopened.completed_at, None);
+
+    let (item, events) = db.item_history("Done", "1").await.unwrap();
+    assert_eq!(item.id, milk.id);
+    let changes: Vec<String> = events.iter().map(|event| event.changes.join(", ")).collect();
+    // Undo and redo are changes of their own.
+    assert_eq!(changes, vec!["added to 'Home'", "completed", "reopened", "completed", "moved from 'Home' to 'Done'", "reopened"]);
+    let (_, events) = db.list_history("Home").await.unwrap();
+    assert_eq!(events.len(), 7);
+}
+
+#[tokio::test]
+async fn test_history_outlives_the_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = crate::storage::JsonStorage::open(&dir.path().join("local_db.json")).await.unwrap();
+    let mut db = Database::with_storage(Box::new(storage), dir.path().to_path_buf());
+    db.journal = Journal::new(&dir.path().join("journal.json"), 1);
+    db.create_list("Home").await.unwrap();
+    let milk = Item::new("Buy milk");
+    db.add_item("Home", milk.clone()).await.unwrap();
+    db.update_item_status("Home", &milk.id, true).await.unwrap();
+    db.undo().await.unwrap();
+    assert_eq!(db.undo().await.unwrap(), None);
+
+    let (_, events) = db.item_history("Home", &milk.id).await.unwrap();
+    let changes: Vec<(&str, String)> = events.iter().map(|event| (event.action.as_str(), event.changes.join(", "))).collect();
+    assert_eq!(changes, vec![
+        ("add 'Buy milk' to 'Home'", "added to 'Home'".to_string()),
+        ("mark 'Buy milk' in 'Home' as completed", "completed".to_string()),
+        ("undo mark 'Buy milk' in 'Home' as completed", "reopened".to_string()),
+    ]);
+    assert_eq!(db.list_history("Home").await.unwrap().1[0].changes, vec!["created".to_string()]);
+}
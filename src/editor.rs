@@ -0,0 +1,63 @@
+use std::process::Command;
+use uuid::Uuid;
+use crate::error::{TodoError, TodoResult};
+
+/// The user's editor: `VISUAL`, then `EDITOR`, then a platform default.
+fn command() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() })
+}
+
+/// Opens `text` in the user's editor and returns what was saved.
+pub fn edit(text: &str, extension: &str) -> TodoResult<String> {
+    edit_with(&command(), text, extension)
+}
+
+/// Opens `text` in a temporary file with `editor`, a command line such as
+/// `code --wait`, and reads the file back. Like git, the command runs
+/// through the shell so that it may quote its arguments.
+fn edit_with(editor: &str, text: &str, extension: &str) -> TodoResult<String> {
+    if editor.trim().is_empty() {
+        return Err(TodoError::ConfigError("EDITOR is empty".to_string()));
+    }
+    let path = std::env::temp_dir().join(format!("todo-{}.{}", Uuid::new_v4().simple(), extension));
+    std::fs::write(&path, text)?;
+
+    let status = shell_command(editor).arg(&path).status();
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    match status {
+        Ok(status) if status.success() => Ok(edited?),
+        Ok(status) => Err(TodoError::InvalidEdit(format!("'{}' exited with {}, nothing was changed", editor, status))),
+        Err(e) => Err(TodoError::ConfigError(format!("Could not run editor '{}': {}", editor, e))),
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(editor: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(format!("{} \"$@\"", editor)).arg(editor);
+    command
+}
+
+#[cfg(not(unix))]
+fn shell_command(editor: &str) -> Command {
+    let mut words = editor.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or_default());
+    command.args(words);
+    command
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_with() {
+        assert_eq!(edit_with("sed -i s/open/done/", "- [ ] open\n", "md").unwrap(), "- [ ] done\n");
+        assert!(matches!(edit_with("false", "text", "md"), Err(TodoError::InvalidEdit(_))));
+        assert_eq!(edit_with("sed -i 's/a b/c/'", "a b", "txt").unwrap(), "c");
+        assert!(matches!(edit_with(" ", "text", "md"), Err(TodoError::ConfigError(_))));
+    }
+}
@@ -11,9 +11,39 @@ pub enum TodoError {
     #[error("Item not found: {0}")]
     ItemNotFound(String),
 
+    #[error("Ambiguous item reference: {0}")]
+    AmbiguousItem(String),
+
     #[error("List not found: {0}")]
     ListNotFound(String),
 
+    #[error("List already exists: {0}")]
+    ListExists(String),
+
+    #[error("In the trash: {0}")]
+    InTrash(String),
+
+    #[error("Not in the trash: {0}")]
+    NotInTrash(String),
+
+    #[error("Confirmation required: {0}")]
+    ConfirmationRequired(String),
+
+    #[error("Invalid date: {0}")]
+    InvalidDate(String),
+
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+
+    #[error("Invalid recurrence: {0}")]
+    InvalidRecurrence(String),
+
+    #[error("Invalid edit: {0}")]
+    InvalidEdit(String),
+
+    #[error("Sync conflict: {0}")]
+    SyncConflict(String),
+
     #[error("Authentication error: {0}")]
     AuthError(String),
 
@@ -23,8 +53,20 @@ pub enum TodoError {
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
+    #[error("Remote error: {0}")]
+    RemoteError(String),
+
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] reqwest::Error),
+
     #[error("MongoDB error: {0}")]
     MongoError(#[from] mongodb::error::Error),
+
+    #[error("SQLite error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
 }
 
 pub type TodoResult<T> = Result<T, TodoError>;
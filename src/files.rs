@@ -0,0 +1,80 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Replaces the file at `path` with `data` so that readers and crashes only
+/// ever see the old or the new content: the data is written and synced to a
+/// temporary file next to `path`, which is then renamed over it.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, data, &mut OpenOptions::new())
+}
+
+/// Like `write_atomic`, but the file is only readable by the current user,
+/// for credentials.
+pub fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    write_atomic_with(path, data, &mut options)
+}
+
+fn write_atomic_with(path: &Path, data: &[u8], options: &mut OpenOptions) -> io::Result<()> {
+    let tmp = sibling(path, "tmp");
+    // A leftover from a crash would keep its old permissions.
+    let _ = std::fs::remove_file(&tmp);
+    let mut file = options.write(true).create(true).truncate(true).open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)
+}
+
+/// An exclusive advisory lock on `<path>.lock`, released when dropped. Every
+/// process that reads or writes `path` takes it first.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Blocks until no other process holds the lock for `path`.
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(sibling(path, "lock"))?;
+        file.lock()?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// `path` with `extension` appended, e.g. `local_db.json.bak`.
+pub fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+
+        write_atomic(&path, b"old").unwrap();
+        let _lock = FileLock::acquire(&path).unwrap();
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!sibling(&path, "tmp").exists());
+        assert!(sibling(&path, "lock").exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let secret = dir.path().join("token.json");
+            write_private(&secret, b"secret").unwrap();
+            assert_eq!(std::fs::metadata(&secret).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! The expression language of `todo show --where`, e.g.
+//! `status:open and (tag:work or priority>=high) and due<7d and text~"deploy"`.
+//!
+//! Conditions are `field op value` with the operators `:` and `=` (equals),
+//! `!=`, `<`, `<=`, `>`, `>=` and `~` (contains). They combine with `and`,
+//! `or`, `not` and parentheses; `and` binds tighter than `or` and may be left
+//! out. A bare word or quoted string matches descriptions containing it, and
+//! a bare `#tag` matches items with that tag.
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use crate::dates;
+use crate::error::{TodoError, TodoResult};
+use crate::models::{Item, List, Priority};
+use crate::tags;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cond(Condition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateField {
+    Due,
+    Start,
+    Created,
+    Updated,
+    Completed,
+}
+
+/// A date to compare against. Values without a time of day, like `today`
+/// or `7d`, compare by calendar day in the local time zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateBound {
+    Exact(DateTime<Utc>),
+    Day(NaiveDate),
+    /// `none` or `any`: whether the date is set at all.
+    Set(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Open,
+    Done,
+    Overdue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Status(Status),
+    Priority(Op, Priority),
+    Date(DateField, Op, DateBound),
+    Tag(String),
+    /// Lowercased text searched for in descriptions.
+    Text(String),
+    List(String),
+    /// `cond` with `!=` instead of `:`.
+    Negated(Box<Condition>),
+}
+
+impl Expr {
+    /// Parses `input`, resolving relative dates against `now`.
+    pub fn parse(input: &str, now: &DateTime<Local>) -> TodoResult<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, position: 0, now };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(expr),
+            Some(token) => Err(invalid(format!("unexpected {}", token.describe()))),
+        }
+    }
+
+    pub fn matches(&self, list: &List, item: &Item, now: DateTime<Utc>) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(list, item, now) && right.matches(list, item, now),
+            Expr::Or(left, right) => left.matches(list, item, now) || right.matches(list, item, now),
+            Expr::Not(expr) => !expr.matches(list, item, now),
+            Expr::Cond(condition) => condition.matches(list, item, now),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, list: &List, item: &Item, now: DateTime<Utc>) -> bool {
+        match self {
+            Condition::Status(Status::Open) => !item.completed,
+            Condition::Status(Status::Done) => item.completed,
+            Condition::Status(Status::Overdue) => item.is_overdue(now),
+            Condition::Priority(op, priority) => compare(op, &item.priority, priority),
+            Condition::Date(field, op, bound) => {
+                let date = match field {
+                    DateField::Due => item.due,
+                    DateField::Start => item.start,
+                    DateField::Created => item.created_at,
+                    DateField::Updated => item.updated_at,
+                    DateField::Completed => item.completed_at,
+                };
+                match (bound, date) {
+                    (DateBound::Set(set), date) => date.is_some() == *set,
+                    (_, None) => false,
+                    (DateBound::Exact(bound), Some(date)) => compare(op, &date, bound),
+                    (DateBound::Day(day), Some(date)) => compare(op, &date.with_timezone(&Local).date_naive(), day),
+                }
+            }
+            Condition::Tag(tag) => item.tags.contains(tag),
+            Condition::Text(text) => item.description.to_lowercase().contains(text),
+            Condition::List(name) => list.name.to_lowercase() == *name,
+            Condition::Negated(condition) => !condition.matches(list, item, now),
+        }
+    }
+}
+
+fn compare<T: PartialOrd>(op: &Op, value: &T, bound: &T) -> bool {
+    match op {
+        Op::Eq | Op::Contains => value == bound,
+        Op::Ne => value != bound,
+        Op::Lt => value < bound,
+        Op::Le => value <= bound,
+        Op::Gt => value > bound,
+        Op::Ge => value >= bound,
+    }
+}
+
+fn invalid(message: String) -> TodoError {
+    TodoError::InvalidFilter(message)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    /// `field`, operator and value of a condition.
+    Cond(String, Op, String),
+    /// A bare word or quoted string.
+    Text(String),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Open => "'('".to_string(),
+            Token::Close => "')'".to_string(),
+            Token::And => "'and'".to_string(),
+            Token::Or => "'or'".to_string(),
+            Token::Not => "'not'".to_string(),
+            Token::Cond(field, _, value) => format!("condition on {} '{}'", field, value),
+            Token::Text(text) => format!("'{}'", text),
+        }
+    }
+}
+
+const OPERATORS: [(&str, Op); 8] = [
+    ("!=", Op::Ne),
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    (":", Op::Eq),
+    ("=", Op::Eq),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("~", Op::Contains),
+];
+
+fn tokenize(input: &str) -> TodoResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '(' => {
+                tokens.push(Token::Open);
+                rest = &rest[1..];
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                rest = &rest[1..];
+            }
+            '"' => {
+                let (text, after) = quoted(rest)?;
+                tokens.push(Token::Text(text));
+                rest = after;
+            }
+            _ => {
+                let field_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+                let operator = OPERATORS.iter().find(|(symbol, _)| rest[field_len..].starts_with(symbol));
+                match operator {
+                    Some((symbol, op)) if field_len > 0 => {
+                        let field = rest[..field_len].to_lowercase();
+                        let after = &rest[field_len + symbol.len()..];
+                        let (value, after) = if after.starts_with('"') { quoted(after)? } else { bare(after) };
+                        if value.is_empty() {
+                            return Err(invalid(format!("missing value after '{}{}'", field, symbol)));
+                        }
+                        tokens.push(Token::Cond(field, *op, value));
+                        rest = after;
+                    }
+                    _ => {
+                        let (word, after) = bare(rest);
+                        tokens.push(match word.to_lowercase().as_str() {
+                            "and" | "&&" => Token::And,
+                            "or" | "||" => Token::Or,
+                            "not" | "!" => Token::Not,
+                            _ => Token::Text(word),
+                        });
+                        rest = after;
+                    }
+                }
+            }
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Splits a quoted string off the front of `input`, which starts with `"`.
+fn quoted(input: &str) -> TodoResult<(String, &str)> {
+    let end = input[1..].find('"').ok_or_else(|| invalid("unterminated string".to_string()))?;
+    Ok((input[1..=end].to_string(), &input[end + 2..]))
+}
+
+/// Splits a word off the front of `input`, up to whitespace or a parenthesis.
+fn bare(input: &str) -> (String, &str) {
+    let end = input.find(|c: char| c.is_whitespace() || c == '(' || c == ')').unwrap_or(input.len());
+    (input[..end].to_string(), &input[end..])
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    now: &'a DateTime<Local>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> TodoResult<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> TodoResult<Expr> {
+        let mut expr = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.position += 1,
+                Some(Token::Or | Token::Close) | None => return Ok(expr),
+                Some(_) => {}
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> TodoResult<Expr> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(invalid("missing ')'".to_string())),
+                }
+            }
+            Some(Token::Cond(field, op, value)) => Ok(Expr::Cond(self.condition(&field, op, &value)?)),
+            Some(Token::Text(text)) => Ok(Expr::Cond(match tags::normalize(&text) {
+                Some(tag) if text.starts_with(['#', '+', '@']) => Condition::Tag(tag),
+                _ => Condition::Text(text.to_lowercase()),
+            })),
+            Some(token) => Err(invalid(format!("unexpected {}", token.describe()))),
+            None => Err(invalid("unexpected end of filter".to_string())),
+        }
+    }
+
+    fn condition(&self, field: &str, op: Op, value: &str) -> TodoResult<Condition> {
+        let equality = |condition: Condition| match op {
+            Op::Eq => Ok(condition),
+            Op::Ne => Ok(Condition::Negated(Box::new(condition))),
+            _ => Err(invalid(format!("{} only supports ':' and '!='", field))),
+        };
+        match field {
+            "status" | "is" => equality(Condition::Status(match value.to_lowercase().as_str() {
+                "open" | "todo" | "incomplete" => Status::Open,
+                "done" | "completed" => Status::Done,
+                "overdue" => Status::Overdue,
+                _ => return Err(invalid(format!("unknown status '{}', expected open, done or overdue", value))),
+            })),
+            "priority" | "pri" => {
+                let priority = Priority::from_str(value, true)
+                    .map_err(|_| invalid(format!("unknown priority '{}'", value)))?;
+                match op {
+                    Op::Contains => Err(invalid("priority does not support '~'".to_string())),
+                    op => Ok(Condition::Priority(op, priority)),
+                }
+            }
+            "due" | "start" | "created" | "updated" | "completed" => {
+                let field = match field {
+                    "due" => DateField::Due,
+                    "start" => DateField::Start,
+                    "created" => DateField::Created,
+                    "updated" => DateField::Updated,
+                    _ => DateField::Completed,
+                };
+                let bound = self.date_bound(value)?;
+                match (op, bound) {
+                    (Op::Contains, _) => Err(invalid("dates do not support '~'".to_string())),
+                    (Op::Eq | Op::Ne, DateBound::Set(set)) => Ok(Condition::Date(field, op, DateBound::Set(set == (op == Op::Eq)))),
+                    (_, DateBound::Set(_)) => Err(invalid(format!("'{}' can only be compared with ':' or '!='", value))),
+                    (op, bound) => Ok(Condition::Date(field, op, bound)),
+                }
+            }
+            "tag" => equality(Condition::Tag(tags::normalize(value).ok_or_else(|| invalid(format!("'{}' is not a valid tag", value)))?)),
+            "text" | "description" => match op {
+                Op::Eq | Op::Contains => Ok(Condition::Text(value.to_lowercase())),
+                Op::Ne => Ok(Condition::Negated(Box::new(Condition::Text(value.to_lowercase())))),
+                _ => Err(invalid("text only supports ':', '~' and '!='".to_string())),
+            },
+            "list" => equality(Condition::List(value.to_lowercase())),
+            _ => Err(invalid(format!("unknown field '{}'", field))),
+        }
+    }
+
+    /// Parses a date value; a bare `7d` means seven days from now.
+    fn date_bound(&self, value: &str) -> TodoResult<DateBound> {
+        match value.to_lowercase().as_str() {
+            "none" => return Ok(DateBound::Set(false)),
+            "any" => return Ok(DateBound::Set(true)),
+            _ => {}
+        }
+        let value = if value.starts_with(|c: char| c.is_ascii_digit()) && value.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            format!("+{}", value)
+        } else {
+            value.to_string()
+        };
+        // A value that ignores the default time has a time of its own.
+        let parse = |default_time| dates::parse_datetime(&value, self.now, default_time)
+            .map_err(|_| invalid(format!("invalid date '{}'", value)));
+        let morning = parse(dates::start_of_day())?;
+        let evening = parse(dates::end_of_day())?;
+        Ok(if morning == evening {
+            DateBound::Exact(morning.with_timezone(&Utc))
+        } else {
+            DateBound::Day(morning.date_naive())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn item(description: &str, completed: bool, priority: Priority, due_in_days: Option<i64>, tags: &[&str]) -> Item {
+        let mut item = Item::new(description);
+        item.completed = completed;
+        item.completed_at = completed.then(|| Utc::now() - Duration::days(2));
+        item.priority = priority;
+        item.due = due_in_days.map(|days| Utc::now() + Duration::days(days));
+        item.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        item
+    }
+
+    fn matching(filter: &str) -> Vec<String> {
+        let list = List {
+            name: "Work".to_string(),
+            items: vec![
+                item("Deploy release", false, Priority::None, Some(2), &["work"]),
+                item("Fix login bug", false, Priority::High, Some(30), &[]),
+                item("Write deploy notes", true, Priority::Low, None, &["work", "docs"]),
+                item("Renew passport", false, Priority::Urgent, Some(-1), &["home"]),
+            ],
+            trashed: None,
+            created_at: None,
+            updated_at: None,
+        };
+        let expr = Expr::parse(filter, &Local::now()).unwrap();
+        list.items.iter()
+            .filter(|item| expr.matches(&list, item, Utc::now()))
+            .map(|item| item.description.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            matching(r#"status:open and (tag:work or priority>=high) and due<7d and text~"deploy""#),
+            vec!["Deploy release"]
+        );
+        assert_eq!(matching("status:open (tag:work or priority>=high)"), vec!["Deploy release", "Fix login bug", "Renew passport"]);
+        assert_eq!(matching("priority>=high or status:done"), vec!["Fix login bug", "Write deploy notes", "Renew passport"]);
+        assert_eq!(matching("not tag:work and due:any"), vec!["Fix login bug", "Renew passport"]);
+        assert_eq!(matching("due:none"), vec!["Write deploy notes"]);
+        assert_eq!(matching("due<=today"), vec!["Renew passport"]);
+        assert_eq!(matching("is:overdue"), vec!["Renew passport"]);
+        assert_eq!(matching("deploy priority!=none"), vec!["Write deploy notes"]);
+        assert_eq!(matching("list:work and +home"), vec!["Renew passport"]);
+        assert_eq!(matching("list!=work"), Vec::<String>::new());
+        assert_eq!(matching("pri>3"), vec!["Renew passport"]);
+        assert_eq!(matching("completed>=-1w"), vec!["Write deploy notes"]);
+        assert_eq!(matching("completed<-1w or updated:any"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let now = Local::now();
+        for bad in ["status:maybe", "(tag:work", "tag:work)", "owner:me", "priority~high", "due>none", "text:\"open", "and", "due<", "due<someday"] {
+            assert!(matches!(Expr::parse(bad, &now), Err(TodoError::InvalidFilter(_))), "{} should not parse", bad);
+        }
+        assert_eq!(
+            Expr::parse("a or b c", &now).unwrap(),
+            Expr::Or(
+                Box::new(Expr::Cond(Condition::Text("a".to_string()))),
+                Box::new(Expr::And(
+                    Box::new(Expr::Cond(Condition::Text("b".to_string()))),
+                    Box::new(Expr::Cond(Condition::Text("c".to_string()))),
+                )),
+            )
+        );
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::auth::TokenManager;
+use crate::dates;
+use crate::db::Database;
+use crate::error::{TodoError, TodoResult};
+use crate::files;
+use crate::models::{Item, List};
+use crate::sync::{Remote, SyncTarget};
+
+const DEFAULT_API_URL: &str = "https://tasks.googleapis.com/tasks/v1";
+
+/// Maps local item IDs to Google task IDs between runs.
+const ID_MAP_FILE: &str = "google_task_ids.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Task {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default)]
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(default, skip_serializing)]
+    position: Option<String>,
+    /// When the task last changed, as RFC 3339.
+    #[serde(default, skip_serializing)]
+    updated: Option<String>,
+    /// When the task was completed, as RFC 3339.
+    #[serde(default, skip_serializing)]
+    completed: Option<String>,
+    #[serde(default, skip_serializing)]
+    deleted: bool,
+}
+
+impl Task {
+    fn is_completed(&self) -> bool {
+        self.status.as_deref() == Some("completed")
+    }
+
+    /// Whether applying `other` would change anything Google stores.
+    fn differs_from(&self, other: &Task) -> bool {
+        self.title != other.title
+            || self.is_completed() != other.is_completed()
+            || self.due_date() != other.due_date()
+            || self.notes() != other.notes()
+    }
+
+    /// Google leaves `notes` out when a task has none.
+    fn notes(&self) -> &str {
+        self.notes.as_deref().unwrap_or_default()
+    }
+
+    fn time(value: Option<&str>) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(value?).ok().map(|time| time.with_timezone(&Utc))
+    }
+
+    /// Google Tasks only keeps the date part of `due`.
+    fn due_date(&self) -> Option<NaiveDate> {
+        let due = self.due.as_deref()?;
+        DateTime::parse_from_rfc3339(due).ok().map(|due| due.date_naive())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TaskList {
+    id: String,
+    title: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Page<T> {
+    #[serde(default = "Vec::new")]
+    items: Vec<T>,
+    next_page_token: Option<String>,
+}
+
+/// A thin client for the parts of the Google Tasks REST API used by sync.
+pub struct GoogleTasksClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: Mutex<String>,
+    /// Replaces a token Google rejects; without it that is an error.
+    tokens: Option<TokenManager>,
+}
+
+impl GoogleTasksClient {
+    pub fn new(base_url: &str, token: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: Mutex::new(token.to_string()),
+            tokens: None,
+        }
+    }
+
+    pub fn with_tokens(mut self, tokens: TokenManager) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Uses `GOOGLE_TASKS_API_URL` if set, which lets tests point the client at a mock server.
+    pub fn from_env(token: &str) -> Self {
+        let base_url = std::env::var("GOOGLE_TASKS_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.to_string());
+        Self::new(&base_url, token)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    fn token(&self) -> String {
+        self.token.lock().unwrap().clone()
+    }
+
+    /// Sends `request`. If Google rejects the token, which it can do before
+    /// the token was due to expire, it is refreshed and the request retried
+    /// once.
+    async fn send(&self, request: RequestBuilder) -> TodoResult<reqwest::Response> {
+        let retry = request.try_clone();
+        let mut response = request.bearer_auth(self.token()).send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            if let (Some(tokens), Some(retry)) = (&self.tokens, retry) {
+                *self.token.lock().unwrap() = tokens.refresh().await?;
+                response = retry.bearer_auth(self.token()).send().await?;
+            }
+        }
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            StatusCode::UNAUTHORIZED => Err(TodoError::AuthError("Google rejected the access token, run `todo login` again".to_string())),
+            status => {
+                let body = response.text().await.unwrap_or_default();
+                Err(TodoError::RemoteError(format!("Google Tasks API returned {}: {}", status, body)))
+            }
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> TodoResult<T> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    async fn list_all<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> TodoResult<Vec<T>> {
+        let mut items = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.http.get(self.url(path)).query(query).query(&[("maxResults", "100")]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+            let page: Page<T> = self.send_json(request).await?;
+            items.extend(page.items);
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(items),
+            }
+        }
+    }
+
+    async fn list_tasklists(&self) -> TodoResult<Vec<TaskList>> {
+        self.list_all("users/@me/lists", &[]).await
+    }
+
+    async fn insert_tasklist(&self, title: &str) -> TodoResult<TaskList> {
+        self.send_json(self.http.post(self.url("users/@me/lists")).json(&serde_json::json!({ "title": title }))).await
+    }
+
+    async fn delete_tasklist(&self, tasklist: &str) -> TodoResult<()> {
+        self.send(self.http.delete(self.url(&format!("users/@me/lists/{}", tasklist)))).await?;
+        Ok(())
+    }
+
+    async fn list_tasks(&self, tasklist: &str) -> TodoResult<Vec<Task>> {
+        let query = [("showCompleted", "true"), ("showHidden", "true")];
+        self.list_all(&format!("lists/{}/tasks", tasklist), &query).await
+    }
+
+    async fn insert_task(&self, tasklist: &str, task: &Task, previous: Option<&str>) -> TodoResult<Task> {
+        let mut request = self.http.post(self.url(&format!("lists/{}/tasks", tasklist))).json(task);
+        if let Some(previous) = previous {
+            request = request.query(&[("previous", previous)]);
+        }
+        self.send_json(request).await
+    }
+
+    async fn update_task(&self, tasklist: &str, task_id: &str, task: &Task) -> TodoResult<Task> {
+        self.send_json(self.http.patch(self.url(&format!("lists/{}/tasks/{}", tasklist, task_id))).json(task)).await
+    }
+
+    async fn delete_task(&self, tasklist: &str, task_id: &str) -> TodoResult<()> {
+        self.send(self.http.delete(self.url(&format!("lists/{}/tasks/{}", tasklist, task_id)))).await?;
+        Ok(())
+    }
+}
+
+/// A tasklist as last fetched: its ID and its tasks by Google ID.
+struct RemoteList {
+    id: String,
+    tasks: HashMap<String, Task>,
+}
+
+/// Syncs lists with Google Tasks tasklists of the same title, and items with
+/// tasks (title, completion and due date).
+pub struct GoogleRemote {
+    client: GoogleTasksClient,
+    ids_path: PathBuf,
+    /// Local item ID to Google task ID.
+    ids: HashMap<String, String>,
+    fetched: HashMap<String, RemoteList>,
+}
+
+impl GoogleRemote {
+    pub async fn open(client: GoogleTasksClient, ids_path: PathBuf) -> TodoResult<Self> {
+        let ids = match tokio::fs::read_to_string(&ids_path).await {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            client,
+            ids_path,
+            ids,
+            fetched: HashMap::new(),
+        })
+    }
+
+    /// Connects with the token saved by `todo login`.
+    pub async fn for_database(db: &Database) -> TodoResult<Self> {
+        let tokens = TokenManager::from_env()?;
+        let client = GoogleTasksClient::from_env(&tokens.access_token().await?).with_tokens(tokens);
+        Self::open(client, db.state_path(ID_MAP_FILE)).await
+    }
+
+    async fn save_ids(&self) -> TodoResult<()> {
+        files::write_atomic(&self.ids_path, serde_json::to_string_pretty(&self.ids)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Remote for GoogleRemote {
+    fn name(&self) -> &str {
+        SyncTarget::Google.name()
+    }
+
+    fn describe(&self) -> String {
+        "Google Tasks".to_string()
+    }
+
+    async fn fetch(&mut self, base: &[List]) -> TodoResult<Vec<List>> {
+        let known: HashMap<&str, &Item> = base.iter().flat_map(|list| &list.items).map(|item| (item.id.as_str(), item)).collect();
+        let local_ids: HashMap<String, String> = self.ids.iter().map(|(local, google)| (google.clone(), local.clone())).collect();
+
+        self.fetched.clear();
+        let mut lists = Vec::new();
+        for tasklist in self.client.list_tasklists().await? {
+            let mut tasks: Vec<Task> = self.client.list_tasks(&tasklist.id).await?
+                .into_iter()
+                .filter(|task| !task.deleted && task.id.is_some())
+                .collect();
+            tasks.sort_by(|a, b| a.position.cmp(&b.position));
+
+            let mut items = Vec::new();
+            for task in &tasks {
+                let google_id = task.id.clone().unwrap_or_default();
+                let local_id = match local_ids.get(&google_id) {
+                    Some(local_id) => local_id.clone(),
+                    None => {
+                        let local_id = Item::generate_id();
+                        self.ids.insert(local_id.clone(), google_id);
+                        local_id
+                    }
+                };
+                items.push(to_item(task, local_id.clone(), known.get(local_id.as_str()).copied()));
+            }
+
+            self.fetched.insert(tasklist.title.clone(), RemoteList {
+                id: tasklist.id,
+                tasks: tasks.into_iter().map(|task| (task.id.clone().unwrap_or_default(), task)).collect(),
+            });
+            lists.push(List { name: tasklist.title, items, trashed: None, created_at: None, updated_at: None });
+        }
+        carry_trash(base, &mut lists);
+        Ok(lists)
+    }
+
+    async fn store(&mut self, lists: &[List]) -> TodoResult<()> {
+        // Trashed lists are left in `fetched` and deleted below.
+        for list in lists.iter().filter(|list| list.trashed.is_none()) {
+            let (tasklist, mut remaining) = match self.fetched.remove(&list.name) {
+                Some(remote) => (remote.id, remote.tasks),
+                None => (self.client.insert_tasklist(&list.name).await?.id, HashMap::new()),
+            };
+
+            let mut previous: Option<String> = None;
+            for item in list.items.iter().filter(|item| item.trashed.is_none()) {
+                let task = to_task(item);
+                let existing = self.ids.get(&item.id).and_then(|google_id| remaining.remove(google_id));
+                let google_id = match existing {
+                    Some(existing) => {
+                        let google_id = existing.id.clone().unwrap_or_default();
+                        if existing.differs_from(&task) {
+                            self.client.update_task(&tasklist, &google_id, &task).await?;
+                        }
+                        google_id
+                    }
+                    None => {
+                        let created = self.client.insert_task(&tasklist, &task, previous.as_deref()).await?;
+                        let google_id = created.id.unwrap_or_default();
+                        self.ids.insert(item.id.clone(), google_id.clone());
+                        google_id
+                    }
+                };
+                previous = Some(google_id);
+            }
+
+            for google_id in remaining.keys() {
+                self.client.delete_task(&tasklist, google_id).await?;
+            }
+        }
+
+        for (_, remote) in self.fetched.drain() {
+            self.client.delete_tasklist(&remote.id).await?;
+        }
+
+        let live: std::collections::HashSet<&str> = lists.iter()
+            .filter(|list| list.trashed.is_none())
+            .flat_map(|list| &list.items)
+            .filter(|item| item.trashed.is_none())
+            .map(|item| item.id.as_str())
+            .collect();
+        self.ids.retain(|local_id, _| live.contains(local_id.as_str()));
+        self.save_ids().await
+    }
+}
+
+/// Google Tasks has no trash, so trashed lists and items are never stored
+/// there. Adds the trash as of the last sync to the fetched lists so that its
+/// absence does not read as a remote deletion.
+fn carry_trash(base: &[List], lists: &mut Vec<List>) {
+    for trash in base {
+        match lists.iter_mut().find(|list| list.name == trash.name) {
+            Some(list) => list.items.extend(trash.items.iter().filter(|item| item.trashed.is_some()).cloned()),
+            None if trash.trashed.is_some() => lists.push(trash.clone()),
+            None => {}
+        }
+    }
+}
+
+/// Builds the task Google should hold for `item`.
+fn to_task(item: &Item) -> Task {
+    Task {
+        title: item.description.clone(),
+        status: Some(if item.completed { "completed" } else { "needsAction" }.to_string()),
+        due: item.due.map(|due| format!("{}T00:00:00.000Z", due.with_timezone(&Local).date_naive())),
+        // Sent even when empty, so that clearing the notes clears them there.
+        notes: Some(item.notes.clone()),
+        ..Task::default()
+    }
+}
+
+/// Builds the item for a fetched task. Fields Google does not store are taken
+/// from the `known` version of the item so that syncing does not erase them,
+/// and its timestamps are Google's if the task changed there.
+fn to_item(task: &Task, id: String, known: Option<&Item>) -> Item {
+    let mut item = known.cloned().unwrap_or_else(|| Item::new(""));
+    item.id = id;
+    item.description = task.title.clone();
+    item.completed = task.is_completed();
+    item.notes = task.notes().to_string();
+    item.due = match (task.due_date(), item.due) {
+        (Some(date), Some(due)) if due.with_timezone(&Local).date_naive() == date => Some(due),
+        (Some(date), _) => date.and_time(dates::end_of_day()).and_local_timezone(Local).earliest().map(|due| due.with_timezone(&Utc)),
+        (None, _) => None,
+    };
+    if known != Some(&item) {
+        item.updated_at = Task::time(task.updated.as_deref()).or(item.updated_at);
+        item.completed_at = if item.completed { Task::time(task.completed.as_deref()).or(item.completed_at) } else { None };
+    }
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{self, ConflictStrategy};
+    use std::sync::{Arc, Mutex};
+    use oauth2::basic::BasicClient;
+    use oauth2::{AuthUrl, ClientId, ClientSecret, TokenUrl};
+    use wiremock::matchers::{any, header, method, path};
+    use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+    /// An in-memory stand-in for the Google Tasks API.
+    #[derive(Default, Clone)]
+    struct FakeTasks {
+        state: Arc<Mutex<FakeState>>,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        next_id: usize,
+        /// Tasklist ID, title and tasks in order.
+        lists: Vec<(String, String, Vec<Task>)>,
+    }
+
+    impl FakeState {
+        fn new_id(&mut self) -> String {
+            self.next_id += 1;
+            format!("g{}", self.next_id)
+        }
+
+        fn list_mut(&mut self, id: &str) -> &mut Vec<Task> {
+            &mut self.lists.iter_mut().find(|(list_id, _, _)| list_id == id).unwrap().2
+        }
+    }
+
+    impl Respond for FakeTasks {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            assert_eq!(request.headers.get("authorization").unwrap(), "Bearer test-token");
+            let mut state = self.state.lock().unwrap();
+            let segments: Vec<String> = request.url.path_segments().unwrap().map(|s| s.replace("%40", "@")).collect();
+            let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+            let page = |items: serde_json::Value| ResponseTemplate::new(200).set_body_json(serde_json::json!({ "items": items }));
+
+            match (request.method.as_str(), segments.as_slice()) {
+                ("GET", ["users", "@me", "lists"]) => page(serde_json::json!(state.lists.iter()
+                    .map(|(id, title, _)| serde_json::json!({ "id": id, "title": title }))
+                    .collect::<Vec<_>>())),
+                ("POST", ["users", "@me", "lists"]) => {
+                    let title = request.body_json::<serde_json::Value>().unwrap()["title"].as_str().unwrap().to_string();
+                    let id = state.new_id();
+                    state.lists.push((id.clone(), title.clone(), Vec::new()));
+                    ResponseTemplate::new(200).set_body_json(serde_json::json!({ "id": id, "title": title }))
+                }
+                ("DELETE", ["users", "@me", "lists", id]) => {
+                    state.lists.retain(|(list_id, _, _)| list_id != id);
+                    ResponseTemplate::new(204)
+                }
+                ("GET", ["lists", id, "tasks"]) => page(serde_json::to_value(state.list_mut(id).clone()).unwrap()),
+                ("POST", ["lists", id, "tasks"]) => {
+                    let mut task: Task = request.body_json().unwrap();
+                    task.id = Some(state.new_id());
+                    let previous = request.url.query_pairs().find(|(key, _)| key == "previous").map(|(_, value)| value.to_string());
+                    let tasks = state.list_mut(id);
+                    let index = previous.and_then(|p| tasks.iter().position(|t| t.id.as_deref() == Some(&p))).map_or(0, |i| i + 1);
+                    tasks.insert(index, task.clone());
+                    ResponseTemplate::new(200).set_body_json(task)
+                }
+                ("PATCH", ["lists", list, "tasks", id]) => {
+                    let patch: Task = request.body_json().unwrap();
+                    let task = state.list_mut(list).iter_mut().find(|t| t.id.as_deref() == Some(*id)).unwrap();
+                    *task = Task { id: task.id.clone(), ..patch };
+                    ResponseTemplate::new(200).set_body_json(task.clone())
+                }
+                ("DELETE", ["lists", list, "tasks", id]) => {
+                    state.list_mut(list).retain(|t| t.id.as_deref() != Some(*id));
+                    ResponseTemplate::new(204)
+                }
+                _ => ResponseTemplate::new(404),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_google_sync_round_trip() {
+        let server = MockServer::start().await;
+        let fake = FakeTasks::default();
+        Mock::given(any()).respond_with(fake.clone()).mount(&server).await;
+
+        let (db, dir) = crate::db::test_database().await;
+        let open_remote = || GoogleRemote::open(GoogleTasksClient::new(&server.uri(), "test-token"), dir.path().join(ID_MAP_FILE));
+
+        db.create_list("Chores").await.unwrap();
+        let mut dishes = Item::new("Dishes");
+        dishes.due = Some(Utc::now());
+        dishes.start = Some(Utc::now());
+        db.add_item("Chores", dishes.clone()).await.unwrap();
+        db.add_item("Chores", Item::new("Laundry")).await.unwrap();
+
+        // First sync creates the tasklist and tasks in order.
+        sync::sync(&db, &mut open_remote().await.unwrap(), ConflictStrategy::Ours).await.unwrap();
+        {
+            let state = fake.state.lock().unwrap();
+            assert_eq!(state.lists.len(), 1);
+            assert_eq!(state.lists[0].1, "Chores");
+            let titles: Vec<&str> = state.lists[0].2.iter().map(|t| t.title.as_str()).collect();
+            assert_eq!(titles, vec!["Dishes", "Laundry"]);
+            assert!(state.lists[0].2[0].due.is_some());
+        }
+
+        // Complete a task and add one on the Google side, edit locally.
+        {
+            let mut state = fake.state.lock().unwrap();
+            let new_id = state.new_id();
+            let tasks = &mut state.lists[0].2;
+            tasks[1].status = Some("completed".to_string());
+            tasks.push(Task { id: Some(new_id), title: "Water plants".to_string(), notes: Some("Not the cactus".to_string()), ..Task::default() });
+        }
+        db.update_item("Chores", &dishes.id, |item| {
+            item.description = "Wash dishes".to_string();
+            item.notes = "- pans\n- glasses".to_string();
+        }).await.unwrap();
+
+        sync::sync(&db, &mut open_remote().await.unwrap(), ConflictStrategy::Ask).await.unwrap();
+
+        let chores = db.get_list("Chores").await.unwrap();
+        let descriptions: Vec<&str> = chores.items.iter().map(|i| i.description.as_str()).collect();
+        assert_eq!(descriptions, vec!["Wash dishes", "Laundry", "Water plants"]);
+        assert!(chores.items[1].completed);
+        // Details Google cannot store survive the round trip.
+        assert_eq!(chores.items[0].id, dishes.id);
+        assert_eq!(chores.items[0].due, dishes.due);
+        assert_eq!(chores.items[0].start, dishes.start);
+        assert_eq!(fake.state.lock().unwrap().lists[0].2[0].title, "Wash dishes");
+        // Notes go both ways.
+        assert_eq!(chores.items[2].notes, "Not the cactus");
+        assert_eq!(fake.state.lock().unwrap().lists[0].2[0].notes.as_deref(), Some("- pans\n- glasses"));
+
+        // Trashed items and lists are removed from Google but stay in the
+        // local trash across syncs.
+        db.remove_item("Chores", "2").await.unwrap();
+        sync::sync(&db, &mut open_remote().await.unwrap(), ConflictStrategy::Ask).await.unwrap();
+        assert_eq!(fake.state.lock().unwrap().lists[0].2.len(), 2);
+
+        db.remove_list("Chores").await.unwrap();
+        for _ in 0..2 {
+            sync::sync(&db, &mut open_remote().await.unwrap(), ConflictStrategy::Ask).await.unwrap();
+            assert!(fake.state.lock().unwrap().lists.is_empty());
+        }
+        let trash = db.trash().await.unwrap();
+        assert_eq!(trash[0].name, "Chores");
+        assert!(trash[0].trashed.is_some());
+        assert_eq!(trash[0].items.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_token_is_refreshed() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).and(header("authorization", "Bearer stale"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET")).and(header("authorization", "Bearer fresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "items": [{ "id": "g1", "title": "Chores" }] })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST")).and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "fresh",
+                "token_type": "Bearer",
+                "expires_in": 3600
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let token_path = dir.path().join("token.json");
+        let oauth = BasicClient::new(
+            ClientId::new("client".to_string()),
+            Some(ClientSecret::new("secret".to_string())),
+            AuthUrl::new(format!("{}/auth", server.uri())).unwrap(),
+            Some(TokenUrl::new(format!("{}/token", server.uri())).unwrap()),
+        );
+        // Google rejects the token although it has not expired yet.
+        let token = serde_json::json!({ "access_token": "stale", "refresh_token": "refresh", "expires_at": Utc::now() + chrono::Duration::hours(1) });
+        std::fs::write(&token_path, token.to_string()).unwrap();
+
+        let client = GoogleTasksClient::new(&server.uri(), "stale").with_tokens(TokenManager::new(oauth.clone(), &token_path));
+        let tasklists = client.list_tasklists().await.unwrap();
+        assert_eq!(tasklists[0].title, "Chores");
+
+        // A refresh token that no longer works is reported once.
+        server.reset().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(401)).mount(&server).await;
+        Mock::given(method("POST")).and(path("/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({ "error": "invalid_grant" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = GoogleTasksClient::new(&server.uri(), "stale").with_tokens(TokenManager::new(oauth, &token_path));
+        assert!(matches!(client.list_tasklists().await, Err(TodoError::AuthError(message)) if message.contains("revoked")));
+    }
+}
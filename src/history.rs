@@ -0,0 +1,294 @@
+//! What happened to items and lists over time, kept in an append-only log
+//! next to the local database. Unlike the undo journal it is never trimmed,
+//! and undoing an operation adds to it rather than taking the operation
+//! out.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::error::TodoResult;
+use crate::files::FileLock;
+use crate::journal::{ItemChange, ListChange, Slot};
+use crate::models::Item;
+use crate::output;
+use crate::tags;
+
+/// Name of the log in the directory of the local database.
+pub const LOG_FILE: &str = "history.jsonl";
+
+/// One operation in the log, with how it changed each list and item it
+/// touched.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    pub action: String,
+    /// Changes by list name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub lists: BTreeMap<String, Vec<String>>,
+    /// Changes by item ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub items: BTreeMap<String, Vec<String>>,
+}
+
+impl Record {
+    /// Describes the journaled `changes` of an operation, or `None` if they
+    /// changed nothing worth showing.
+    pub fn new(action: &str, at: DateTime<Utc>, changes: &[ListChange]) -> Option<Self> {
+        let lists: BTreeMap<String, Vec<String>> = changes.iter()
+            .map(|change| (change.name.clone(), describe_list(change)))
+            .filter(|(_, changes)| !changes.is_empty())
+            .collect();
+        let ids: BTreeSet<&str> = changes.iter().flat_map(|change| &change.items).map(|item| item.id.as_str()).collect();
+        let items: BTreeMap<String, Vec<String>> = ids.into_iter()
+            .map(|id| {
+                let before = find_item(changes, id, |change| change.before.as_ref());
+                let after = find_item(changes, id, |change| change.after.as_ref());
+                (id.to_string(), describe_item(before, after))
+            })
+            .filter(|(_, changes)| !changes.is_empty())
+            .collect();
+        if lists.is_empty() && items.is_empty() {
+            return None;
+        }
+        Some(Self { at, action: action.to_string(), lists, items })
+    }
+}
+
+/// The log file, one JSON record per line.
+pub struct Log {
+    path: PathBuf,
+}
+
+impl Log {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf() }
+    }
+
+    /// Adds `record` at the end and returns where it starts, for
+    /// `truncate`.
+    pub fn append(&self, record: &Record) -> TodoResult<u64> {
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let offset = file.metadata()?.len();
+        file.write_all(line.as_bytes())?;
+        Ok(offset)
+    }
+
+    /// Takes back the records from `offset` on, for an operation that
+    /// turned out not to be stored.
+    pub fn truncate(&self, offset: u64) -> TodoResult<()> {
+        let _lock = FileLock::acquire(&self.path)?;
+        std::fs::OpenOptions::new().write(true).open(&self.path)?.set_len(offset)?;
+        Ok(())
+    }
+
+    /// Every record, oldest first. A line cut short by a crash is skipped.
+    pub fn load(&self) -> TodoResult<Vec<Record>> {
+        let _lock = FileLock::acquire(&self.path)?;
+        let data = match std::fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(data.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+/// One operation that touched the item or list, with what it changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub at: DateTime<Utc>,
+    pub action: String,
+    pub changes: Vec<String>,
+}
+
+/// The changes to the item with ID `id`, oldest first, in whichever list
+/// it was in.
+pub fn item_events(records: &[Record], id: &str) -> Vec<Event> {
+    records.iter()
+        .filter_map(|record| Some(event(record, record.items.get(id)?)))
+        .collect()
+}
+
+/// The list holding the item with ID `id` and the item, in the version
+/// picked by `version`.
+fn find_item<'a>(changes: &'a [ListChange], id: &str, version: impl Fn(&'a ItemChange) -> Option<&'a Slot>) -> Option<(&'a str, &'a Item)> {
+    changes.iter().find_map(|change| {
+        let slot = change.items.iter().filter(|item| item.id == id).find_map(&version)?;
+        Some((change.name.as_str(), &slot.item))
+    })
+}
+
+/// The operations that changed the list named `name`, oldest first.
+pub fn list_events(records: &[Record], name: &str) -> Vec<Event> {
+    records.iter()
+        .filter_map(|record| Some(event(record, record.lists.get(name)?)))
+        .collect()
+}
+
+fn event(record: &Record, changes: &[String]) -> Event {
+    Event { at: record.at, action: record.action.clone(), changes: changes.to_vec() }
+}
+
+/// How an item changed, given its list and version before and after an
+/// operation. Timestamps alone are no change.
+fn describe_item(before: Option<(&str, &Item)>, after: Option<(&str, &Item)>) -> Vec<String> {
+    let (old_list, old, new_list, new) = match (before, after) {
+        (None, None) => return Vec::new(),
+        (None, Some((list, _))) => return vec![format!("added to '{}'", list)],
+        (Some(_), None) => return vec!["deleted".to_string()],
+        (Some((old_list, old)), Some((new_list, new))) => (old_list, old, new_list, new),
+    };
+
+    let mut changes = Vec::new();
+    if old_list != new_list {
+        changes.push(format!("moved from '{}' to '{}'", old_list, new_list));
+    }
+    if old.description != new.description {
+        changes.push(format!("renamed from '{}' to '{}'", old.description, new.description));
+    }
+    if old.completed != new.completed {
+        changes.push(if new.completed { "completed" } else { "reopened" }.to_string());
+    }
+    for (name, old_date, new_date) in [("due", old.due, new.due), ("start", old.start, new.start)] {
+        match (old_date, new_date) {
+            (old, new) if old == new => {}
+            (_, Some(date)) => changes.push(format!("{} set to {}", name, output::format_date(date))),
+            (_, None) => changes.push(format!("{} date cleared", name)),
+        }
+    }
+    if old.priority != new.priority {
+        changes.push(format!("priority changed from {} to {}", old.priority.name(), new.priority.name()));
+    }
+    let added: BTreeSet<String> = new.tags.difference(&old.tags).cloned().collect();
+    let removed: BTreeSet<String> = old.tags.difference(&new.tags).cloned().collect();
+    if !added.is_empty() {
+        changes.push(format!("tagged{}", tags::format(&added)));
+    }
+    if !removed.is_empty() {
+        changes.push(format!("untagged{}", tags::format(&removed)));
+    }
+    if old.parent != new.parent {
+        changes.push(if new.parent.is_some() { "made a subtask" } else { "moved to the top level" }.to_string());
+    }
+    if old.recurrence != new.recurrence {
+        changes.push(match &new.recurrence {
+            Some(recurrence) => format!("repeats {}", recurrence.describe()),
+            None => "stopped repeating".to_string(),
+        });
+    }
+    if old.notes != new.notes {
+        changes.push(if new.notes.is_empty() { "notes cleared" } else { "notes changed" }.to_string());
+    }
+    if old.trashed != new.trashed {
+        changes.push(if new.trashed.is_some() { "moved to the trash" } else { "restored from the trash" }.to_string());
+    }
+    changes
+}
+
+fn describe_list(change: &ListChange) -> Vec<String> {
+    let (old, new) = match (&change.before, &change.after) {
+        (None, None) => return Vec::new(),
+        (None, Some(_)) => return vec!["created".to_string()],
+        (Some(_), None) => return vec!["deleted".to_string()],
+        (Some(old), Some(new)) => (old, new),
+    };
+
+    let mut changes = Vec::new();
+    if old.trashed != new.trashed {
+        changes.push(if new.trashed.is_some() { "moved to the trash" } else { "restored from the trash" }.to_string());
+    }
+    let added = change.items.iter().filter(|item| item.before.is_none()).count();
+    let removed = change.items.iter().filter(|item| item.after.is_none()).count();
+    let kept: Vec<(&Slot, &Slot)> = change.items.iter().filter_map(|item| Some((item.before.as_ref()?, item.after.as_ref()?))).collect();
+    let changed = kept.iter()
+        .filter(|(old, new)| !describe_item(Some(("", &old.item)), Some(("", &new.item))).is_empty())
+        .count();
+    for (count, what) in [(added, "added"), (removed, "removed"), (changed, "changed")] {
+        if count > 0 {
+            changes.push(format!("{} {} {}", count, if count == 1 { "item" } else { "items" }, what));
+        }
+    }
+    if changes.is_empty() && kept.iter().any(|(old, new)| old.follows != new.follows) {
+        changes.push("items reordered".to_string());
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::List;
+
+    fn record(action: &str, before: Option<List>, after: Option<List>) -> Option<Record> {
+        let name = before.as_ref().or(after.as_ref()).map(|list| list.name.clone()).unwrap_or_default();
+        Record::new(action, Utc::now(), &[ListChange::new(name, before, after)])
+    }
+
+    fn list(name: &str, items: &[&Item]) -> List {
+        List { name: name.to_string(), items: items.iter().map(|item| (*item).clone()).collect(), trashed: None, created_at: None, updated_at: None }
+    }
+
+    #[test]
+    fn test_events() {
+        let milk = Item::new("Milk");
+        let bread = Item::new("Bread");
+        let done = Item { completed: true, description: "Oat milk".to_string(), updated_at: Some(Utc::now()), ..milk.clone() };
+        let tagged = Item { tags: ["dairy".to_string()].into(), ..done.clone() };
+        let restamped = Item { updated_at: Some(Utc::now()), ..bread.clone() };
+        let records: Vec<Record> = [
+            record("create list 'Shop'", None, Some(list("Shop", &[]))),
+            record("add 'Milk' to 'Shop'", Some(list("Shop", &[])), Some(list("Shop", &[&milk]))),
+            record("add 'Bread' to 'Shop'", Some(list("Shop", &[&milk])), Some(list("Shop", &[&milk, &bread]))),
+            record("edit 'Milk' in 'Shop'", Some(list("Shop", &[&milk, &bread])), Some(list("Shop", &[&done, &bread]))),
+            Record::new("move items from 'Shop' to 'Fridge'", Utc::now(), &[
+                ListChange::new("Shop".to_string(), Some(list("Shop", &[&done, &bread])), Some(list("Shop", &[&bread]))),
+                ListChange::new("Fridge".to_string(), None, Some(list("Fridge", &[&tagged]))),
+            ]),
+        ].into_iter().flatten().collect();
+        // Timestamps alone are no change worth recording.
+        assert_eq!(record("touch", Some(list("Shop", &[&bread])), Some(list("Shop", &[&restamped]))), None);
+
+        let events = item_events(&records, &milk.id);
+        let changes: Vec<Vec<String>> = events.iter().map(|event| event.changes.clone()).collect();
+        assert_eq!(changes, vec![
+            vec!["added to 'Shop'".to_string()],
+            vec!["renamed from 'Milk' to 'Oat milk'".to_string(), "completed".to_string()],
+            vec!["moved from 'Shop' to 'Fridge'".to_string(), "tagged #dairy".to_string()],
+        ]);
+        assert_eq!(events[1].action, "edit 'Milk' in 'Shop'");
+
+        let changes: Vec<Vec<String>> = list_events(&records, "Shop").into_iter().map(|event| event.changes).collect();
+        assert_eq!(changes, vec![
+            vec!["created".to_string()],
+            vec!["1 item added".to_string()],
+            vec!["1 item added".to_string()],
+            vec!["1 item changed".to_string()],
+            vec!["1 item removed".to_string()],
+        ]);
+        assert_eq!(list_events(&records, "Fridge")[0].changes, vec!["created".to_string()]);
+    }
+
+    #[test]
+    fn test_log_is_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(&dir.path().join(LOG_FILE));
+        assert!(log.load().unwrap().is_empty());
+        let first = record("create list 'Shop'", None, Some(list("Shop", &[]))).unwrap();
+        let second = record("remove list 'Shop'", Some(list("Shop", &[])), None).unwrap();
+        assert_eq!(log.append(&first).unwrap(), 0);
+        let offset = log.append(&second).unwrap();
+        log.truncate(offset).unwrap();
+        assert_eq!(log.load().unwrap(), vec![first.clone()]);
+        log.append(&second).unwrap();
+
+        // A record torn by a crash does not hide the others.
+        let mut file = std::fs::OpenOptions::new().append(true).open(dir.path().join(LOG_FILE)).unwrap();
+        file.write_all(b"{\"at\":").unwrap();
+        assert_eq!(log.load().unwrap(), vec![first, second]);
+    }
+}
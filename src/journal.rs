@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::error::TodoResult;
+use crate::files::{self, FileLock};
+use crate::models::{Item, List};
+
+/// Number of operations kept when `TODO_UNDO_DEPTH` is not set.
+pub const DEFAULT_DEPTH: usize = 100;
+
+/// How an operation changed one list: the list before and after it without
+/// its items, where `None` means the list did not exist, and the items it
+/// changed. Only changed items are kept so that small changes to big lists
+/// stay small in the journal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "ListChangeFormat", into = "ListChangeFormat")]
+pub struct ListChange {
+    pub name: String,
+    pub before: Option<List>,
+    pub after: Option<List>,
+    pub items: Vec<ItemChange>,
+}
+
+/// An item before and after an operation, `None` where it was not in the
+/// list. Moving an item within the list changes what it follows.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ItemChange {
+    pub id: String,
+    pub before: Option<Slot>,
+    pub after: Option<Slot>,
+}
+
+/// An item and the ID of the item before it, `None` if it came first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub item: Item,
+    pub follows: Option<String>,
+}
+
+impl ListChange {
+    /// Diffs two versions of the list named `name`.
+    pub fn new(name: String, before: Option<List>, after: Option<List>) -> Self {
+        let items = diff_items(
+            before.as_ref().map_or(&[], |list| &list.items),
+            after.as_ref().map_or(&[], |list| &list.items),
+        );
+        let without_items = |list: List| List { items: Vec::new(), ..list };
+        Self { name, before: before.map(without_items), after: after.map(without_items), items }
+    }
+
+    /// The list as the operation left it, or as it found it if `undo`,
+    /// given the list as it is now. Items the operation did not change keep
+    /// their place.
+    pub fn apply(&self, current: Option<List>, undo: bool) -> Option<List> {
+        let list = if undo { self.before.clone() } else { self.after.clone() }?;
+        let mut slots = Vec::new();
+        let mut previous = None;
+        for item in current.map(|list| list.items).unwrap_or_default() {
+            let follows = previous.replace(item.id.clone());
+            if !self.items.iter().any(|change| change.id == item.id) {
+                slots.push(Slot { item, follows });
+            }
+        }
+        slots.extend(self.items.iter().filter_map(|change| if undo { change.before.clone() } else { change.after.clone() }));
+        Some(List { items: order(slots), ..list })
+    }
+}
+
+/// Journals before items were diffed hold both versions of each list
+/// whole.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ListChangeFormat {
+    Items {
+        name: String,
+        before: Option<List>,
+        after: Option<List>,
+        items: Vec<ItemChange>,
+    },
+    Lists {
+        name: String,
+        before: Option<List>,
+        after: Option<List>,
+    },
+}
+
+impl From<ListChangeFormat> for ListChange {
+    fn from(format: ListChangeFormat) -> Self {
+        match format {
+            ListChangeFormat::Items { name, before, after, items } => Self { name, before, after, items },
+            ListChangeFormat::Lists { name, before, after } => Self::new(name, before, after),
+        }
+    }
+}
+
+impl From<ListChange> for ListChangeFormat {
+    fn from(change: ListChange) -> Self {
+        let ListChange { name, before, after, items } = change;
+        ListChangeFormat::Items { name, before, after, items }
+    }
+}
+
+/// A journaled operation, described for `todo undo` and `todo redo` output.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub action: String,
+    /// When the operation happened; absent in journals from before this was
+    /// recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<DateTime<Utc>>,
+    pub changes: Vec<ListChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct History {
+    pub undo: Vec<Entry>,
+    pub redo: Vec<Entry>,
+}
+
+/// Undo and redo stacks stored in a file next to the local database, so
+/// they survive across invocations. Only the most recent `depth` operations
+/// are kept.
+pub struct Journal {
+    path: PathBuf,
+    depth: usize,
+}
+
+impl Journal {
+    pub fn new(path: &Path, depth: usize) -> Self {
+        Self { path: path.to_path_buf(), depth }
+    }
+
+    /// Reads the depth from `TODO_UNDO_DEPTH`.
+    pub fn depth_from_env() -> usize {
+        std::env::var("TODO_UNDO_DEPTH").ok()
+            .and_then(|depth| depth.parse().ok())
+            .unwrap_or(DEFAULT_DEPTH)
+    }
+
+    pub async fn load(&self) -> TodoResult<History> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(History::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self, history: &History) -> TodoResult<()> {
+        files::write_atomic(&self.path, serde_json::to_string(history)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Keeps other processes from changing the journal until dropped.
+    pub fn lock(&self) -> TodoResult<FileLock> {
+        Ok(FileLock::acquire(&self.path)?)
+    }
+
+    /// Journals an operation done `at` that made `changes`, as computed by
+    /// `diff`. Operations that changed nothing are not recorded. A new
+    /// operation discards everything that could be redone. Returns the
+    /// stacks from before, to `save` back should the operation not be
+    /// stored after all, or `None` if nothing was recorded.
+    pub async fn record(&self, action: String, at: DateTime<Utc>, changes: Vec<ListChange>) -> TodoResult<Option<History>> {
+        if changes.is_empty() || self.depth == 0 {
+            return Ok(None);
+        }
+
+        let _lock = self.lock()?;
+        let previous = self.load().await?;
+        let mut history = History { undo: previous.undo.clone(), redo: Vec::new() };
+        history.undo.push(Entry { action, at: Some(at), changes });
+        let excess = history.undo.len().saturating_sub(self.depth);
+        history.undo.drain(..excess);
+        self.save(&history).await?;
+        Ok(Some(previous))
+    }
+}
+
+/// How the lists `before` an operation became those `after` it, by name.
+pub fn diff(before: Vec<List>, mut after: Vec<List>) -> Vec<ListChange> {
+    let mut changes = Vec::new();
+    for list in before {
+        let after = after.iter().position(|l| l.name == list.name).map(|i| after.remove(i));
+        if after.as_ref() != Some(&list) {
+            changes.push(ListChange::new(list.name.clone(), Some(list), after));
+        }
+    }
+    changes.extend(after.into_iter().map(|list| ListChange::new(list.name.clone(), None, Some(list))));
+    changes
+}
+
+fn slots(items: &[Item]) -> Vec<Slot> {
+    let mut previous = None;
+    items.iter()
+        .map(|item| Slot { item: item.clone(), follows: previous.replace(item.id.clone()) })
+        .collect()
+}
+
+fn diff_items(before: &[Item], after: &[Item]) -> Vec<ItemChange> {
+    let mut after: Vec<Slot> = slots(after);
+    let mut changes = Vec::new();
+    for slot in slots(before) {
+        let id = slot.item.id.clone();
+        let after = after.iter().position(|s| s.item.id == id).map(|i| after.remove(i));
+        if after.as_ref() != Some(&slot) {
+            changes.push(ItemChange { id, before: Some(slot), after });
+        }
+    }
+    changes.extend(after.into_iter().map(|slot| ItemChange { id: slot.item.id.clone(), before: None, after: Some(slot) }));
+    changes
+}
+
+/// Lines up items by what each follows. Items whose place cannot be found,
+/// because the list changed in ways the journal does not know about, go at
+/// the end.
+fn order(slots: Vec<Slot>) -> Vec<Item> {
+    let mut next: HashMap<Option<&str>, usize> = HashMap::new();
+    for (i, slot) in slots.iter().enumerate() {
+        next.entry(slot.follows.as_deref()).or_insert(i);
+    }
+    let mut placed = vec![false; slots.len()];
+    let mut order = Vec::new();
+    let mut previous = None;
+    while let Some(&i) = next.get(&previous) {
+        if placed[i] {
+            break;
+        }
+        placed[i] = true;
+        order.push(i);
+        previous = Some(slots[i].item.id.as_str());
+    }
+    order.extend((0..slots.len()).filter(|&i| !placed[i]));
+
+    let mut items: Vec<Option<Item>> = slots.into_iter().map(|slot| Some(slot.item)).collect();
+    order.into_iter().filter_map(|i| items[i].take()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Item;
+
+    fn list(name: &str, items: &[&str]) -> List {
+        List { name: name.to_string(), items: items.iter().map(|d| Item::new(d)).collect(), trashed: None, created_at: None, updated_at: None }
+    }
+
+    #[tokio::test]
+    async fn test_record_is_bounded_and_clears_redo() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(&dir.path().join("journal.json"), 2);
+
+        let shop = list("Shop", &["Milk"]);
+        let now = Utc::now();
+        journal.record("no-op".to_string(), now, diff(vec![shop.clone()], vec![shop.clone()])).await.unwrap();
+        assert!(journal.load().await.unwrap().undo.is_empty());
+
+        let mut history = journal.load().await.unwrap();
+        history.redo.push(Entry { action: "stale".to_string(), at: None, changes: Vec::new() });
+        journal.save(&history).await.unwrap();
+
+        let work = list("Work", &[]);
+        journal.record("create".to_string(), now, diff(vec![], vec![work.clone()])).await.unwrap();
+        journal.record("remove all".to_string(), now, diff(vec![shop.clone(), work.clone()], vec![])).await.unwrap();
+        journal.record("recreate".to_string(), now, diff(vec![], vec![shop.clone()])).await.unwrap();
+
+        let history = journal.load().await.unwrap();
+        assert!(history.redo.is_empty());
+        let actions: Vec<&str> = history.undo.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["remove all", "recreate"]);
+        assert_eq!(history.undo[1].at, Some(now));
+        assert_eq!(history.undo[0].changes, vec![
+            ListChange::new("Shop".to_string(), Some(shop), None),
+            ListChange::new("Work".to_string(), Some(work), None),
+        ]);
+    }
+
+    #[test]
+    fn test_changes_keep_only_changed_items() {
+        let names: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let before = list("Big", &names.iter().map(String::as_str).collect::<Vec<_>>());
+        let mut after = before.clone();
+        let first = after.items.remove(0);
+        after.items.push(first);
+        after.items[500].completed = true;
+
+        let change = ListChange::new("Big".to_string(), Some(before.clone()), Some(after.clone()));
+        // The moved item, the one now first, and the completed one.
+        assert_eq!(change.items.len(), 3);
+        assert!(change.before.as_ref().unwrap().items.is_empty());
+        assert_eq!(change.apply(Some(after.clone()), true), Some(before.clone()));
+        assert_eq!(change.apply(Some(before.clone()), false), Some(after.clone()));
+
+        // Items swapped around an unchanged one in between.
+        let before = list("Mix", &["a", "c", "x", "d", "y"]);
+        let ids = |order: &[usize]| order.iter().map(|&i| before.items[i].clone()).collect::<Vec<_>>();
+        let after = List { items: ids(&[0, 3, 4, 1, 2]), ..before.clone() };
+        let change = ListChange::new("Mix".to_string(), Some(before.clone()), Some(after.clone()));
+        assert_eq!(change.apply(Some(after.clone()), true), Some(before.clone()));
+        assert_eq!(change.apply(Some(before), false), Some(after));
+    }
+
+    #[test]
+    fn test_undo_keeps_later_changes() {
+        let before = list("Shop", &["Milk", "Bread"]);
+        let mut after = before.clone();
+        after.items[1].completed = true;
+        let change = ListChange::new("Shop".to_string(), Some(before.clone()), Some(after.clone()));
+
+        let eggs = Item::new("Eggs");
+        let mut current = after;
+        current.items.insert(1, eggs.clone());
+        let undone = change.apply(Some(current), true).unwrap();
+        assert_eq!(undone.items, vec![before.items[0].clone(), eggs, before.items[1].clone()]);
+
+        assert_eq!(ListChange::new("Shop".to_string(), None, Some(before)).apply(Some(undone), true), None);
+    }
+
+    #[tokio::test]
+    async fn test_loads_whole_list_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(&dir.path().join("journal.json"), 2);
+        let before = list("Shop", &["Milk"]);
+        let mut after = before.clone();
+        after.items.push(Item::new("Bread"));
+        let legacy = serde_json::json!({
+            "undo": [{ "action": "add", "changes": [{ "name": "Shop", "before": before, "after": after }] }],
+            "redo": [],
+        });
+        std::fs::write(dir.path().join("journal.json"), legacy.to_string()).unwrap();
+
+        let history = journal.load().await.unwrap();
+        assert_eq!(history.undo[0].changes, vec![ListChange::new("Shop".to_string(), Some(before), Some(after))]);
+        assert_eq!(history.undo[0].changes[0].items.len(), 1);
+    }
+}
@@ -5,6 +5,25 @@ mod models;
 mod error;
 mod auth;
 mod sync;
+mod storage;
+mod dates;
+mod merge;
+mod google;
+mod files;
+mod journal;
+mod output;
+mod tui;
+mod paths;
+mod tags;
+mod filter;
+mod views;
+mod search;
+mod checklist;
+mod editor;
+mod selection;
+mod tree;
+mod recurrence;
+mod history;
 
 use clap::Parser;
 use cli::Cli;
@@ -14,7 +33,14 @@ use dotenv::dotenv;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
+    if let Ok(dir) = paths::config_dir() {
+        dotenv::from_path(dir.join("config.env")).ok();
+    }
     let cli = Cli::parse();
-    execute_command(cli.command).await?;
+    let scope = cli.scope();
+    if let Some(dir) = cli.data_dir {
+        paths::override_data_dir(dir);
+    }
+    execute_command(cli.command, scope).await?;
     Ok(())
 }
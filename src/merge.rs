@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use crate::models::{Item, List};
+
+/// The same item was changed differently on both sides since the last sync.
+/// `None` means the item does not exist (never did, or was removed) on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub list: String,
+    pub base: Option<Item>,
+    pub ours: Option<Item>,
+    pub theirs: Option<Item>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    /// Keep both versions; theirs is added as a new item.
+    Both,
+}
+
+/// Changes made on one side of a merge, for reporting.
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeStats {
+    pub from_ours: usize,
+    pub from_theirs: usize,
+    pub conflicts: usize,
+}
+
+/// Three-way merges two versions of the store against their common `base`.
+///
+/// Items are matched by ID and compared by content, not timestamps. A side
+/// that left an item as it was in `base` accepts the other side's version,
+/// including removal. When both sides changed the same item differently,
+/// `resolve` picks the outcome. Lists keep
+/// our item order with items only known to them appended in their order.
+pub fn merge(base: &[List], ours: &[List], theirs: &[List], mut resolve: impl FnMut(&Conflict) -> Resolution) -> (Vec<List>, MergeStats) {
+    let base = index(base);
+    let ours = index(ours);
+    let theirs = index(theirs);
+
+    let mut names: Vec<&str> = ours.keys().chain(theirs.keys()).chain(base.keys()).copied().collect();
+    names.sort();
+    names.dedup();
+
+    let mut stats = MergeStats::default();
+    let mut merged = Vec::new();
+
+    for name in names {
+        let (b, o, t) = (base.get(name), ours.get(name), theirs.get(name));
+        let base_items = b.map(|list| list.items.as_slice()).unwrap_or_default();
+        let our_items = o.map(|list| list.items.as_slice()).unwrap_or_default();
+        let their_items = t.map(|list| list.items.as_slice()).unwrap_or_default();
+
+        let items = merge_items(name, base_items, our_items, their_items, &mut resolve, &mut stats);
+
+        // The list itself survives if the side that changed its existence
+        // wants it, or if any item still lives in it.
+        let exists = if o.is_some() == b.is_some() { t.is_some() } else { o.is_some() };
+        // Whether the list is in the trash merges like an item field, and so
+        // does its creation time; it was last updated on whichever side did so
+        // later.
+        let field = |get: fn(&List) -> Option<DateTime<Utc>>| {
+            let value = |list: Option<&&List>| list.and_then(|list| get(list));
+            if value(o) == value(b) { value(t) } else { value(o) }
+        };
+        let updated_at = o.and_then(|list| list.updated_at).max(t.and_then(|list| list.updated_at));
+        if exists || !items.is_empty() {
+            merged.push(List {
+                name: name.to_string(),
+                items,
+                trashed: field(|list| list.trashed),
+                created_at: field(|list| list.created_at),
+                updated_at,
+            });
+        }
+    }
+
+    (merged, stats)
+}
+
+fn merge_items(
+    list: &str,
+    base: &[Item],
+    ours: &[Item],
+    theirs: &[Item],
+    resolve: &mut impl FnMut(&Conflict) -> Resolution,
+    stats: &mut MergeStats,
+) -> Vec<Item> {
+    let base_by_id: HashMap<&str, &Item> = base.iter().map(|item| (item.id.as_str(), item)).collect();
+    let our_by_id: HashMap<&str, &Item> = ours.iter().map(|item| (item.id.as_str(), item)).collect();
+    let their_by_id: HashMap<&str, &Item> = theirs.iter().map(|item| (item.id.as_str(), item)).collect();
+
+    let mut seen = HashSet::new();
+    let ids = ours.iter().chain(theirs).chain(base).map(|item| item.id.as_str()).filter(|id| seen.insert(*id));
+
+    let mut merged = Vec::new();
+    for id in ids {
+        let (b, o, t) = (base_by_id.get(id).copied(), our_by_id.get(id).copied(), their_by_id.get(id).copied());
+
+        if same_content(o, t) {
+            merged.extend(o.map(|o| with_times(o, t)));
+        } else if same_content(o, b) {
+            stats.from_theirs += 1;
+            merged.extend(t.map(|t| with_times(t, o)));
+        } else if same_content(t, b) {
+            stats.from_ours += 1;
+            merged.extend(o.map(|o| with_times(o, t)));
+        } else {
+            stats.conflicts += 1;
+            let conflict = Conflict {
+                list: list.to_string(),
+                base: b.cloned(),
+                ours: o.cloned(),
+                theirs: t.cloned(),
+            };
+            match resolve(&conflict) {
+                Resolution::Ours => merged.extend(conflict.ours),
+                Resolution::Theirs => merged.extend(conflict.theirs),
+                Resolution::Both => {
+                    let keep_theirs = conflict.ours.is_some();
+                    merged.extend(conflict.ours);
+                    merged.extend(conflict.theirs.map(|item| if keep_theirs {
+                        Item { id: Item::generate_id(), ..item }
+                    } else {
+                        item
+                    }));
+                }
+            }
+        }
+    }
+    merged
+}
+
+/// Whether two versions of an item say the same, whenever they were
+/// stamped: two machines making the same edit stamp it at different times.
+fn same_content(a: Option<&Item>, b: Option<&Item>) -> bool {
+    let (Some(a), Some(b)) = (a, b) else {
+        return a.is_none() && b.is_none();
+    };
+    let Item {
+        id,
+        description,
+        notes,
+        completed,
+        due,
+        start,
+        priority,
+        tags,
+        created_at: _,
+        updated_at: _,
+        completed_at: _,
+        parent,
+        recurrence,
+        trashed,
+    } = a;
+    *id == b.id
+        && *description == b.description
+        && *notes == b.notes
+        && *completed == b.completed
+        && *due == b.due
+        && *start == b.start
+        && *priority == b.priority
+        && *tags == b.tags
+        && *parent == b.parent
+        && *recurrence == b.recurrence
+        && *trashed == b.trashed
+}
+
+/// `item` with the later of its timestamps and those of the `other` side's
+/// version.
+fn with_times(item: &Item, other: Option<&Item>) -> Item {
+    let mut item = item.clone();
+    if let Some(other) = other {
+        item.created_at = item.created_at.or(other.created_at);
+        item.updated_at = item.updated_at.max(other.updated_at);
+        if item.completed && other.completed {
+            item.completed_at = item.completed_at.max(other.completed_at);
+        }
+    }
+    item
+}
+
+fn index(lists: &[List]) -> HashMap<&str, &List> {
+    lists.iter().map(|list| (list.name.as_str(), list)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(name: &str, items: &[&Item]) -> List {
+        List { name: name.to_string(), items: items.iter().map(|item| (*item).clone()).collect(), trashed: None, created_at: None, updated_at: None }
+    }
+
+    fn edited(item: &Item, description: &str) -> Item {
+        Item { description: description.to_string(), ..item.clone() }
+    }
+
+    fn descriptions(lists: &[List], name: &str) -> Vec<String> {
+        lists.iter().find(|list| list.name == name).unwrap().items.iter().map(|item| item.description.clone()).collect()
+    }
+
+    fn no_conflicts(conflict: &Conflict) -> Resolution {
+        panic!("unexpected conflict: {:?}", conflict)
+    }
+
+    #[test]
+    fn test_merge_combines_independent_changes() {
+        let milk = Item::new("Milk");
+        let eggs = Item::new("Eggs");
+        let bread = Item::new("Bread");
+        let base = vec![list("Shop", &[&milk, &eggs, &bread]), list("Old", &[])];
+
+        let mut done_milk = milk.clone();
+        done_milk.completed = true;
+        let butter = Item::new("Butter");
+        let ours = vec![list("Shop", &[&done_milk, &eggs, &bread, &butter])];
+
+        let jam = Item::new("Jam");
+        let theirs = vec![list("Shop", &[&milk, &edited(&eggs, "Free-range eggs"), &jam]), list("Old", &[]), list("New", &[])];
+
+        let (merged, stats) = merge(&base, &ours, &theirs, no_conflicts);
+
+        assert_eq!(merged.iter().map(|l| l.name.as_str()).collect::<Vec<_>>(), vec!["New", "Shop"]);
+        assert_eq!(descriptions(&merged, "Shop"), vec!["Milk", "Free-range eggs", "Butter", "Jam"]);
+        assert!(merged[1].items[0].completed);
+        assert_eq!(stats, MergeStats { from_ours: 2, from_theirs: 3, conflicts: 0 });
+    }
+
+    #[test]
+    fn test_merge_ignores_timestamps() {
+        let base_item = Item::new("Task");
+        let base = vec![list("L", &[&base_item])];
+        let stamped = |at: i64| Item {
+            completed: true,
+            updated_at: Some(DateTime::from_timestamp(at, 0).unwrap()),
+            completed_at: Some(DateTime::from_timestamp(at, 0).unwrap()),
+            ..base_item.clone()
+        };
+
+        // The same edit made on both sides at different times.
+        let (merged, stats) = merge(&base, &[list("L", &[&stamped(100)])], &[list("L", &[&stamped(200)])], no_conflicts);
+        assert_eq!(merged[0].items, vec![stamped(200)]);
+        assert_eq!(stats, MergeStats::default());
+
+        // A side that only restamped the item takes the other side's edit.
+        let touched = Item { updated_at: Some(DateTime::from_timestamp(300, 0).unwrap()), ..base_item.clone() };
+        let (merged, stats) = merge(&base, &[list("L", &[&touched])], &[list("L", &[&stamped(200)])], no_conflicts);
+        assert!(merged[0].items[0].completed);
+        assert_eq!(merged[0].items[0].updated_at, touched.updated_at);
+        assert_eq!(stats.from_theirs, 1);
+    }
+
+    #[test]
+    fn test_merge_first_sync_without_base() {
+        let shared = Item::new("Shared");
+        let ours = vec![list("Inbox", &[&shared, &Item::new("Local")])];
+        let theirs = vec![list("Inbox", &[&shared, &Item::new("Remote")]), list("Work", &[&Item::new("Deploy")])];
+
+        let (merged, _) = merge(&[], &ours, &theirs, no_conflicts);
+
+        assert_eq!(descriptions(&merged, "Inbox"), vec!["Shared", "Local", "Remote"]);
+        assert_eq!(descriptions(&merged, "Work"), vec!["Deploy"]);
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let task = Item::new("Task");
+        let gone = Item::new("Gone");
+        let base = vec![list("L", &[&task, &gone])];
+        let ours = vec![list("L", &[&edited(&task, "Ours")])];
+        let theirs = vec![list("L", &[&edited(&task, "Theirs"), &edited(&gone, "Edited")])];
+
+        let mut seen = Vec::new();
+        let (merged, stats) = merge(&base, &ours, &theirs, |conflict| {
+            seen.push(conflict.clone());
+            Resolution::Theirs
+        });
+        assert_eq!(stats.conflicts, 2);
+        assert_eq!(seen[0].ours.as_ref().unwrap().description, "Ours");
+        assert_eq!(seen[1].ours, None);
+        assert_eq!(descriptions(&merged, "L"), vec!["Theirs", "Edited"]);
+
+        let (merged, _) = merge(&base, &ours, &theirs, |_| Resolution::Ours);
+        assert_eq!(descriptions(&merged, "L"), vec!["Ours"]);
+
+        let (merged, _) = merge(&base, &ours, &theirs, |_| Resolution::Both);
+        assert_eq!(descriptions(&merged, "L"), vec!["Ours", "Theirs", "Edited"]);
+        assert_ne!(merged[0].items[0].id, merged[0].items[1].id);
+        assert_eq!(merged[0].items[2].id, gone.id);
+    }
+
+    #[test]
+    fn test_merge_removed_list_keeps_edited_items() {
+        let kept = Item::new("Kept");
+        let base = vec![list("L", &[&kept])];
+        let theirs = vec![list("L", &[&edited(&kept, "Changed remotely")])];
+
+        let (merged, _) = merge(&base, &[], &theirs, |_| Resolution::Theirs);
+        assert_eq!(descriptions(&merged, "L"), vec!["Changed remotely"]);
+
+        let (merged, _) = merge(&base, &[], &theirs, |_| Resolution::Ours);
+        assert!(merged.is_empty());
+    }
+}
@@ -1,14 +1,247 @@
+use std::collections::BTreeSet;
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use crate::recurrence::Recurrence;
+use crate::tags;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Shortest ID prefix accepted as an item reference. Anything shorter is
+/// treated as a 1-based position so that `3` keeps meaning "the third item".
+pub(crate) const MIN_ID_PREFIX_LEN: usize = 4;
+
+/// Number of ID characters shown in human-readable output.
+pub(crate) const SHORT_ID_LEN: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 
 pub(crate) struct List {
     pub name: String,
     pub items: Vec<Item>,
+    /// When the list was moved to the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed: Option<DateTime<Utc>>,
+    /// Absent for lists created before this was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the list or any of its items last changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct Item {
+    #[serde(default = "Item::generate_id")]
+    pub id: String,
     pub description: String,
+    /// Longer Markdown text kept with the item, shown by `todo view`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
     pub completed: bool,
-}
\ No newline at end of file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Priority::is_none")]
+    pub priority: Priority,
+    /// Lowercase tag names without their `#` marker.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Absent for items created before this was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the item last changed, set by `Database` along with
+    /// `completed_at`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// When the item was last marked as completed; absent while it is open.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// ID of the item of the same list this is a subtask of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// How the item repeats; completing it creates the next occurrence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
+    /// When the item was moved to the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed: Option<DateTime<Utc>>,
+}
+
+/// How urgent an item is, ordered from least to most urgent. Can also be
+/// given as a number from 0 to 4.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Priority {
+    #[default]
+    #[value(alias = "0")]
+    None,
+    #[value(alias = "1")]
+    Low,
+    #[value(alias = "2")]
+    Medium,
+    #[value(alias = "3")]
+    High,
+    #[value(alias = "4")]
+    Urgent,
+}
+
+impl Priority {
+    pub fn is_none(&self) -> bool {
+        *self == Priority::None
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    /// Marker shown before the description in human-readable output.
+    pub fn badge(self) -> &'static str {
+        match self {
+            Priority::None => "",
+            Priority::Low => "! ",
+            Priority::Medium => "!! ",
+            Priority::High => "!!! ",
+            Priority::Urgent => "!!!! ",
+        }
+    }
+}
+
+impl Item {
+    pub fn new(description: &str) -> Self {
+        Self {
+            id: Self::generate_id(),
+            description: description.to_string(),
+            notes: String::new(),
+            completed: false,
+            due: None,
+            start: None,
+            priority: Priority::None,
+            tags: BTreeSet::new(),
+            created_at: Some(Utc::now()),
+            updated_at: None,
+            completed_at: None,
+            parent: None,
+            recurrence: None,
+            trashed: None,
+        }
+    }
+
+    /// Creates an item from a description typed by the user, taking tag
+    /// words such as `#home` or `+release` out of it.
+    pub fn from_input(input: &str) -> Self {
+        let (description, tags) = tags::extract(input);
+        let description = if description.is_empty() { input.trim() } else { &description };
+        Self { tags, ..Self::new(description) }
+    }
+
+    pub fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+        !self.completed && self.due.is_some_and(|due| due < now)
+    }
+
+    /// The next occurrence of a recurring item completed at `completed`: a
+    /// copy with a new ID, due as its rule says and with its start date moved
+    /// along. `None` if the item does not recur or its rule has run out.
+    pub fn next_occurrence(&self, completed: DateTime<Utc>) -> Option<Item> {
+        let recurrence = self.recurrence.as_ref()?;
+        let local = |date: DateTime<Utc>| date.with_timezone(&Local);
+        let due = recurrence.next(self.due.map(local).as_ref(), &local(completed))?.with_timezone(&Utc);
+        Some(Item {
+            id: Self::generate_id(),
+            completed: false,
+            due: Some(due),
+            start: self.start.zip(self.due).map(|(start, old_due)| start + (due - old_due)),
+            recurrence: Some(recurrence.following()),
+            created_at: Some(completed),
+            updated_at: None,
+            completed_at: None,
+            trashed: None,
+            ..self.clone()
+        })
+    }
+
+    pub fn generate_id() -> String {
+        Uuid::new_v4().simple().to_string()
+    }
+
+    pub fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(SHORT_ID_LEN)]
+    }
+}
+
+/// Why an item reference could not be resolved.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ItemRefError {
+    NotFound,
+    Ambiguous(usize),
+}
+
+/// Resolves an item reference to its index in the list.
+///
+/// A reference is either a full ID, a unique ID prefix of at least
+/// `MIN_ID_PREFIX_LEN` characters, or a 1-based position. IDs always win over
+/// positions.
+pub(crate) fn resolve_item_ref<'a>(ids: impl IntoIterator<Item = &'a str>, reference: &str) -> Result<usize, ItemRefError> {
+    let reference = reference.trim();
+    let ids: Vec<&str> = ids.into_iter().collect();
+
+    if let Some(index) = ids.iter().position(|id| *id == reference) {
+        return Ok(index);
+    }
+
+    if reference.len() >= MIN_ID_PREFIX_LEN {
+        let matches: Vec<usize> = ids.iter()
+            .enumerate()
+            .filter(|(_, id)| id.starts_with(reference))
+            .map(|(i, _)| i)
+            .collect();
+        match matches.len() {
+            0 => {}
+            1 => return Ok(matches[0]),
+            n => return Err(ItemRefError::Ambiguous(n)),
+        }
+    }
+
+    match reference.parse::<usize>() {
+        Ok(position) if position >= 1 && position <= ids.len() => Ok(position - 1),
+        _ => Err(ItemRefError::NotFound),
+    }
+}
+
+/// Where moved, copied or reordered items go among a list's items.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    Top,
+    Bottom,
+    /// A 1-based position; past the end means the bottom.
+    At(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_item_ref() {
+        let ids = ["3f2a9c1b00", "3f2b000000", "a1b2c3d4e5"];
+
+        assert_eq!(resolve_item_ref(ids, "a1b2c3d4e5"), Ok(2));
+        assert_eq!(resolve_item_ref(ids, "a1b2"), Ok(2));
+        assert_eq!(resolve_item_ref(ids, "3f2a"), Ok(0));
+        assert_eq!(resolve_item_ref(ids, "3f2"), Err(ItemRefError::NotFound));
+        assert_eq!(resolve_item_ref(ids, "3f2a9c1b00ff"), Err(ItemRefError::NotFound));
+        assert_eq!(resolve_item_ref(["3f2a0000", "3f2a1111"], "3f2a"), Err(ItemRefError::Ambiguous(2)));
+
+        // Short numeric references are positions, even if an ID starts with them.
+        assert_eq!(resolve_item_ref(ids, "3"), Ok(2));
+        assert_eq!(resolve_item_ref(ids, "1"), Ok(0));
+        assert_eq!(resolve_item_ref(ids, "0"), Err(ItemRefError::NotFound));
+        assert_eq!(resolve_item_ref(ids, "4"), Err(ItemRefError::NotFound));
+    }
+}
@@ -0,0 +1,449 @@
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::error::TodoResult;
+use crate::models::{Item, Priority};
+use crate::tags;
+use crate::tree::Node;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default, ValueEnum)]
+pub enum Format {
+    /// Human-readable listing
+    #[default]
+    Text,
+    /// One object per list with every item field
+    Json,
+    /// One row per item
+    Csv,
+    /// Markdown checklists
+    Markdown,
+    /// Aligned columns
+    Table,
+}
+
+/// The items of one list selected for display, in tree order with their
+/// paths.
+pub struct ListView<'a> {
+    pub name: &'a str,
+    /// Which database the list is from, when several are shown together.
+    pub source: Option<&'a str>,
+    pub items: Vec<Node<'a>>,
+}
+
+/// Stable JSON schema for an item; every field is always present.
+#[derive(Serialize)]
+struct ItemRecord<'a> {
+    list: &'a str,
+    source: Option<&'a str>,
+    /// 1-based position among the item's siblings.
+    position: usize,
+    /// Positions from the top level down, e.g. `3.2`.
+    path: String,
+    /// ID of the item this is a subtask of.
+    parent: Option<&'a str>,
+    id: &'a str,
+    description: &'a str,
+    /// Markdown notes, empty if there are none.
+    notes: &'a str,
+    completed: bool,
+    due: Option<DateTime<Utc>>,
+    start: Option<DateTime<Utc>>,
+    priority: Priority,
+    tags: Vec<&'a str>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+    /// The repeat rule as an RRULE.
+    recurrence: Option<String>,
+}
+
+impl<'a> ItemRecord<'a> {
+    fn new(view: &ListView<'a>, node: &Node<'a>) -> Self {
+        let item = node.item;
+        Self {
+            list: view.name,
+            source: view.source,
+            position: node.path.last().copied().unwrap_or_default(),
+            path: node.reference(),
+            parent: node.parent,
+            id: &item.id,
+            description: &item.description,
+            notes: &item.notes,
+            completed: item.completed,
+            due: item.due,
+            start: item.start,
+            priority: item.priority,
+            tags: item.tags.iter().map(String::as_str).collect(),
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+            completed_at: item.completed_at,
+            recurrence: item.recurrence.as_ref().map(ToString::to_string),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ListRecord<'a> {
+    name: &'a str,
+    source: Option<&'a str>,
+    items: Vec<ItemRecord<'a>>,
+}
+
+/// Renders `views`; with `verbose`, text and Markdown show notes under
+/// their items. JSON and CSV always include them.
+pub fn render(format: Format, views: &[ListView], now: DateTime<Utc>, highlight: bool, verbose: bool) -> TodoResult<String> {
+    match format {
+        Format::Text => Ok(render_text(views, now, highlight, verbose)),
+        Format::Json => render_json(views),
+        Format::Csv => render_csv(views),
+        Format::Markdown => Ok(render_markdown(views, now, verbose)),
+        Format::Table => Ok(render_table(views, now)),
+    }
+}
+
+fn render_text(views: &[ListView], now: DateTime<Utc>, highlight: bool, verbose: bool) -> String {
+    let mut out = String::new();
+    for view in views {
+        out.push_str(&format!("List: {}{}\n", view.name, source_suffix(view)));
+        for node in &view.items {
+            let item = node.item;
+            let line = format!(
+                "  {}{}. {} [{}] {}{}{}{}{}{}",
+                indent(node), node.reference(), item.short_id(), checkbox(item), item.priority.badge(),
+                item.description, progress(node), tags::format(&item.tags), format_dates(item, now), format_recurrence(item)
+            );
+            if highlight && item.is_overdue(now) {
+                out.push_str(&format!("\x1b[31m{}\x1b[0m\n", line));
+            } else {
+                out.push_str(&format!("{}\n", line));
+            }
+            if verbose {
+                out.push_str(&indent_notes(item, &format!("  {}{}", indent(node), " ".repeat(node.reference().len() + 2))));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn render_json(views: &[ListView]) -> TodoResult<String> {
+    let lists: Vec<ListRecord> = views.iter()
+        .map(|view| ListRecord {
+            name: view.name,
+            source: view.source,
+            items: view.items.iter().map(|node| ItemRecord::new(view, node)).collect(),
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&serde_json::json!({ "lists": lists }))? + "\n")
+}
+
+fn render_csv(views: &[ListView]) -> TodoResult<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["list", "position", "id", "description", "completed", "due", "start", "priority", "tags", "created_at", "path", "parent", "recurrence", "updated_at", "completed_at", "notes", "source"])?;
+    for view in views {
+        for node in &view.items {
+            let record = ItemRecord::new(view, node);
+            writer.write_record([
+                record.list.to_string(),
+                record.position.to_string(),
+                record.id.to_string(),
+                record.description.to_string(),
+                record.completed.to_string(),
+                record.due.map(|due| due.to_rfc3339()).unwrap_or_default(),
+                record.start.map(|start| start.to_rfc3339()).unwrap_or_default(),
+                record.priority.name().to_string(),
+                record.tags.join(" "),
+                record.created_at.map(|created_at| created_at.to_rfc3339()).unwrap_or_default(),
+                record.path,
+                record.parent.unwrap_or_default().to_string(),
+                record.recurrence.unwrap_or_default(),
+                record.updated_at.map(|updated_at| updated_at.to_rfc3339()).unwrap_or_default(),
+                record.completed_at.map(|completed_at| completed_at.to_rfc3339()).unwrap_or_default(),
+                record.notes.to_string(),
+                record.source.unwrap_or_default().to_string(),
+            ])?;
+        }
+    }
+    let data = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn render_markdown(views: &[ListView], now: DateTime<Utc>, verbose: bool) -> String {
+    let mut out = String::new();
+    for view in views {
+        out.push_str(&format!("## {}{}\n\n", view.name, source_suffix(view)));
+        for node in &view.items {
+            let item = node.item;
+            out.push_str(&format!(
+                "{}- [{}] {}{}{}{}{}{}\n",
+                indent(node), checkbox(item), item.priority.badge(), item.description, progress(node), tags::format(&item.tags), format_dates(item, now),
+                format_recurrence(item)
+            ));
+            if verbose {
+                out.push_str(&indent_notes(item, &format!("{}  ", indent(node))));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn render_table(views: &[ListView], now: DateTime<Utc>) -> String {
+    let sourced = views.iter().any(|view| view.source.is_some());
+    let mut rows = vec![["SOURCE", "LIST", "#", "ID", "DONE", "PRI", "DESCRIPTION", "TAGS", "DUE", "START", "REPEAT"].map(String::from).to_vec()];
+    for view in views {
+        for node in &view.items {
+            let item = node.item;
+            let due = item.due.map(|due| {
+                let date = format_date(due);
+                if item.is_overdue(now) { format!("{} !", date) } else { date }
+            });
+            rows.push(vec![
+                view.source.unwrap_or_default().to_string(),
+                view.name.to_string(),
+                node.reference(),
+                item.short_id().to_string(),
+                checkbox(item).to_string(),
+                if item.priority.is_none() { String::new() } else { item.priority.name().to_string() },
+                format!("{}{}{}", indent(node), item.description, progress(node)),
+                tags::format(&item.tags).trim_start().to_string(),
+                due.unwrap_or_default(),
+                item.start.map(format_date).unwrap_or_default(),
+                item.recurrence.as_ref().map(|recurrence| recurrence.describe()).unwrap_or_default(),
+            ]);
+        }
+    }
+
+    if !sourced {
+        rows.iter_mut().for_each(|row| { row.remove(0); });
+    }
+
+    let columns = rows[0].len();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Every field of the item at `node` of `list` for `todo view`, with its
+/// notes below.
+pub fn render_detail(list: &str, node: &Node, parent: Option<&Item>, now: DateTime<Utc>) -> String {
+    let item = node.item;
+    let mut fields = vec![
+        ("Task", format!("{} at {} in list '{}'", item.id, node.reference(), list)),
+        ("Description", item.description.clone()),
+        ("Status", if item.completed { "completed" } else if item.is_overdue(now) { "overdue" } else { "open" }.to_string()),
+    ];
+    if !item.priority.is_none() {
+        fields.push(("Priority", item.priority.name().to_string()));
+    }
+    if !item.tags.is_empty() {
+        fields.push(("Tags", tags::format(&item.tags).trim_start().to_string()));
+    }
+    for (label, date) in [("Start", item.start), ("Due", item.due)] {
+        if let Some(date) = date {
+            fields.push((label, format_date(date)));
+        }
+    }
+    if let Some(recurrence) = &item.recurrence {
+        fields.push(("Repeats", recurrence.describe()));
+    }
+    if let Some(parent) = parent {
+        fields.push(("Subtask of", format!("{} {}", parent.short_id(), parent.description)));
+    }
+    if let Some((done, total)) = node.progress {
+        fields.push(("Subtasks", format!("{} of {} done", done, total)));
+    }
+    for (label, date) in [("Created", item.created_at), ("Updated", item.updated_at), ("Completed", item.completed_at)] {
+        if let Some(date) = date {
+            fields.push((label, format_date(date)));
+        }
+    }
+
+    let mut out: String = fields.iter().map(|(label, value)| format!("{:<12} {}\n", format!("{}:", label), value)).collect();
+    if !item.notes.is_empty() {
+        out.push_str(&format!("\n{}\n", item.notes.trim_end()));
+    }
+    out
+}
+
+/// The item's notes with every line indented by `prefix`.
+fn indent_notes(item: &Item, prefix: &str) -> String {
+    item.notes.lines()
+        .map(|line| if line.trim().is_empty() { "\n".to_string() } else { format!("{}{}\n", prefix, line) })
+        .collect()
+}
+
+fn source_suffix(view: &ListView) -> String {
+    view.source.map(|source| format!(" ({})", source)).unwrap_or_default()
+}
+
+/// Two spaces per level of nesting.
+fn indent(node: &Node) -> String {
+    "  ".repeat(node.depth())
+}
+
+/// How many subtasks are done, e.g. ` [2/5]`, for items that have any.
+pub fn progress(node: &Node) -> String {
+    node.progress.map(|(done, total)| format!(" [{}/{}]", done, total)).unwrap_or_default()
+}
+
+fn checkbox(item: &Item) -> &'static str {
+    if item.completed { "x" } else { " " }
+}
+
+/// The priority as shown after an edit, empty if the item has none.
+pub fn format_priority(item: &Item) -> String {
+    if item.priority.is_none() {
+        String::new()
+    } else {
+        format!(" (priority {})", item.priority.name())
+    }
+}
+
+pub fn format_dates(item: &Item, now: DateTime<Utc>) -> String {
+    let mut out = String::new();
+    if let Some(start) = item.start {
+        out.push_str(&format!(" (starts {})", format_date(start)));
+    }
+    if let Some(due) = item.due {
+        let label = if item.is_overdue(now) { "OVERDUE" } else { "due" };
+        out.push_str(&format!(" ({} {})", label, format_date(due)));
+    }
+    out
+}
+
+/// How the item repeats, empty if it does not.
+pub fn format_recurrence(item: &Item) -> String {
+    item.recurrence.as_ref().map(|recurrence| format!(" (repeats {})", recurrence.describe())).unwrap_or_default()
+}
+
+pub fn format_date(date: DateTime<Utc>) -> String {
+    date.with_timezone(&Local).format("%a %Y-%m-%d %H:%M").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree;
+
+    fn sample() -> (Item, Item) {
+        let mut milk = Item::new("Milk, 2%");
+        milk.id = "aaaaaaaa1111".to_string();
+        milk.completed = true;
+        milk.completed_at = Some(DateTime::parse_from_rfc3339("2024-04-02T09:30:00Z").unwrap().with_timezone(&Utc));
+        milk.created_at = Some(DateTime::parse_from_rfc3339("2024-04-01T08:00:00Z").unwrap().with_timezone(&Utc));
+        let mut bread = Item::new("Bread");
+        bread.id = "bbbbbbbb2222".to_string();
+        bread.due = Some(DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc));
+        bread.priority = Priority::High;
+        bread.tags.insert("bakery".to_string());
+        bread.created_at = None;
+        bread.recurrence = Some("weekly on mon".parse().unwrap());
+        bread.notes = "Sourdough\n\n- sliced".to_string();
+        (milk, bread)
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let (milk, bread) = sample();
+        let bread = Item { parent: Some(milk.id.clone()), ..bread };
+        let items = [milk, Item::new("Eggs"), bread];
+        let mut nodes = tree::walk(&items);
+        nodes.remove(2);
+        let views = vec![ListView { name: "Shop", source: None, items: nodes }];
+        let now = DateTime::parse_from_rfc3339("2024-06-01T00:00:00Z").unwrap().with_timezone(&Utc);
+
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json, &views, now, false, false).unwrap()).unwrap();
+        assert_eq!(json["lists"][0]["name"], "Shop");
+        assert_eq!(json["lists"][0]["items"][0], serde_json::json!({
+            "list": "Shop",
+            "source": null,
+            "position": 1,
+            "path": "1",
+            "parent": null,
+            "id": "aaaaaaaa1111",
+            "description": "Milk, 2%",
+            "completed": true,
+            "due": null,
+            "start": null,
+            "priority": "none",
+            "tags": [],
+            "created_at": "2024-04-01T08:00:00Z",
+            "updated_at": null,
+            "completed_at": "2024-04-02T09:30:00Z",
+            "notes": "",
+            "recurrence": null
+        }));
+        assert_eq!(json["lists"][0]["items"][1]["path"], "1.1");
+        assert_eq!(json["lists"][0]["items"][1]["parent"], "aaaaaaaa1111");
+        assert_eq!(json["lists"][0]["items"][1]["due"], "2024-05-01T12:00:00Z");
+        assert_eq!(json["lists"][0]["items"][1]["priority"], "high");
+        assert_eq!(json["lists"][0]["items"][1]["tags"], serde_json::json!(["bakery"]));
+        assert_eq!(json["lists"][0]["items"][1]["recurrence"], "FREQ=WEEKLY;BYDAY=MO");
+        assert_eq!(json["lists"][0]["items"][1]["notes"], "Sourdough\n\n- sliced");
+
+        let csv = render(Format::Csv, &views, now, false, false).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "list,position,id,description,completed,due,start,priority,tags,created_at,path,parent,recurrence,updated_at,completed_at,notes,source");
+        assert_eq!(lines[1], "Shop,1,aaaaaaaa1111,\"Milk, 2%\",true,,,none,,2024-04-01T08:00:00+00:00,1,,,,2024-04-02T09:30:00+00:00,,");
+        assert_eq!(lines[2], "Shop,1,bbbbbbbb2222,Bread,false,2024-05-01T12:00:00+00:00,,high,bakery,,1.1,aaaaaaaa1111,FREQ=WEEKLY;BYDAY=MO,,,\"Sourdough");
+        assert!(csv.ends_with("- sliced\",\n"));
+
+        let markdown = render(Format::Markdown, &views, now, false, false).unwrap();
+        assert!(markdown.starts_with("## Shop\n\n- [x] Milk, 2% [0/1]\n  - [ ] !!! Bread #bakery (OVERDUE "));
+        assert!(!markdown.contains("Sourdough"));
+        let markdown = render(Format::Markdown, &views, now, false, true).unwrap();
+        assert!(markdown.ends_with("(repeats weekly on Mon)\n    Sourdough\n\n    - sliced\n\n"));
+
+        let table = render(Format::Table, &views, now, false, false).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("LIST  #    ID        DONE  PRI   DESCRIPTION     TAGS     DUE"));
+        assert!(lines[1].starts_with("Shop  1    aaaaaaaa  x           Milk, 2% [0/1]"));
+        assert!(lines[2].starts_with("Shop  1.1  bbbbbbbb        high    Bread"));
+        assert_eq!(lines[1].find("Milk").map(|column| column + 2), lines[2].find("Bread"));
+        assert!(lines[2].ends_with("weekly on Mon"));
+
+        let text = render(Format::Text, &views, now, false, false).unwrap();
+        assert!(text.starts_with("List: Shop\n  1. aaaaaaaa [x] Milk, 2% [0/1]\n    1.1. bbbbbbbb [ ] !!! Bread #bakery (OVERDUE"));
+        assert!(text.contains(" (repeats weekly on Mon)\n"));
+        let text = render(Format::Text, &views, now, false, true).unwrap();
+        assert!(text.contains(" (repeats weekly on Mon)\n         Sourdough\n\n         - sliced\n"));
+
+        let detail = render_detail("Shop", &views[0].items[1], Some(&items[0]), now);
+        assert!(detail.starts_with("Task:        bbbbbbbb2222 at 1.1 in list 'Shop'\nDescription: Bread\nStatus:      overdue\nPriority:    high\nTags:        #bakery\n"));
+        assert!(detail.contains("Subtask of:  aaaaaaaa Milk, 2%\n"));
+        assert!(detail.ends_with("\nSourdough\n\n- sliced\n"));
+    }
+
+    #[test]
+    fn test_render_sources() {
+        let (milk, bread) = sample();
+        let (shop, home) = ([milk], [bread]);
+        let views = vec![
+            ListView { name: "Shop", source: Some("project"), items: tree::walk(&shop) },
+            ListView { name: "Home", source: Some("global"), items: tree::walk(&home) },
+        ];
+        let now = Utc::now();
+
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json, &views, now, false, false).unwrap()).unwrap();
+        assert_eq!(json["lists"][1]["source"], "global");
+        assert_eq!(json["lists"][1]["items"][0]["source"], "global");
+
+        let csv = render(Format::Csv, &views, now, false, false).unwrap();
+        assert!(csv.lines().nth(1).unwrap().ends_with(",project"));
+
+        let table = render(Format::Table, &views, now, false, false).unwrap();
+        assert!(table.starts_with("SOURCE   LIST  #"));
+        assert!(table.lines().nth(2).unwrap().starts_with("global   Home  1"));
+
+        assert!(render(Format::Text, &views, now, false, false).unwrap().starts_with("List: Shop (project)\n"));
+    }
+}
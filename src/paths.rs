@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use crate::error::{TodoError, TodoResult};
+
+/// Subdirectory of the platform's data and config directories.
+const APP_DIR: &str = "todo";
+
+static DATA_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes `data_dir` return `dir` for the rest of the process, for
+/// `--data-dir`.
+pub fn override_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR_OVERRIDE.set(dir);
+}
+
+/// Where the local database and its bookkeeping files live: `--data-dir`,
+/// then `TODO_DATA_DIR`, then `todo` in the platform's data directory
+/// (`$XDG_DATA_HOME/todo` on Linux).
+pub fn data_dir() -> TodoResult<PathBuf> {
+    if let Some(dir) = DATA_DIR_OVERRIDE.get() {
+        return Ok(dir.clone());
+    }
+    from_env_or("TODO_DATA_DIR", dirs::data_dir())
+}
+
+/// Where configuration and credentials live: `TODO_CONFIG_DIR`, then `todo`
+/// in the platform's config directory (`$XDG_CONFIG_HOME/todo` on Linux).
+pub fn config_dir() -> TodoResult<PathBuf> {
+    from_env_or("TODO_CONFIG_DIR", dirs::config_dir())
+}
+
+fn from_env_or(var: &str, platform_dir: Option<PathBuf>) -> TodoResult<PathBuf> {
+    match std::env::var(var) {
+        Ok(dir) => Ok(PathBuf::from(dir)),
+        Err(_) => platform_dir
+            .map(|dir| dir.join(APP_DIR))
+            .ok_or_else(|| TodoError::ConfigError(format!("Cannot determine the home directory, set {}", var))),
+    }
+}
+
+/// Directory holding a project's own task database, like `.git`.
+pub const PROJECT_DIR: &str = ".todo";
+
+/// Files in a project directory that are per-user or transient and should
+/// stay out of version control.
+const PROJECT_GITIGNORE: &str = "*.lock\n*.tmp\n*.bak\njournal.json\nsync_base_*.json\ngoogle_task_ids.json\nsearch_index/\n";
+
+/// The `.todo` directory in `start` or its nearest ancestor that has one.
+pub fn find_project_dir(start: &Path) -> Option<PathBuf> {
+    start.ancestors()
+        .map(|dir| dir.join(PROJECT_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+/// Creates `.todo` in `dir` and returns it.
+pub fn init_project(dir: &Path) -> TodoResult<PathBuf> {
+    let project = dir.join(PROJECT_DIR);
+    if project.exists() {
+        return Err(TodoError::ConfigError(format!("{} already exists", project.display())));
+    }
+    std::fs::create_dir(&project)?;
+    std::fs::write(project.join(".gitignore"), PROJECT_GITIGNORE)?;
+    Ok(project)
+}
+
+/// Copies a file written by versions that kept everything in the working
+/// directory, unless `target` already exists. Returns whether it copied.
+pub fn adopt_legacy_file(legacy: &Path, target: &Path) -> TodoResult<bool> {
+    if target.exists() || !legacy.is_file() {
+        return Ok(false);
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(legacy, target)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adopt_legacy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("local_db.json");
+        let target = dir.path().join("data").join("todo").join("local_db.json");
+
+        assert!(!adopt_legacy_file(&legacy, &target).unwrap());
+
+        std::fs::write(&legacy, "{}").unwrap();
+        assert!(adopt_legacy_file(&legacy, &target).unwrap());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "{}");
+
+        // Only ever once: later changes in the working directory are ignored.
+        std::fs::write(&legacy, "{\"Newer\": []}").unwrap();
+        assert!(!adopt_legacy_file(&legacy, &target).unwrap());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_find_project_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src").join("deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_dir(&nested), None);
+
+        let project = init_project(dir.path()).unwrap();
+        assert_eq!(find_project_dir(&nested), Some(project.clone()));
+        assert_eq!(find_project_dir(dir.path()), Some(project));
+        assert!(matches!(init_project(dir.path()), Err(TodoError::ConfigError(_))));
+
+        // The nearest project wins.
+        let inner = init_project(&nested).unwrap();
+        assert_eq!(find_project_dir(&nested), Some(inner));
+    }
+}
@@ -0,0 +1,531 @@
+//! Recurrence rules of repeating items: a subset of RFC 5545 RRULEs, also
+//! written as `daily`, `weekly on mon,wed`, `monthly on the 15th` or
+//! `every 3 days after completion`.
+//!
+//! Rules are stored as RRULE strings. `X-FROM=COMPLETION`, which RFC 5545
+//! lacks, marks rules counting from when an item was completed rather than
+//! from its due date. Ordinal weekdays such as `2TU` count within the month.
+
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, TimeZone, Weekday};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::dates;
+use crate::error::{TodoError, TodoResult};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Freq {
+    fn name(self) -> &'static str {
+        match self {
+            Freq::Daily => "DAILY",
+            Freq::Weekly => "WEEKLY",
+            Freq::Monthly => "MONTHLY",
+            Freq::Yearly => "YEARLY",
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            Freq::Daily => "day",
+            Freq::Weekly => "week",
+            Freq::Monthly => "month",
+            Freq::Yearly => "year",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recurrence {
+    pub freq: Freq,
+    pub interval: u32,
+    /// Weekdays, optionally the nth (or nth to last if negative) of the month.
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    /// Days of the month, negative ones counting from its end.
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    /// Occurrences left, including the current one.
+    pub count: Option<u32>,
+    /// Last day an occurrence may fall on.
+    pub until: Option<NaiveDate>,
+    /// Whether the next occurrence counts from the completion rather than
+    /// the due date.
+    pub after_completion: bool,
+}
+
+impl Recurrence {
+    fn every(freq: Freq, interval: u32) -> Self {
+        Self {
+            freq,
+            interval,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            count: None,
+            until: None,
+            after_completion: false,
+        }
+    }
+
+    /// The rule for the occurrence after this one.
+    pub fn following(&self) -> Self {
+        Self { count: self.count.map(|count| count.saturating_sub(1)), ..self.clone() }
+    }
+
+    /// The due date of the occurrence after the one due `due` and completed
+    /// at `completed`, or `None` once the rule has run out. Occurrences that
+    /// are already past are skipped. Items without a due date repeat from
+    /// the completion and fall due at the end of the day.
+    pub fn next<Tz: TimeZone>(&self, due: Option<&DateTime<Tz>>, completed: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        if self.count.is_some_and(|count| count <= 1) {
+            return None;
+        }
+        let time = due.map_or(dates::end_of_day(), |due| due.naive_local().time());
+        let today = completed.naive_local().date();
+        let date = if self.after_completion {
+            self.advance(today)?
+        } else {
+            let anchor = due.map_or(today, |due| due.naive_local().date());
+            let mut date = self.after(anchor, anchor)?;
+            while date.and_time(time) <= completed.naive_local() {
+                date = self.after(date, anchor)?;
+            }
+            date
+        };
+        if self.until.is_some_and(|until| date > until) {
+            return None;
+        }
+        completed.timezone().from_local_datetime(&date.and_time(time)).earliest()
+    }
+
+    /// `date` moved on by the interval.
+    fn advance(&self, date: NaiveDate) -> Option<NaiveDate> {
+        match self.freq {
+            Freq::Daily => date.checked_add_signed(Duration::days(self.interval as i64)),
+            Freq::Weekly => date.checked_add_signed(Duration::weeks(self.interval as i64)),
+            Freq::Monthly => date.checked_add_months(Months::new(self.interval)),
+            Freq::Yearly => date.checked_add_months(Months::new(self.interval * 12)),
+        }
+    }
+
+    /// The first occurrence strictly after `date` of the rule started on
+    /// `anchor`.
+    fn after(&self, date: NaiveDate, anchor: NaiveDate) -> Option<NaiveDate> {
+        // Long enough for any rule that can match at all, such as 29 February.
+        let limit = 366 * 8 * self.interval as i64;
+        (1..=limit)
+            .map_while(|days| date.checked_add_signed(Duration::days(days)))
+            .find(|day| self.matches(*day, anchor))
+    }
+
+    fn matches(&self, day: NaiveDate, anchor: NaiveDate) -> bool {
+        let interval = self.interval as i64;
+        let months = |date: NaiveDate| date.year() as i64 * 12 + date.month0() as i64;
+        let in_period = match self.freq {
+            Freq::Daily => (day - anchor).num_days() % interval == 0,
+            Freq::Weekly => {
+                let monday = |date: NaiveDate| date - Duration::days(date.weekday().num_days_from_monday() as i64);
+                ((monday(day) - monday(anchor)).num_days() / 7) % interval == 0
+            }
+            Freq::Monthly => (months(day) - months(anchor)) % interval == 0,
+            Freq::Yearly => (day.year() - anchor.year()) as i64 % interval == 0,
+        };
+        if !in_period {
+            return false;
+        }
+
+        let month_ok = match (self.freq, self.by_month.is_empty()) {
+            (_, false) => self.by_month.contains(&day.month()),
+            (Freq::Yearly, true) => day.month() == anchor.month(),
+            _ => true,
+        };
+        let last = days_in_month(day) as i32;
+        let month_day_ok = self.by_month_day.iter().any(|&n| n == day.day() as i32 || n == day.day() as i32 - last - 1);
+        let weekday_ok = self.by_day.iter().any(|&(ordinal, weekday)| {
+            weekday == day.weekday() && match ordinal {
+                None => true,
+                Some(n) if n > 0 => (day.day() as i32 - 1) / 7 + 1 == n,
+                Some(n) => (last - day.day() as i32) / 7 + 1 == -n,
+            }
+        });
+        let day_ok = match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+            (false, false) => month_day_ok && weekday_ok,
+            (false, true) => month_day_ok,
+            (true, false) => weekday_ok,
+            (true, true) => match self.freq {
+                Freq::Daily => true,
+                Freq::Weekly => day.weekday() == anchor.weekday(),
+                Freq::Monthly | Freq::Yearly => day.day() == anchor.day(),
+            },
+        };
+        month_ok && day_ok
+    }
+
+    /// How `todo show` describes the rule, e.g. `weekly on Mon, Wed`.
+    pub fn describe(&self) -> String {
+        let mut out = match (self.freq, self.interval) {
+            (Freq::Daily, 1) => "daily".to_string(),
+            (Freq::Weekly, 1) => "weekly".to_string(),
+            (Freq::Monthly, 1) => "monthly".to_string(),
+            (Freq::Yearly, 1) => "yearly".to_string(),
+            (freq, interval) => format!("every {} {}s", interval, freq.unit()),
+        };
+        if self.by_day.len() == 5 && WEEKDAYS.iter().all(|weekday| self.by_day.contains(&(None, *weekday))) {
+            out.push_str(" on weekdays");
+        } else if !self.by_day.is_empty() {
+            let days: Vec<String> = self.by_day.iter()
+                .map(|&(ordinal, weekday)| match ordinal {
+                    None => weekday.to_string(),
+                    Some(-1) => format!("last {}", weekday),
+                    Some(n) if n < 0 => format!("{} to last {}", ordinal_name(-n), weekday),
+                    Some(n) => format!("{} {}", ordinal_name(n), weekday),
+                })
+                .collect();
+            out.push_str(&format!(" on {}", days.join(", ")));
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter()
+                .map(|&n| match n {
+                    -1 => "last day".to_string(),
+                    n if n < 0 => format!("{} to last day", ordinal_name(-n)),
+                    n => ordinal_name(n),
+                })
+                .collect();
+            out.push_str(&format!(" on the {}", days.join(", ")));
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<&str> = self.by_month.iter().map(|&month| MONTHS[month as usize - 1]).collect();
+            out.push_str(&format!(" in {}", months.join(", ")));
+        }
+        if self.after_completion {
+            out.push_str(" after completion");
+        }
+        if let Some(until) = self.until {
+            out.push_str(&format!(" until {}", until));
+        }
+        if let Some(count) = self.count {
+            out.push_str(&format!(", {} left", count));
+        }
+        out
+    }
+
+    /// Parses an RRULE such as `FREQ=WEEKLY;BYDAY=MO,WE`, with or without
+    /// its `RRULE:` prefix.
+    fn parse_rrule(input: &str) -> TodoResult<Self> {
+        let invalid = |reason: &str| TodoError::InvalidRecurrence(format!("{} in '{}'", reason, input));
+        let rule = input.trim();
+        let rule = if rule.len() >= 6 && rule[..6].eq_ignore_ascii_case("rrule:") { &rule[6..] } else { rule };
+
+        let mut freq = None;
+        let mut recurrence = Recurrence::every(Freq::Daily, 1);
+        for part in rule.split(';').map(str::trim).filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| invalid("expected KEY=VALUE"))?;
+            let value = value.trim().to_uppercase();
+            let values = || value.split(',').map(str::trim);
+            match key.trim().to_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.as_str() {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        "YEARLY" => Freq::Yearly,
+                        _ => return Err(invalid("unsupported FREQ")),
+                    })
+                }
+                "INTERVAL" => recurrence.interval = value.parse().ok().filter(|&n| n >= 1).ok_or_else(|| invalid("bad INTERVAL"))?,
+                "COUNT" => recurrence.count = Some(value.parse().ok().filter(|&n| n >= 1).ok_or_else(|| invalid("bad COUNT"))?),
+                "UNTIL" => {
+                    let date = value.get(..8).and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok());
+                    recurrence.until = Some(date.ok_or_else(|| invalid("bad UNTIL"))?);
+                }
+                "BYDAY" => recurrence.by_day = values().map(parse_byday).collect::<Option<_>>().ok_or_else(|| invalid("bad BYDAY"))?,
+                "BYMONTHDAY" => {
+                    recurrence.by_month_day = values()
+                        .map(|day| day.parse().ok().filter(|day: &i32| (1..=31).contains(&day.abs())))
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| invalid("bad BYMONTHDAY"))?
+                }
+                "BYMONTH" => {
+                    recurrence.by_month = values()
+                        .map(|month| month.parse().ok().filter(|month| (1..=12).contains(month)))
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| invalid("bad BYMONTH"))?
+                }
+                "WKST" if value == "MO" => {}
+                "X-FROM" if value == "COMPLETION" => recurrence.after_completion = true,
+                key => return Err(invalid(&format!("unsupported part {}", key))),
+            }
+        }
+        recurrence.freq = freq.ok_or_else(|| invalid("missing FREQ"))?;
+        recurrence.check().map_err(|reason| invalid(&reason))?;
+        Ok(recurrence)
+    }
+
+    /// Rejects combinations the rule cannot honour.
+    fn check(&self) -> Result<(), String> {
+        if self.count.is_some() && self.until.is_some() {
+            return Err("COUNT and UNTIL exclude each other".to_string());
+        }
+        if self.after_completion && !(self.by_day.is_empty() && self.by_month_day.is_empty() && self.by_month.is_empty()) {
+            return Err("rules repeating after completion cannot pick days".to_string());
+        }
+        if self.freq == Freq::Weekly && self.by_day.iter().any(|(ordinal, _)| ordinal.is_some()) {
+            return Err("weekly rules cannot number weekdays".to_string());
+        }
+        Ok(())
+    }
+}
+
+const WEEKDAYS: [Weekday; 5] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let first = date.with_day(1).unwrap();
+    let next = first.checked_add_months(Months::new(1)).unwrap_or(first);
+    (next - first).num_days() as u32
+}
+
+fn ordinal_name(n: i32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (1, 11) | (2, 12) | (3, 13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// Parses a BYDAY value such as `MO`, `2TU` or `-1FR`.
+fn parse_byday(value: &str) -> Option<(Option<i32>, Weekday)> {
+    let (ordinal, day) = value.split_at(value.len().checked_sub(2)?);
+    let weekday = match day {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let ordinal = match ordinal {
+        "" => None,
+        ordinal => Some(ordinal.parse::<i32>().ok().filter(|n| (1..=5).contains(&n.abs()))?),
+    };
+    Some((ordinal, weekday))
+}
+
+fn byday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Parses a day of the month such as `15`, `15th` or `last`.
+fn parse_month_day(word: &str) -> Option<i32> {
+    if word == "last" {
+        return Some(-1);
+    }
+    let digits = word.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+impl FromStr for Recurrence {
+    type Err = TodoError;
+
+    /// Parses an RRULE or one of `daily`, `weekly`, `monthly`, `yearly`,
+    /// `weekdays` and `every N days|weeks|months|years`, optionally followed
+    /// by `on` and weekdays or days of the month and by `after completion`.
+    fn from_str(input: &str) -> TodoResult<Self> {
+        let lowered = input.trim().to_lowercase();
+        if lowered.starts_with("rrule:") || lowered.contains("freq=") {
+            return Self::parse_rrule(input);
+        }
+        let invalid = || TodoError::InvalidRecurrence(input.to_string());
+
+        let (rule, after_completion) = match lowered.strip_suffix("after completion") {
+            Some(rule) => (rule, true),
+            None => (lowered.as_str(), false),
+        };
+        let words: Vec<&str> = rule.split(|c: char| c.is_whitespace() || c == ',').filter(|word| !word.is_empty()).collect();
+        let unit = |word: &str| match word.trim_end_matches('s') {
+            "day" => Some(Freq::Daily),
+            "week" => Some(Freq::Weekly),
+            "month" => Some(Freq::Monthly),
+            "year" => Some(Freq::Yearly),
+            _ => None,
+        };
+
+        let (mut recurrence, rest) = match words.as_slice() {
+            ["daily", rest @ ..] => (Recurrence::every(Freq::Daily, 1), rest),
+            ["weekly", rest @ ..] => (Recurrence::every(Freq::Weekly, 1), rest),
+            ["monthly", rest @ ..] => (Recurrence::every(Freq::Monthly, 1), rest),
+            ["yearly" | "annually", rest @ ..] => (Recurrence::every(Freq::Yearly, 1), rest),
+            ["weekdays"] | ["every", "weekday"] => {
+                let weekdays = WEEKDAYS.iter().map(|weekday| (None, *weekday)).collect();
+                (Recurrence { by_day: weekdays, ..Recurrence::every(Freq::Weekly, 1) }, &[][..])
+            }
+            ["every", n, word, rest @ ..] if n.parse::<u32>().is_ok() => {
+                let interval = n.parse().ok().filter(|&n| n >= 1).ok_or_else(invalid)?;
+                (Recurrence::every(unit(word).ok_or_else(invalid)?, interval), rest)
+            }
+            ["every", word, rest @ ..] if unit(word).is_some() => (Recurrence::every(unit(word).unwrap(), 1), rest),
+            // `every mon wed` is `weekly on mon wed`.
+            ["every", days @ ..] if !days.is_empty() => (Recurrence::every(Freq::Weekly, 1), days),
+            _ => return Err(invalid()),
+        };
+
+        let rest = match rest {
+            ["on", "the", rest @ ..] | ["on", rest @ ..] => rest,
+            rest if words.first() == Some(&"every") && recurrence.freq == Freq::Weekly && recurrence.by_day.is_empty() => rest,
+            [] => rest,
+            _ => return Err(invalid()),
+        };
+        if !rest.is_empty() {
+            if let Some(days) = rest.iter().map(|word| dates::parse_weekday(word)).collect::<Option<Vec<_>>>() {
+                if recurrence.freq == Freq::Daily {
+                    return Err(invalid());
+                }
+                recurrence.by_day = days.into_iter().map(|day| (None, day)).collect();
+            } else if let Some(days) = rest.iter().map(|word| parse_month_day(word)).collect::<Option<Vec<_>>>() {
+                if !matches!(recurrence.freq, Freq::Monthly | Freq::Yearly) {
+                    return Err(invalid());
+                }
+                recurrence.by_month_day = days;
+            } else {
+                return Err(invalid());
+            }
+        }
+        recurrence.after_completion = after_completion;
+        recurrence.check().map_err(|reason| TodoError::InvalidRecurrence(format!("{} in '{}'", reason, input)))?;
+        Ok(recurrence)
+    }
+}
+
+/// The rule as an RRULE without its `RRULE:` prefix.
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |values: Vec<String>| values.join(",");
+        let mut parts = vec![format!("FREQ={}", self.freq.name())];
+        if self.interval != 1 {
+            parts.push(format!("INTERVAL={}", self.interval));
+        }
+        if !self.by_day.is_empty() {
+            let days = self.by_day.iter()
+                .map(|&(ordinal, weekday)| format!("{}{}", ordinal.map(|n| n.to_string()).unwrap_or_default(), byday_code(weekday)))
+                .collect();
+            parts.push(format!("BYDAY={}", join(days)));
+        }
+        if !self.by_month_day.is_empty() {
+            parts.push(format!("BYMONTHDAY={}", join(self.by_month_day.iter().map(i32::to_string).collect())));
+        }
+        if !self.by_month.is_empty() {
+            parts.push(format!("BYMONTH={}", join(self.by_month.iter().map(u32::to_string).collect())));
+        }
+        if let Some(count) = self.count {
+            parts.push(format!("COUNT={}", count));
+        }
+        if let Some(until) = self.until {
+            parts.push(format!("UNTIL={}", until.format("%Y%m%d")));
+        }
+        if self.after_completion {
+            parts.push("X-FROM=COMPLETION".to_string());
+        }
+        write!(f, "{}", parts.join(";"))
+    }
+}
+
+impl Serialize for Recurrence {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Recurrence {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    /// The due dates of the occurrences after one due `due`, each completed
+    /// on time.
+    fn next_dues(rule: &str, due: &str, n: usize) -> Vec<String> {
+        let mut recurrence: Recurrence = rule.parse().unwrap();
+        let mut due = at(due);
+        let mut dues = Vec::new();
+        while dues.len() < n {
+            let Some(next) = recurrence.next(Some(&due), &(due - Duration::hours(1))) else {
+                break;
+            };
+            dues.push(next.format("%a %Y-%m-%d %H:%M").to_string());
+            due = next;
+            recurrence = recurrence.following();
+        }
+        dues
+    }
+
+    #[test]
+    fn test_recurrence() {
+        // 2024-05-01 is a Wednesday.
+        assert_eq!(next_dues("daily", "2024-05-01T09:00:00Z", 2), ["Thu 2024-05-02 09:00", "Fri 2024-05-03 09:00"]);
+        assert_eq!(next_dues("weekly on mon, fri", "2024-05-01T09:00:00Z", 3), ["Fri 2024-05-03 09:00", "Mon 2024-05-06 09:00", "Fri 2024-05-10 09:00"]);
+        assert_eq!(next_dues("every 2 weeks", "2024-05-01T09:00:00Z", 1), ["Wed 2024-05-15 09:00"]);
+        assert_eq!(next_dues("weekdays", "2024-05-03T09:00:00Z", 1), ["Mon 2024-05-06 09:00"]);
+        assert_eq!(next_dues("monthly on the 31st", "2024-01-31T09:00:00Z", 2), ["Sun 2024-03-31 09:00", "Fri 2024-05-31 09:00"]);
+        assert_eq!(next_dues("monthly on last", "2024-01-31T09:00:00Z", 2), ["Thu 2024-02-29 09:00", "Sun 2024-03-31 09:00"]);
+        assert_eq!(next_dues("yearly", "2024-02-29T09:00:00Z", 1), ["Tue 2028-02-29 09:00"]);
+        assert_eq!(next_dues("RRULE:FREQ=MONTHLY;BYDAY=-1FR;COUNT=3", "2024-05-31T09:00:00Z", 5), ["Fri 2024-06-28 09:00", "Fri 2024-07-26 09:00"]);
+        assert_eq!(next_dues("FREQ=MONTHLY;INTERVAL=2;BYDAY=2TU;UNTIL=20240801", "2024-05-14T09:00:00Z", 5), ["Tue 2024-07-09 09:00"]);
+
+        // Counting from the completion, and skipping occurrences already past.
+        let completed = at("2024-05-10T18:00:00Z");
+        let after: Recurrence = "every 3 days after completion".parse().unwrap();
+        assert_eq!(after.next(Some(&at("2024-05-01T09:00:00Z")), &completed), Some(at("2024-05-13T09:00:00Z")));
+        let weekly: Recurrence = "weekly".parse().unwrap();
+        assert_eq!(weekly.next(Some(&at("2024-05-01T09:00:00Z")), &completed), Some(at("2024-05-15T09:00:00Z")));
+        assert_eq!(weekly.next(None, &completed), Some(at("2024-05-17T23:59:00Z")));
+
+        // Round trips through the stored RRULE and descriptions.
+        for (input, rrule, description) in [
+            ("every 3 days after completion", "FREQ=DAILY;INTERVAL=3;X-FROM=COMPLETION", "every 3 days after completion"),
+            ("Weekly on Mon,Wed", "FREQ=WEEKLY;BYDAY=MO,WE", "weekly on Mon, Wed"),
+            ("every fri", "FREQ=WEEKLY;BYDAY=FR", "weekly on Fri"),
+            ("weekdays", "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR", "weekly on weekdays"),
+            ("monthly on the 15th", "FREQ=MONTHLY;BYMONTHDAY=15", "monthly on the 15th"),
+            ("rrule:freq=yearly;bymonth=3;bymonthday=-1;count=2", "FREQ=YEARLY;BYMONTHDAY=-1;BYMONTH=3;COUNT=2", "yearly on the last day in Mar, 2 left"),
+        ] {
+            let recurrence: Recurrence = input.parse().unwrap();
+            assert_eq!(recurrence.to_string(), rrule);
+            assert_eq!(recurrence.describe(), description);
+            assert_eq!(rrule.parse::<Recurrence>().unwrap(), recurrence);
+        }
+
+        for bad in ["", "sometimes", "every 0 days", "daily on mon", "weekly on 15", "monthly on mon after completion",
+                    "FREQ=HOURLY", "FREQ=DAILY;BYSETPOS=1", "FREQ=DAILY;COUNT=2;UNTIL=20240101", "FREQ=WEEKLY;BYDAY=1MO", "INTERVAL=2"] {
+            assert!(matches!(bad.parse::<Recurrence>(), Err(TodoError::InvalidRecurrence(_))), "{:?} should not parse", bad);
+        }
+    }
+}
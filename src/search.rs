@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use crate::error::{TodoError, TodoResult};
+use crate::files::{self, FileLock};
+use crate::models::{List, SHORT_ID_LEN};
+use crate::tree;
+
+/// Name of the index directory in the directory of the local database.
+pub const INDEX_DIR: &str = "search_index";
+
+/// Written to the index directory once it is complete, holding
+/// `INDEX_VERSION`.
+const VERSION_FILE: &str = "version";
+
+/// Bumped whenever the index format or tokenization changes, so that older
+/// indexes are rebuilt rather than misread.
+const INDEX_VERSION: u32 = 4;
+
+/// How `todo search` matches the query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Every query word starts a word of the item or its notes, ignoring
+    /// case.
+    Words,
+    /// The query's characters appear in order in the description or a tag,
+    /// ignoring case.
+    Fuzzy,
+    /// A regular expression, ignoring case, matched against the notes too.
+    Regex,
+}
+
+/// A searchable item, as indexed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Doc {
+    id: String,
+    /// Path among the list's live items, as in `todo show`, e.g. `3.2`.
+    path: String,
+    /// Place in the list as `todo show` lists it.
+    order: usize,
+    completed: bool,
+    description: String,
+    tags: Vec<String>,
+    #[serde(default)]
+    notes: String,
+}
+
+impl Doc {
+    /// Words of the description and tags.
+    fn title_words(&self) -> BTreeSet<String> {
+        let mut words = words(&self.description);
+        words.extend(self.tags.iter().cloned());
+        words
+    }
+
+    fn words(&self) -> BTreeSet<String> {
+        let mut all = self.title_words();
+        all.extend(words(&self.notes));
+        all
+    }
+
+    /// The first line of the notes that `matches`, trimmed.
+    fn note_line(&self, matches: impl Fn(&str) -> bool) -> Option<String> {
+        self.notes.lines().map(str::trim).find(|line| matches(line)).map(str::to_string)
+    }
+}
+
+/// What is indexed for one live list, kept in a file of its own so that a
+/// change to the list rewrites only that file.
+#[derive(Serialize, Deserialize, Default)]
+struct Segment {
+    list: String,
+    /// Live items of the list by ID.
+    docs: BTreeMap<String, Doc>,
+    /// Word to the IDs of the items containing it.
+    words: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Segment {
+    /// Indexes the live items of `list`, or `None` if it is in the trash.
+    fn build(list: &List) -> Option<Self> {
+        if list.trashed.is_some() {
+            return None;
+        }
+        let live: Vec<_> = list.items.iter().filter(|item| item.trashed.is_none()).cloned().collect();
+        let mut segment = Segment { list: list.name.clone(), ..Segment::default() };
+        for (order, node) in tree::walk(&live).into_iter().enumerate() {
+            let doc = Doc {
+                id: node.item.id.clone(),
+                path: node.reference(),
+                order,
+                completed: node.item.completed,
+                description: node.item.description.clone(),
+                tags: node.item.tags.iter().cloned().collect(),
+                notes: node.item.notes.clone(),
+            };
+            for word in doc.words() {
+                segment.words.entry(word).or_default().insert(doc.id.clone());
+            }
+            segment.docs.insert(doc.id.clone(), doc);
+        }
+        Some(segment)
+    }
+
+    /// Items containing every word of `query` as a word prefix, scored by
+    /// how many words match exactly. Words found only in the notes count
+    /// least.
+    fn search_words(&self, query: &str) -> Vec<(u32, &Doc)> {
+        let mut scores: Option<BTreeMap<&str, u32>> = None;
+        for query_word in words(query) {
+            let mut matched: BTreeMap<&str, u32> = BTreeMap::new();
+            for (word, ids) in self.words.range(query_word.clone()..).take_while(|(word, _)| word.starts_with(&query_word)) {
+                for id in ids {
+                    let in_title = self.docs.get(id).is_some_and(|doc| doc.title_words().contains(word));
+                    let score = match (in_title, *word == query_word) {
+                        (false, _) => 1,
+                        (true, true) => 3,
+                        (true, false) => 2,
+                    };
+                    let best = matched.entry(id.as_str()).or_default();
+                    *best = (*best).max(score);
+                }
+            }
+            scores = Some(match scores {
+                None => matched,
+                Some(scores) => scores.into_iter()
+                    .filter_map(|(id, score)| matched.get(id).map(|extra| (id, score + extra)))
+                    .collect(),
+            });
+        }
+        scores.unwrap_or_default().into_iter()
+            .filter_map(|(id, score)| Some((score, self.docs.get(id)?)))
+            .collect()
+    }
+}
+
+/// The segments of every live list, by list name.
+struct Index {
+    lists: BTreeMap<String, Segment>,
+}
+
+impl Index {
+    fn search_words(&self, query: &str) -> Vec<(u32, &str, &Doc)> {
+        self.lists.iter()
+            .flat_map(|(list, segment)| segment.search_words(query).into_iter().map(move |(score, doc)| (score, list.as_str(), doc)))
+            .collect()
+    }
+
+    fn search_fuzzy(&self, query: &str) -> Vec<(u32, &str, &Doc)> {
+        let query = query.to_lowercase();
+        self.docs()
+            .filter_map(|(list, doc)| {
+                let score = fuzzy_score(&query, &doc.description.to_lowercase())
+                    .max(doc.tags.iter().filter_map(|tag| fuzzy_score(&query, tag)).max())?;
+                Some((score, list, doc))
+            })
+            .collect()
+    }
+
+    fn search_regex(&self, query: &str) -> TodoResult<Vec<(u32, &str, &Doc)>> {
+        let regex = build_regex(query)?;
+        Ok(self.docs()
+            .filter_map(|(list, doc)| {
+                let matches = regex.find_iter(&doc.description).count()
+                    + doc.tags.iter().filter(|tag| regex.is_match(tag)).count()
+                    + regex.find_iter(&doc.notes).count();
+                (matches > 0).then_some((matches as u32, list, doc))
+            })
+            .collect())
+    }
+
+    fn docs(&self) -> impl Iterator<Item = (&str, &Doc)> {
+        self.lists.iter().flat_map(|(list, segment)| segment.docs.values().map(move |doc| (list.as_str(), doc)))
+    }
+}
+
+fn build_regex(query: &str) -> TodoResult<regex::Regex> {
+    RegexBuilder::new(query)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| TodoError::InvalidFilter(e.to_string()))
+}
+
+/// Lowercase alphanumeric words of `text`.
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Scores how well the characters of `query` appear in order in `text`,
+/// rewarding runs of consecutive characters and matches at word starts.
+/// Both must already be lowercase.
+fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let mut chars = text.char_indices();
+    let mut last_char = ' ';
+    for wanted in query.chars().filter(|c| !c.is_whitespace()) {
+        loop {
+            let (index, c) = chars.next()?;
+            let at_word_start = !last_char.is_alphanumeric();
+            last_char = c;
+            if c == wanted {
+                score += 1;
+                if previous.is_some_and(|previous| previous + 1 == index) {
+                    score += 3;
+                }
+                if at_word_start {
+                    score += 2;
+                }
+                previous = Some(index);
+                break;
+            }
+        }
+    }
+    Some(score)
+}
+
+/// An item found by a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub list: String,
+    /// Path of the item as `todo show` prints it.
+    pub path: String,
+    pub id: String,
+    pub completed: bool,
+    pub description: String,
+    pub tags: BTreeSet<String>,
+    /// The first line of the item's notes that matched, if any did.
+    pub note: Option<String>,
+}
+
+impl Hit {
+    pub fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(SHORT_ID_LEN)]
+    }
+}
+
+/// `name` made safe to use as a file name on any platform: characters other
+/// than lowercase ASCII letters, digits, `-` and `_` are percent-encoded.
+fn file_name(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// An index of the live items of a database for `todo search`, kept in a
+/// directory next to it with a file per list. `Database` rewrites the files
+/// of the lists each change touches; if the index is missing or outdated,
+/// the next search rebuilds it.
+pub struct SearchIndex {
+    dir: PathBuf,
+}
+
+impl SearchIndex {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf() }
+    }
+
+    /// Whether the index was built completely, by this version.
+    fn is_current(&self) -> bool {
+        std::fs::read_to_string(self.dir.join(VERSION_FILE)).ok()
+            .and_then(|version| version.trim().parse().ok())
+            == Some(INDEX_VERSION)
+    }
+
+    fn segment_path(&self, list_name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", file_name(list_name)))
+    }
+
+    /// Reads the index, or `None` if it has to be rebuilt.
+    fn load(&self) -> Option<Index> {
+        if !self.is_current() {
+            return None;
+        }
+        let mut lists = BTreeMap::new();
+        for entry in std::fs::read_dir(&self.dir).ok()? {
+            let path = entry.ok()?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                let segment: Segment = serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+                lists.insert(segment.list.clone(), segment);
+            }
+        }
+        Some(Index { lists })
+    }
+
+    /// Indexes the list `name` as `list`, which may be absent or in the
+    /// trash.
+    fn save_segment(&self, name: &str, list: Option<&List>) -> TodoResult<()> {
+        let path = self.segment_path(name);
+        match list.and_then(Segment::build) {
+            Some(segment) => files::write_atomic(&path, serde_json::to_string(&segment)?.as_bytes())?,
+            None => match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// Reindexes the lists named in `changed` from their new versions in
+    /// `lists`; a name missing there means the list is gone. Only their
+    /// files are written.
+    pub fn update(&self, changed: &BTreeSet<&str>, lists: &[List]) -> TodoResult<()> {
+        let _lock = FileLock::acquire(&self.dir)?;
+        if !self.is_current() {
+            return Ok(());
+        }
+        for name in changed {
+            self.save_segment(name, lists.iter().find(|list| list.name == *name))?;
+        }
+        Ok(())
+    }
+
+    /// Marks the index as outdated, so that the next search rebuilds it.
+    pub fn invalidate(&self) -> TodoResult<()> {
+        match std::fs::remove_file(self.dir.join(VERSION_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Indexes `lists` from scratch.
+    pub fn rebuild(&self, lists: &[List]) -> TodoResult<()> {
+        let _lock = FileLock::acquire(&self.dir)?;
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        // Versions before segments kept the whole index in one file.
+        let _ = std::fs::remove_file(files::sibling(&self.dir, "json"));
+        std::fs::create_dir_all(&self.dir)?;
+        for list in lists {
+            self.save_segment(&list.name, Some(list))?;
+        }
+        files::write_atomic(&self.dir.join(VERSION_FILE), INDEX_VERSION.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Finds items matching `query`, best first, or `None` if there is no
+    /// index yet. Ties keep the order of `todo show`.
+    pub fn search(&self, query: &str, mode: Mode) -> TodoResult<Option<Vec<Hit>>> {
+        let _lock = FileLock::acquire(&self.dir)?;
+        let Some(index) = self.load() else {
+            return Ok(None);
+        };
+        let mut found = match mode {
+            Mode::Words => index.search_words(query),
+            Mode::Fuzzy => index.search_fuzzy(query),
+            Mode::Regex => index.search_regex(query)?,
+        };
+        let query_words = words(query);
+        let regex = if mode == Mode::Regex { Some(build_regex(query)?) } else { None };
+        let note_matches = |line: &str| match mode {
+            Mode::Words => words(line).iter().any(|word| query_words.iter().any(|query_word| word.starts_with(query_word.as_str()))),
+            Mode::Fuzzy => false,
+            Mode::Regex => regex.as_ref().is_some_and(|regex| regex.is_match(line)),
+        };
+        // Open items matter more than completed ones with the same score.
+        found.sort_by_key(|(score, list, doc)| (std::cmp::Reverse((*score, !doc.completed)), *list, doc.order));
+        Ok(Some(found.into_iter()
+            .map(|(_, list, doc)| Hit {
+                list: list.to_string(),
+                path: doc.path.clone(),
+                id: doc.id.clone(),
+                completed: doc.completed,
+                description: doc.description.clone(),
+                tags: doc.tags.iter().cloned().collect(),
+                note: doc.note_line(note_matches),
+            })
+            .collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Item;
+
+    fn list(name: &str, descriptions: &[&str]) -> List {
+        List { name: name.to_string(), items: descriptions.iter().map(|d| Item::from_input(d)).collect(), trashed: None, created_at: None, updated_at: None }
+    }
+
+    fn found(index: &SearchIndex, query: &str, mode: Mode) -> Vec<String> {
+        index.search(query, mode).unwrap().unwrap().into_iter()
+            .map(|hit| format!("{}:{}", hit.list, hit.path))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_modes_and_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = SearchIndex::new(&dir.path().join(INDEX_DIR));
+        let work = list("Work", &["Deploy the release", "Write deployment notes #docs", "Fix login bug"]);
+        let home = list("Home", &["Deploy new shelves", "Call plumber +deploy"]);
+
+        assert_eq!(index.search("deploy", Mode::Words).unwrap(), None);
+        index.update(&BTreeSet::from(["Work"]), std::slice::from_ref(&work)).unwrap();
+        assert_eq!(index.search("deploy", Mode::Words).unwrap(), None);
+
+        index.rebuild(&[work.clone(), home.clone()]).unwrap();
+        // Exact words rank above prefixes; ties keep list order.
+        assert_eq!(found(&index, "DEPLOY", Mode::Words), vec!["Home:1", "Home:2", "Work:1", "Work:2"]);
+        assert_eq!(found(&index, "deploy notes", Mode::Words), vec!["Work:2"]);
+        assert_eq!(found(&index, "ploy", Mode::Words), Vec::<String>::new());
+        assert_eq!(found(&index, "fxlgn", Mode::Fuzzy), vec!["Work:3"]);
+        assert_eq!(found(&index, "^(fix|call) ", Mode::Regex), vec!["Home:2", "Work:3"]);
+        assert!(matches!(index.search("(", Mode::Regex), Err(TodoError::InvalidFilter(_))));
+
+        let mut changed = home.clone();
+        changed.items.remove(0);
+        changed.items[0].completed = true;
+        let untouched = std::fs::read_to_string(index.segment_path("Home")).unwrap();
+        index.update(&BTreeSet::from(["Home", "Work"]), &[changed]).unwrap();
+        assert_ne!(std::fs::read_to_string(index.segment_path("Home")).unwrap(), untouched);
+        assert!(!index.segment_path("Work").exists());
+        let hits = index.search("deploy", Mode::Words).unwrap().unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].list.as_str(), hits[0].path.as_str(), hits[0].completed), ("Home", "1", true));
+
+        // Subtasks are found by their path.
+        let mut nested = work.clone();
+        nested.items[0].parent = Some(nested.items[2].id.clone());
+        index.rebuild(&[nested]).unwrap();
+        assert_eq!(found(&index, "deploy", Mode::Words), vec!["Work:2.1", "Work:1"]);
+
+        // Notes are searched, but rank below descriptions and tags.
+        let mut noted = work.clone();
+        noted.items[2].notes = "Happens after the deploy\nSee ticket 42".to_string();
+        index.rebuild(&[noted]).unwrap();
+        assert_eq!(found(&index, "deploy", Mode::Words), vec!["Work:1", "Work:2", "Work:3"]);
+        let hits = index.search("ticket", Mode::Words).unwrap().unwrap();
+        assert_eq!((hits[0].path.as_str(), hits[0].note.as_deref()), ("3", Some("See ticket 42")));
+        assert_eq!(found(&index, "ticket \\d+", Mode::Regex), vec!["Work:3"]);
+        assert_eq!(found(&index, "tckt", Mode::Fuzzy), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_segment_file_names() {
+        assert_eq!(file_name("Home/Work 2"), "%48ome%2F%57ork%202");
+        assert_ne!(file_name("Work"), file_name("work"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let index = SearchIndex::new(&dir.path().join(INDEX_DIR));
+        index.rebuild(&[list("../Home", &["Deploy"]), list("home", &["Deploy"])]).unwrap();
+        assert_eq!(found(&index, "deploy", Mode::Words), vec!["../Home:1", "home:1"]);
+    }
+}
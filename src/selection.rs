@@ -0,0 +1,160 @@
+//! Which items of a list a command applies to, e.g. `3`, `1-4,7,9`, `all`,
+//! `done` or a filter expression such as `tag:errands and due<today`.
+//!
+//! A selection containing whitespace or any of `:<>=~()!"#+@` is a filter
+//! expression in the language of `todo show --where`; item IDs and positions
+//! never do. Positions and ranges count top-level items, while paths such as
+//! `3.2` reach subtasks.
+
+use chrono::{DateTime, Local, Utc};
+use crate::error::{TodoError, TodoResult};
+use crate::filter::Expr;
+use crate::models::{resolve_item_ref, Item, ItemRefError, List, MIN_ID_PREFIX_LEN};
+use crate::tree;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    All,
+    Done,
+    Open,
+    /// Comma-separated item references and ranges of top-level positions
+    /// like `2-5`.
+    Refs(Vec<String>),
+    Filter(Expr),
+}
+
+impl Selection {
+    /// Parses `input`, resolving relative dates in filters against `now`.
+    pub fn parse(input: &str, now: &DateTime<Local>) -> TodoResult<Self> {
+        let input = input.trim();
+        match input.to_lowercase().as_str() {
+            "all" => return Ok(Selection::All),
+            "done" => return Ok(Selection::Done),
+            "open" => return Ok(Selection::Open),
+            _ => {}
+        }
+        if input.contains(|c: char| c.is_whitespace() || ":<>=~()!\"#+@".contains(c)) {
+            return Ok(Selection::Filter(Expr::parse(input, now)?));
+        }
+        let refs: Vec<String> = input.split(',').map(str::trim).filter(|r| !r.is_empty()).map(String::from).collect();
+        if refs.is_empty() {
+            return Err(TodoError::ItemNotFound("no items given".to_string()));
+        }
+        Ok(Selection::Refs(refs))
+    }
+
+    /// A selection of the single item `item_ref`.
+    pub fn item(item_ref: &str) -> Self {
+        Selection::Refs(vec![item_ref.to_string()])
+    }
+
+    /// Indexes of the selected items among the items of `list`, which must
+    /// not contain trashed items. References keep the order they were given
+    /// in and each item is selected once.
+    pub fn resolve(&self, list: &List, now: DateTime<Utc>) -> TodoResult<Vec<usize>> {
+        let items = &list.items;
+        let matching = |keep: &dyn Fn(&Item) -> bool| items.iter().enumerate().filter(|(_, item)| keep(item)).map(|(i, _)| i).collect();
+        Ok(match self {
+            Selection::All => (0..items.len()).collect(),
+            Selection::Done => matching(&|item| item.completed),
+            Selection::Open => matching(&|item| !item.completed),
+            Selection::Filter(expr) => matching(&|item| expr.matches(list, item, now)),
+            Selection::Refs(refs) => {
+                let roots = tree::roots(items);
+                let mut indexes = Vec::new();
+                for reference in refs {
+                    let found = match parse_position_range(reference) {
+                        Some((first, last)) if first >= 1 && first <= last && last <= roots.len() => roots[first - 1..last].to_vec(),
+                        Some(_) => return Err(TodoError::ItemNotFound(format!("Positions {} in list {}", reference, list.name))),
+                        None => vec![find_item(items, &list.name, reference)?],
+                    };
+                    for index in found {
+                        if !indexes.contains(&index) {
+                            indexes.push(index);
+                        }
+                    }
+                }
+                indexes
+            }
+        })
+    }
+}
+
+/// Parses a range of 1-based positions such as `2-5`.
+fn parse_position_range(reference: &str) -> Option<(usize, usize)> {
+    let (first, last) = reference.split_once('-')?;
+    Some((first.trim().parse().ok()?, last.trim().parse().ok()?))
+}
+
+/// Resolves `item_ref` against the items of a list: an ID or ID prefix of
+/// any item, the position of a top-level item or the path of a subtask.
+pub(crate) fn find_item(items: &[Item], list_name: &str, item_ref: &str) -> TodoResult<usize> {
+    let not_found = || TodoError::ItemNotFound(format!("Item {} in list {}", item_ref, list_name));
+    if item_ref.contains('.') {
+        return tree::parse_path(item_ref.trim()).and_then(|path| tree::find_path(items, &path)).ok_or_else(not_found);
+    }
+    // Top-level items come first so that positions only count them.
+    let roots = tree::roots(items);
+    let order: Vec<usize> = roots.iter().copied().chain((0..items.len()).filter(|index| !roots.contains(index))).collect();
+    let found = resolve(order.iter().map(|&index| &items[index]), list_name, item_ref)?;
+    let by_id = item_ref.trim().len() >= MIN_ID_PREFIX_LEN && items[order[found]].id.starts_with(item_ref.trim());
+    if found >= roots.len() && !by_id {
+        return Err(not_found());
+    }
+    Ok(order[found])
+}
+
+/// Resolves `item_ref` against items numbered in their stored order, as
+/// `todo trash` shows them.
+pub(crate) fn find_listed_item(items: &[Item], list_name: &str, item_ref: &str) -> TodoResult<usize> {
+    resolve(items.iter(), list_name, item_ref)
+}
+
+fn resolve<'a>(items: impl Iterator<Item = &'a Item>, list_name: &str, item_ref: &str) -> TodoResult<usize> {
+    resolve_item_ref(items.map(|item| item.id.as_str()), item_ref).map_err(|e| match e {
+        ItemRefError::NotFound => TodoError::ItemNotFound(format!("Item {} in list {}", item_ref, list_name)),
+        ItemRefError::Ambiguous(n) => TodoError::AmbiguousItem(format!("'{}' matches {} items in list {}", item_ref, n, list_name)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let mut items: Vec<Item> = ["Buy milk #errands", "Call Bob", "Post letter #errands", "Fix bug", "Pay rent"]
+            .iter()
+            .map(|d| Item::from_input(d))
+            .collect();
+        items[1].completed = true;
+        items[3].completed = true;
+        let list = List { name: "Home".to_string(), items, trashed: None, created_at: None, updated_at: None };
+        let now = Local::now();
+        let select = |input: &str| Selection::parse(input, &now).and_then(|s| s.resolve(&list, now.with_timezone(&Utc)));
+
+        assert_eq!(select("1-3,5,2").unwrap(), vec![0, 1, 2, 4]);
+        assert_eq!(select(&format!("4,{}", &list.items[0].id[..6])).unwrap(), vec![3, 0]);
+        assert_eq!(select("ALL").unwrap(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(select("done").unwrap(), vec![1, 3]);
+        assert_eq!(select("open").unwrap(), vec![0, 2, 4]);
+        assert_eq!(select("#errands").unwrap(), vec![0, 2]);
+        assert_eq!(select("status:done and text~bob").unwrap(), vec![1]);
+        assert!(matches!(select("4-6"), Err(TodoError::ItemNotFound(_))));
+        assert!(matches!(select("3-2"), Err(TodoError::ItemNotFound(_))));
+        assert!(matches!(select(" , "), Err(TodoError::ItemNotFound(_))));
+        assert!(matches!(select("due<"), Err(TodoError::InvalidFilter(_))));
+
+        // Subtasks are reached by path or ID, and positions skip them.
+        let mut list = list.clone();
+        list.items[1].parent = Some(list.items[4].id.clone());
+        list.items[2].parent = Some(list.items[1].id.clone());
+        let select = |input: &str| Selection::parse(input, &now).and_then(|s| s.resolve(&list, now.with_timezone(&Utc)));
+        assert_eq!(select("1-3").unwrap(), vec![0, 3, 4]);
+        assert_eq!(select("3.1,3.1.1").unwrap(), vec![1, 2]);
+        assert_eq!(select(&list.items[2].id[..6]).unwrap(), vec![2]);
+        assert!(matches!(select("4"), Err(TodoError::ItemNotFound(_))));
+        assert!(matches!(select("1.1"), Err(TodoError::ItemNotFound(_))));
+        assert!(matches!(select("3.x"), Err(TodoError::ItemNotFound(_))));
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use crate::error::{TodoError, TodoResult};
+use crate::files::{self, FileLock};
+use crate::models::{Item, List};
+use super::{list_not_found, Operation, Storage};
+
+/// List name to list, serialized as the `local_db.json` object.
+type Lists = BTreeMap<String, StoredList>;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(from = "StoredListFormat", into = "StoredListFormat")]
+struct StoredList {
+    items: Vec<Item>,
+    trashed: Option<DateTime<Utc>>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl StoredList {
+    fn list(&self, name: &str) -> List {
+        List {
+            name: name.to_string(),
+            items: self.items.clone(),
+            trashed: self.trashed,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+impl From<List> for StoredList {
+    fn from(list: List) -> Self {
+        Self { items: list.items, trashed: list.trashed, created_at: list.created_at, updated_at: list.updated_at }
+    }
+}
+
+/// Lists are written as a plain array of items unless they are in the
+/// trash or have timestamps, so files from before either keep their format.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredListFormat {
+    Items(Vec<Item>),
+    Full {
+        items: Vec<Item>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trashed: Option<DateTime<Utc>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        created_at: Option<DateTime<Utc>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        updated_at: Option<DateTime<Utc>>,
+    },
+}
+
+impl From<StoredListFormat> for StoredList {
+    fn from(format: StoredListFormat) -> Self {
+        match format {
+            StoredListFormat::Items(items) => Self { items, ..Self::default() },
+            StoredListFormat::Full { items, trashed, created_at, updated_at } => Self { items, trashed, created_at, updated_at },
+        }
+    }
+}
+
+impl From<StoredList> for StoredListFormat {
+    fn from(list: StoredList) -> Self {
+        match list {
+            StoredList { items, trashed: None, created_at: None, updated_at: None } => StoredListFormat::Items(items),
+            StoredList { items, trashed, created_at, updated_at } => StoredListFormat::Full { items, trashed, created_at, updated_at },
+        }
+    }
+}
+
+/// Reads the JSON file on every access and rewrites it on every mutation,
+/// holding a lock file throughout so that concurrent `todo` processes never
+/// interleave. The previous version is kept as a `.bak` file and restored
+/// if the file turns out to be corrupt.
+pub struct JsonStorage {
+    path: PathBuf,
+    /// Serializes access from this process; the lock file only keeps other
+    /// processes out.
+    in_process: Mutex<()>,
+}
+
+impl JsonStorage {
+    pub async fn open(path: &Path) -> TodoResult<Self> {
+        let storage = Self {
+            path: path.to_path_buf(),
+            in_process: Mutex::new(()),
+        };
+        storage.read().await?;
+        Ok(storage)
+    }
+
+    async fn read(&self) -> TodoResult<Lists> {
+        let _guard = self.in_process.lock().await;
+        let _lock = FileLock::acquire(&self.path)?;
+        Ok(self.load()?.0)
+    }
+
+    /// Applies `f` to the stored lists and writes the result to disk.
+    async fn modify<R>(&self, f: impl FnOnce(&mut Lists) -> TodoResult<R> + Send) -> TodoResult<R> {
+        let _guard = self.in_process.lock().await;
+        let _lock = FileLock::acquire(&self.path)?;
+        let (mut lists, previous) = self.load()?;
+        let result = f(&mut lists)?;
+        self.save(&lists, previous.as_deref())?;
+        Ok(result)
+    }
+
+    /// Reads the lists along with the file content they came from, which is
+    /// known to be good. Must be called with the lock held.
+    fn load(&self) -> TodoResult<(Lists, Option<String>)> {
+        let data = match std::fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Lists::new(), None)),
+            Err(e) => return Err(e.into()),
+        };
+        match parse(&data) {
+            Ok((lists, migrated)) => {
+                if migrated {
+                    self.save(&lists, Some(&data))?;
+                }
+                Ok((lists, Some(data)))
+            }
+            Err(e) => self.recover(e),
+        }
+    }
+
+    /// Restores the backup after the file failed to parse with `error`.
+    fn recover(&self, error: TodoError) -> TodoResult<(Lists, Option<String>)> {
+        let backup = files::sibling(&self.path, "bak");
+        let (lists, _) = std::fs::read_to_string(&backup).ok()
+            .and_then(|data| parse(&data).ok())
+            .ok_or_else(|| TodoError::DatabaseError(format!(
+                "{} is corrupt ({}) and has no usable backup",
+                self.path.display(), error
+            )))?;
+        eprintln!("Warning: {} is corrupt ({}), restored the previous version from {}", self.path.display(), error, backup.display());
+        let data = serde_json::to_string_pretty(&lists)?;
+        files::write_atomic(&self.path, data.as_bytes())?;
+        Ok((lists, Some(data)))
+    }
+
+    /// Writes `lists`, keeping `previous` as the backup.
+    fn save(&self, lists: &Lists, previous: Option<&str>) -> TodoResult<()> {
+        if let Some(previous) = previous {
+            files::write_atomic(&files::sibling(&self.path, "bak"), previous.as_bytes())?;
+        }
+        let data = serde_json::to_string_pretty(lists)?;
+        files::write_atomic(&self.path, data.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Parses the file, assigning IDs to items written before items had one.
+/// Also returns whether that happened and the file should be rewritten.
+fn parse(data: &str) -> TodoResult<(Lists, bool)> {
+    let mut local_db: serde_json::Value = serde_json::from_str(data)?;
+    if !local_db.is_object() {
+        return Err(TodoError::DatabaseError("Invalid local database format".into()));
+    }
+    let migrated = migrate_item_ids(&mut local_db);
+    Ok((serde_json::from_value(local_db)?, migrated))
+}
+
+#[async_trait]
+impl Storage for JsonStorage {
+    async fn get_lists(&self) -> TodoResult<Vec<List>> {
+        let lists = self.read().await?;
+        Ok(lists.iter()
+            .map(|(name, list)| list.list(name))
+            .collect())
+    }
+
+    async fn get_list(&self, name: &str) -> TodoResult<List> {
+        let lists = self.read().await?;
+        Ok(lists.get(name).ok_or_else(|| list_not_found(name))?.list(name))
+    }
+
+    async fn replace_all(&self, new_lists: Vec<List>) -> TodoResult<()> {
+        self.modify(|lists| {
+            *lists = new_lists.into_iter()
+                .map(|list| (list.name.clone(), StoredList::from(list)))
+                .collect();
+            Ok(())
+        }).await
+    }
+
+    async fn find_lists(&self, names: &[&str]) -> TodoResult<Vec<List>> {
+        let lists = self.read().await?;
+        Ok(lists.iter()
+            .filter(|(name, _)| names.contains(&name.as_str()))
+            .map(|(name, list)| list.list(name))
+            .collect())
+    }
+
+    async fn apply(&self, operations: Vec<Operation>) -> TodoResult<()> {
+        self.modify(|lists| {
+            for operation in operations {
+                match operation {
+                    Operation::PutList(list) => {
+                        let stored = lists.entry(list.name).or_default();
+                        stored.trashed = list.trashed;
+                        stored.created_at = list.created_at;
+                        stored.updated_at = list.updated_at;
+                    }
+                    Operation::RemoveList(name) => {
+                        lists.remove(&name);
+                    }
+                    Operation::AddItem { list, item, after } => {
+                        let items = items_of(lists, &list)?;
+                        items.insert(index_after(items, after.as_deref()), item);
+                    }
+                    Operation::UpdateItem { list, item } => {
+                        if let Some(stored) = items_of(lists, &list)?.iter_mut().find(|stored| stored.id == item.id) {
+                            *stored = item;
+                        }
+                    }
+                    Operation::MoveItem { list, id, after } => {
+                        let items = items_of(lists, &list)?;
+                        if let Some(index) = items.iter().position(|item| item.id == id) {
+                            let item = items.remove(index);
+                            items.insert(index_after(items, after.as_deref()), item);
+                        }
+                    }
+                    Operation::RemoveItem { list, id } => {
+                        items_of(lists, &list)?.retain(|item| item.id != id);
+                    }
+                }
+            }
+            Ok(())
+        }).await
+    }
+}
+
+fn items_of<'a>(lists: &'a mut Lists, name: &str) -> TodoResult<&'a mut Vec<Item>> {
+    Ok(&mut lists.get_mut(name).ok_or_else(|| list_not_found(name))?.items)
+}
+
+/// Where an item placed right after the item `after` goes, first if `None`
+/// and last if there is no such item.
+fn index_after(items: &[Item], after: Option<&str>) -> usize {
+    match after {
+        Some(after) => items.iter().position(|item| item.id == after).map_or(items.len(), |index| index + 1),
+        None => 0,
+    }
+}
+
+/// Assigns an ID to every item written before items had one.
+/// Returns true if anything changed and the file should be rewritten.
+fn migrate_item_ids(local_db: &mut serde_json::Value) -> bool {
+    let mut migrated = false;
+    if let Some(lists) = local_db.as_object_mut() {
+        let item_arrays = lists.values_mut()
+            .filter_map(|list| if list.is_object() { list.get_mut("items") } else { Some(list) })
+            .filter_map(|items| items.as_array_mut());
+        for item in item_arrays.flatten() {
+            if let Some(item) = item.as_object_mut() {
+                if !item.contains_key("id") {
+                    item.insert("id".to_string(), serde_json::Value::String(Item::generate_id()));
+                    migrated = true;
+                }
+            }
+        }
+    }
+    migrated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{list, put_lists};
+
+    #[test]
+    fn test_migrate_item_ids() {
+        let mut local_db = serde_json::json!({
+            "Chores": [
+                { "description": "Dishes", "completed": false },
+                { "id": "3f2a9c1b", "description": "Laundry", "completed": true }
+            ]
+        });
+
+        assert!(migrate_item_ids(&mut local_db));
+        assert!(local_db["Chores"][0]["id"].is_string());
+        assert_eq!(local_db["Chores"][1]["id"], "3f2a9c1b");
+        assert!(!migrate_item_ids(&mut local_db));
+    }
+
+    #[tokio::test]
+    async fn test_json_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local_db.json");
+        let storage = JsonStorage::open(&path).await.unwrap();
+        super::super::tests::exercise_storage(&storage).await;
+
+        put_lists(&storage, vec![list("Persisted", Vec::new())]).await;
+        let reopened = JsonStorage::open(&path).await.unwrap();
+        assert_eq!(reopened.get_lists().await.unwrap()[0].name, "Persisted");
+
+        // Every access goes to the file, so instances see each other's writes.
+        put_lists(&reopened, vec![list("Shared", Vec::new())]).await;
+        assert_eq!(storage.get_lists().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_recovers_from_corrupt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local_db.json");
+        let storage = JsonStorage::open(&path).await.unwrap();
+        put_lists(&storage, vec![list("Chores", Vec::new())]).await;
+        put_lists(&storage, vec![list("Chores", vec![Item::new("Dishes")])]).await;
+
+        // A torn write leaves the file cut short.
+        let data = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        let lists = storage.get_lists().await.unwrap();
+        assert_eq!(lists[0].name, "Chores");
+        assert!(lists[0].items.is_empty());
+        assert!(JsonStorage::open(&path).await.is_ok());
+
+        std::fs::write(&path, "[]").unwrap();
+        std::fs::remove_file(files::sibling(&path, "bak")).unwrap();
+        assert!(matches!(JsonStorage::open(&path).await, Err(TodoError::DatabaseError(_))));
+    }
+}
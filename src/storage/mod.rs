@@ -0,0 +1,417 @@
+mod json;
+mod sqlite;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use crate::error::{TodoError, TodoResult};
+use crate::models::{Item, List};
+use crate::paths;
+
+pub use json::JsonStorage;
+pub use sqlite::SqliteStorage;
+
+/// Persistence for lists and items. Items are addressed by their ID; turning
+/// user input into an ID is the job of `Database`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get_lists(&self) -> TodoResult<Vec<List>>;
+    async fn get_list(&self, name: &str) -> TodoResult<List>;
+    /// The lists named in `names` that exist, by name.
+    async fn find_lists(&self, names: &[&str]) -> TodoResult<Vec<List>>;
+    /// Replaces the whole store with `lists`.
+    async fn replace_all(&self, lists: Vec<List>) -> TodoResult<()>;
+    /// Applies `operations` in order, in one write.
+    async fn apply(&self, operations: Vec<Operation>) -> TodoResult<()>;
+}
+
+/// A change to the store that touches one list or one item, as computed by
+/// `operations`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// Creates the list or updates its fields; its items are left alone.
+    PutList(List),
+    /// Removes the list with its items.
+    RemoveList(String),
+    /// Inserts `item` right after the item `after`, or first if `None`.
+    AddItem { list: String, item: Item, after: Option<String> },
+    /// Stores a new version of an item, which keeps its place.
+    UpdateItem { list: String, item: Item },
+    /// Moves an item right after the item `after`, or first if `None`.
+    MoveItem { list: String, id: String, after: Option<String> },
+    RemoveItem { list: String, id: String },
+}
+
+/// The operations that turn the stored lists `before` into `after`, matching
+/// lists by name and items by ID. Only items that changed, came, went or
+/// moved relative to the others are written.
+pub fn operations(before: &[List], after: &[List]) -> Vec<Operation> {
+    let mut operations: Vec<Operation> = before.iter()
+        .filter(|list| !after.iter().any(|other| other.name == list.name))
+        .map(|list| Operation::RemoveList(list.name.clone()))
+        .collect();
+    for list in after {
+        let stored = before.iter().find(|other| other.name == list.name);
+        if stored != Some(list) {
+            operations.extend(list_operations(stored, list));
+        }
+    }
+    operations
+}
+
+fn list_operations(before: Option<&List>, after: &List) -> Vec<Operation> {
+    let without_items = |list: &List| List { items: Vec::new(), ..list.clone() };
+    let mut operations = Vec::new();
+    if before.map(without_items) != Some(without_items(after)) {
+        operations.push(Operation::PutList(without_items(after)));
+    }
+
+    let stored = before.map_or(&[][..], |list| &list.items);
+    let positions: HashMap<&str, usize> = stored.iter().enumerate().map(|(i, item)| (item.id.as_str(), i)).collect();
+    let kept: HashSet<&str> = after.items.iter().map(|item| item.id.as_str()).collect();
+    for item in stored.iter().filter(|item| !kept.contains(item.id.as_str())) {
+        operations.push(Operation::RemoveItem { list: after.name.clone(), id: item.id.clone() });
+    }
+
+    // Items in the longest run that kept its order stay where they are and
+    // the others are moved around them.
+    let common: Vec<&str> = after.items.iter().map(|item| item.id.as_str()).filter(|id| positions.contains_key(id)).collect();
+    let in_order = longest_increasing(&common.iter().map(|id| positions[id]).collect::<Vec<_>>());
+    let in_place: HashSet<&str> = common.into_iter().zip(in_order).filter(|(_, in_order)| *in_order).map(|(id, _)| id).collect();
+
+    let mut previous = None;
+    for item in &after.items {
+        match positions.get(item.id.as_str()) {
+            None => operations.push(Operation::AddItem { list: after.name.clone(), item: item.clone(), after: previous.clone() }),
+            Some(&index) => {
+                if stored[index] != *item {
+                    operations.push(Operation::UpdateItem { list: after.name.clone(), item: item.clone() });
+                }
+                if !in_place.contains(item.id.as_str()) {
+                    operations.push(Operation::MoveItem { list: after.name.clone(), id: item.id.clone(), after: previous.clone() });
+                }
+            }
+        }
+        previous = Some(item.id.clone());
+    }
+    operations
+}
+
+/// Marks the values of a longest strictly increasing subsequence of
+/// `values`.
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // `tails[k]` is where the lowest known end of a run of length `k + 1` is.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+    for (i, &value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&tail| values[tail] < value);
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut marked = vec![false; values.len()];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        marked[i] = true;
+        next = previous[i];
+    }
+    marked
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl Backend {
+    /// Reads the backend from `TODO_STORAGE`, defaulting to the JSON file.
+    pub fn from_env() -> TodoResult<Self> {
+        match std::env::var("TODO_STORAGE") {
+            Ok(value) => Self::from_str(&value, true)
+                .map_err(|_| TodoError::ConfigError(format!("Unknown TODO_STORAGE backend '{}'", value))),
+            Err(_) => Ok(Backend::Json),
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Backend::Json => "local_db.json",
+            Backend::Sqlite => "local_db.sqlite3",
+        }
+    }
+
+    /// Where this backend lives in the data directory.
+    pub fn default_path(self) -> TodoResult<PathBuf> {
+        Ok(paths::data_dir()?.join(self.file_name()))
+    }
+
+    /// `TODO_DB_PATH` if it is set and this is the configured backend.
+    fn path_override(self) -> Option<PathBuf> {
+        match std::env::var("TODO_DB_PATH") {
+            Ok(path) if Self::from_env().ok() == Some(self) => Some(PathBuf::from(path)),
+            _ => None,
+        }
+    }
+
+    /// The configured location of this backend: `TODO_DB_PATH` if it is set
+    /// and this is the configured backend, the default path otherwise.
+    pub fn configured_path(self) -> TodoResult<PathBuf> {
+        match self.path_override() {
+            Some(path) => Ok(path),
+            None => self.default_path(),
+        }
+    }
+
+    pub async fn open(self, path: &Path) -> TodoResult<Box<dyn Storage>> {
+        Ok(match self {
+            Backend::Json => Box::new(JsonStorage::open(path).await?),
+            Backend::Sqlite => Box::new(SqliteStorage::open(path)?),
+        })
+    }
+}
+
+/// Files kept next to the local database by `Database` and sync.
+const STATE_FILES: &[&str] = &["journal.json", "google_task_ids.json", "sync_base_mongo.json", "sync_base_google.json"];
+
+/// Which database a command works on, chosen with `--local` and `--global`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// The nearest project, or the global database outside of one.
+    Nearest,
+    Local,
+    Global,
+}
+
+/// A database found for a `Scope`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    /// The project whose `.todo` directory this is.
+    Project(PathBuf),
+    /// The user's database in the data directory, or at `TODO_DB_PATH`.
+    Global,
+}
+
+impl Location {
+    /// Finds the database for `scope`, looking for projects from `dir` up.
+    pub fn find(scope: Scope, dir: &Path) -> TodoResult<Self> {
+        let project = || paths::find_project_dir(dir).map(Location::Project);
+        match scope {
+            Scope::Nearest => Ok(project().unwrap_or(Location::Global)),
+            Scope::Local => project()
+                .ok_or_else(|| TodoError::ConfigError("Not inside a project, run `todo init` to create one".to_string())),
+            Scope::Global => Ok(Location::Global),
+        }
+    }
+
+    /// Short name shown next to lists when several databases are displayed.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Location::Project(_) => "project",
+            Location::Global => "global",
+        }
+    }
+
+    /// Opens the backend selected by `TODO_STORAGE` here, and returns it
+    /// with the directory it lives in.
+    pub async fn open(&self) -> TodoResult<(Box<dyn Storage>, PathBuf)> {
+        let backend = Backend::from_env()?;
+        let path = match self {
+            Location::Project(dir) => dir.join(backend.file_name()),
+            Location::Global => match backend.path_override() {
+                Some(path) => path,
+                None => {
+                    let path = backend.default_path()?;
+                    adopt_legacy_database(Path::new("."), backend, &path)?;
+                    path
+                }
+            },
+        };
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        std::fs::create_dir_all(&dir)?;
+        Ok((backend.open(&path).await?, dir))
+    }
+}
+
+/// Copies a database that older versions kept in `legacy_dir`, the working
+/// directory, to `path` along with its bookkeeping files. Happens once: as
+/// soon as `path` exists the legacy copy is left alone.
+fn adopt_legacy_database(legacy_dir: &Path, backend: Backend, path: &Path) -> TodoResult<()> {
+    let legacy = legacy_dir.join(backend.file_name());
+    if !paths::adopt_legacy_file(&legacy, path)? {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        for name in STATE_FILES {
+            paths::adopt_legacy_file(&legacy_dir.join(name), &dir.join(name))?;
+        }
+    }
+    eprintln!("Copied {} to {}; the original can be deleted", legacy.display(), path.display());
+    Ok(())
+}
+
+/// Copies every list from `from` into `to`, replacing whatever `to` held.
+pub async fn migrate(from: &dyn Storage, to: &dyn Storage) -> TodoResult<usize> {
+    let lists = from.get_lists().await?;
+    let count = lists.len();
+    to.replace_all(lists).await?;
+    Ok(count)
+}
+
+fn list_not_found(list_name: &str) -> TodoError {
+    TodoError::ListNotFound(list_name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crate::models::Item;
+
+    pub(super) fn list(name: &str, items: Vec<Item>) -> List {
+        List { name: name.to_string(), items, trashed: None, created_at: None, updated_at: None }
+    }
+
+    /// Stores `lists` in place of the lists of the same names.
+    pub(super) async fn put_lists(storage: &dyn Storage, lists: Vec<List>) {
+        let names: Vec<&str> = lists.iter().map(|list| list.name.as_str()).collect();
+        let stored = storage.find_lists(&names).await.unwrap();
+        storage.apply(operations(&stored, &lists)).await.unwrap();
+    }
+
+    /// Runs the same scenario against any backend.
+    pub(super) async fn exercise_storage(storage: &dyn Storage) {
+        let chores = list("Chores", vec![Item::new("Dishes"), Item::new("Laundry")]);
+        let work = list("Work", vec![Item::new("Deploy")]);
+        put_lists(storage, vec![work.clone(), chores.clone()]).await;
+        assert_eq!(storage.get_list("Chores").await.unwrap(), chores);
+        assert!(matches!(storage.get_list("Missing").await, Err(TodoError::ListNotFound(_))));
+        let names: Vec<String> = storage.get_lists().await.unwrap().into_iter().map(|l| l.name).collect();
+        assert_eq!(names, vec!["Chores", "Work"]);
+        assert_eq!(storage.find_lists(&["Work", "Missing", "Work"]).await.unwrap(), vec![work.clone()]);
+
+        // Items are edited, moved and added one at a time.
+        let trashed = Some(Utc::now());
+        let mut edited = chores.clone();
+        edited.items.reverse();
+        edited.items[0].description = "Fold laundry".to_string();
+        edited.trashed = trashed;
+        edited.created_at = trashed;
+        storage.apply(operations(&[chores.clone(), work.clone()], &[edited.clone()])).await.unwrap();
+        assert_eq!(storage.get_lists().await.unwrap(), vec![edited.clone()]);
+
+        let mut reordered = edited.clone();
+        for description in ["First", "Middle", "Last"] {
+            reordered.items.push(Item::new(description));
+        }
+        reordered.items.rotate_right(1);
+        reordered.items.swap(2, 3);
+        for _ in 0..12 {
+            // Inserting at the same spot over and over uses up the room
+            // between positions.
+            reordered.items.insert(1, Item::new("Squeezed"));
+        }
+        storage.apply(operations(&[edited.clone()], &[reordered.clone()])).await.unwrap();
+        assert_eq!(storage.get_list("Chores").await.unwrap(), reordered);
+
+        let mut shuffled = reordered.clone();
+        shuffled.items.remove(4);
+        shuffled.items.reverse();
+        storage.apply(operations(&[reordered], &[shuffled.clone()])).await.unwrap();
+        assert_eq!(storage.get_lists().await.unwrap(), vec![shuffled.clone()]);
+        storage.apply(operations(&[shuffled], &[edited.clone()])).await.unwrap();
+        assert_eq!(storage.get_lists().await.unwrap(), vec![edited]);
+
+        storage.replace_all(vec![List { trashed, updated_at: trashed, ..list("Fresh", vec![Item::new("One")]) }]).await.unwrap();
+        let lists = storage.get_lists().await.unwrap();
+        assert_eq!(lists.len(), 1);
+        assert_eq!(lists[0].items[0].description, "One");
+        assert_eq!(lists[0].trashed, trashed);
+        assert_eq!(lists[0].updated_at, trashed);
+
+        storage.apply(vec![Operation::RemoveList("Fresh".to_string())]).await.unwrap();
+        assert!(storage.get_lists().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_operations_touch_only_changes() {
+        let items: Vec<Item> = ["a", "b", "c", "d"].into_iter().map(Item::new).collect();
+        let before = vec![list("L", items.clone())];
+
+        // Moving one item moves only that item, whichever way it went.
+        let mut after = before.clone();
+        let moved = after[0].items.remove(0);
+        after[0].items.push(moved.clone());
+        assert_eq!(operations(&before, &after), vec![Operation::MoveItem {
+            list: "L".to_string(),
+            id: moved.id.clone(),
+            after: Some(items[3].id.clone()),
+        }]);
+
+        let mut after = before.clone();
+        after[0].items[2].completed = true;
+        after[0].items.remove(1);
+        assert_eq!(operations(&before, &after), vec![
+            Operation::RemoveItem { list: "L".to_string(), id: items[1].id.clone() },
+            Operation::UpdateItem { list: "L".to_string(), item: after[0].items[1].clone() },
+        ]);
+
+        assert!(operations(&before, &before).is_empty());
+        assert_eq!(operations(&before, &[]), vec![Operation::RemoveList("L".to_string())]);
+    }
+
+    #[test]
+    fn test_longest_increasing() {
+        assert_eq!(longest_increasing(&[3, 0, 1, 4, 2]), vec![false, true, true, false, true]);
+        assert_eq!(longest_increasing(&[]), Vec::<bool>::new());
+    }
+
+    #[test]
+    fn test_adopts_legacy_database_once() {
+        let legacy = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        let path = data.path().join("todo").join("local_db.json");
+        std::fs::write(legacy.path().join("local_db.json"), "{}").unwrap();
+        std::fs::write(legacy.path().join("journal.json"), "{}").unwrap();
+        std::fs::write(legacy.path().join("local_db.sqlite3"), "").unwrap();
+
+        adopt_legacy_database(legacy.path(), Backend::Json, &path).unwrap();
+        assert!(path.exists());
+        assert!(data.path().join("todo").join("journal.json").exists());
+        assert!(!data.path().join("todo").join("local_db.sqlite3").exists());
+        assert!(legacy.path().join("local_db.json").exists());
+
+        std::fs::write(&path, "{\"Kept\": []}").unwrap();
+        std::fs::remove_file(data.path().join("todo").join("journal.json")).unwrap();
+        adopt_legacy_database(legacy.path(), Backend::Json, &path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"Kept\": []}");
+        assert!(!data.path().join("todo").join("journal.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_between_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = Backend::Json.open(&dir.path().join("db.json")).await.unwrap();
+        let sqlite = Backend::Sqlite.open(&dir.path().join("db.sqlite3")).await.unwrap();
+
+        let mut item = Item::new("Dishes");
+        item.completed = true;
+        put_lists(json.as_ref(), vec![list("Chores", vec![item.clone()])]).await;
+        put_lists(sqlite.as_ref(), vec![list("Stale", Vec::new())]).await;
+
+        assert_eq!(migrate(json.as_ref(), sqlite.as_ref()).await.unwrap(), 1);
+
+        let lists = sqlite.get_lists().await.unwrap();
+        assert_eq!(lists.len(), 1);
+        assert_eq!(lists[0].name, "Chores");
+        assert_eq!(lists[0].items[0].id, item.id);
+        assert!(lists[0].items[0].completed);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::error::TodoResult;
+use crate::models::{Item, List};
+use super::{list_not_found, Operation, Storage};
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS lists (
+        name TEXT PRIMARY KEY,
+        trashed TEXT,
+        created_at TEXT,
+        updated_at TEXT
+    );
+    CREATE TABLE IF NOT EXISTS items (
+        list_name TEXT NOT NULL REFERENCES lists(name) ON DELETE CASCADE,
+        id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (list_name, id)
+    );
+    CREATE INDEX IF NOT EXISTS items_by_position ON items(list_name, position);
+";
+
+/// Room left between the positions of neighbouring items, so that an item
+/// can usually be put between two others without renumbering the list.
+const GAP: i64 = 1024;
+
+/// Stores one row per item, so writes only touch the rows of the items
+/// involved. Items are kept as JSON in the `data` column, which lets `Item` gain
+/// fields without a schema change.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> TodoResult<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    fn from_connection(conn: Connection) -> TodoResult<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Self::add_list_columns(&conn)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Databases created before lists could be trashed or had timestamps
+    /// lack the columns.
+    fn add_list_columns(conn: &Connection) -> TodoResult<()> {
+        for column in ["trashed", "created_at", "updated_at"] {
+            let has_column = conn.prepare(&format!("SELECT {} FROM lists LIMIT 0", column)).is_ok();
+            if !has_column {
+                conn.execute(&format!("ALTER TABLE lists ADD COLUMN {} TEXT", column), [])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn load_items(conn: &Connection, list_name: &str) -> TodoResult<Vec<Item>> {
+        let mut stmt = conn.prepare_cached("SELECT data FROM items WHERE list_name = ?1 ORDER BY position")?;
+        let rows = stmt.query_map(params![list_name], |row| row.get::<_, String>(0))?;
+        let mut items = Vec::new();
+        for data in rows {
+            items.push(serde_json::from_str(&data?)?);
+        }
+        Ok(items)
+    }
+
+    fn load_list(conn: &Connection, name: &str) -> TodoResult<Option<List>> {
+        let Some((trashed, created_at, updated_at)) = conn
+            .query_row(
+                "SELECT trashed, created_at, updated_at FROM lists WHERE name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(List {
+            name: name.to_string(),
+            items: Self::load_items(conn, name)?,
+            trashed,
+            created_at,
+            updated_at,
+        }))
+    }
+
+    fn insert_item(conn: &Connection, list_name: &str, item: &Item) -> TodoResult<()> {
+        conn.execute(
+            "INSERT INTO items (list_name, id, position, data)
+             VALUES (?1, ?2, (SELECT COALESCE(MAX(position), 0) + ?4 FROM items WHERE list_name = ?1), ?3)",
+            params![list_name, item.id, serde_json::to_string(item)?, GAP],
+        )?;
+        Ok(())
+    }
+
+    /// A free position right after the item `after`, or before every item
+    /// if `None`, renumbering the list if there is no room. Goes last if
+    /// there is no item `after`.
+    fn position_after(conn: &Connection, list_name: &str, after: Option<&str>) -> TodoResult<i64> {
+        loop {
+            let low: Option<i64> = match after {
+                Some(after) => {
+                    let low = conn.prepare_cached("SELECT position FROM items WHERE list_name = ?1 AND id = ?2")?
+                        .query_row(params![list_name, after], |row| row.get(0))
+                        .optional()?;
+                    if low.is_none() {
+                        let last: Option<i64> = conn.prepare_cached("SELECT MAX(position) FROM items WHERE list_name = ?1")?
+                            .query_row(params![list_name], |row| row.get(0))?;
+                        return Ok(last.unwrap_or(0) + GAP);
+                    }
+                    low
+                }
+                None => None,
+            };
+            let high: Option<i64> = conn.prepare_cached("SELECT MIN(position) FROM items WHERE list_name = ?1 AND position > ?2")?
+                .query_row(params![list_name, low.unwrap_or(i64::MIN)], |row| row.get(0))?;
+            match (low, high) {
+                (None, None) => return Ok(GAP),
+                (None, Some(high)) => return Ok(high - GAP),
+                (Some(low), None) => return Ok(low + GAP),
+                (Some(low), Some(high)) if high - low > 1 => return Ok(low + (high - low) / 2),
+                _ => Self::renumber(conn, list_name)?,
+            }
+        }
+    }
+
+    /// Spreads the positions of a list's items `GAP` apart.
+    fn renumber(conn: &Connection, list_name: &str) -> TodoResult<()> {
+        let ids = conn.prepare_cached("SELECT id FROM items WHERE list_name = ?1 ORDER BY position")?
+            .query_map(params![list_name], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut stmt = conn.prepare_cached("UPDATE items SET position = ?3 WHERE list_name = ?1 AND id = ?2")?;
+        for (i, id) in (1..).zip(&ids) {
+            stmt.execute(params![list_name, id, i * GAP])?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn get_lists(&self) -> TodoResult<Vec<List>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, trashed, created_at, updated_at FROM lists ORDER BY name")?;
+        let lists = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        lists.into_iter()
+            .map(|(name, trashed, created_at, updated_at)| {
+                let items = Self::load_items(&conn, &name)?;
+                Ok(List { name, items, trashed, created_at, updated_at })
+            })
+            .collect()
+    }
+
+    async fn get_list(&self, name: &str) -> TodoResult<List> {
+        let conn = self.conn.lock().unwrap();
+        Self::load_list(&conn, name)?.ok_or_else(|| list_not_found(name))
+    }
+
+    async fn find_lists(&self, names: &[&str]) -> TodoResult<Vec<List>> {
+        let conn = self.conn.lock().unwrap();
+        let names: BTreeSet<&str> = names.iter().copied().collect();
+        let mut lists = Vec::new();
+        for name in names {
+            lists.extend(Self::load_list(&conn, name)?);
+        }
+        Ok(lists)
+    }
+
+    async fn replace_all(&self, lists: Vec<List>) -> TodoResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM lists", [])?;
+        for list in &lists {
+            tx.execute(
+                "INSERT INTO lists (name, trashed, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![list.name, list.trashed, list.created_at, list.updated_at],
+            )?;
+            for item in &list.items {
+                Self::insert_item(&tx, &list.name, item)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn apply(&self, operations: Vec<Operation>) -> TodoResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for operation in &operations {
+            match operation {
+                Operation::PutList(list) => {
+                    tx.execute(
+                        "INSERT INTO lists (name, trashed, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)
+                         ON CONFLICT(name) DO UPDATE SET trashed = ?2, created_at = ?3, updated_at = ?4",
+                        params![list.name, list.trashed, list.created_at, list.updated_at],
+                    )?;
+                }
+                Operation::RemoveList(name) => {
+                    tx.execute("DELETE FROM lists WHERE name = ?1", params![name])?;
+                }
+                Operation::AddItem { list, item, after } => {
+                    let position = Self::position_after(&tx, list, after.as_deref())?;
+                    tx.prepare_cached("INSERT INTO items (list_name, id, position, data) VALUES (?1, ?2, ?3, ?4)")?
+                        .execute(params![list, item.id, position, serde_json::to_string(item)?])?;
+                }
+                Operation::UpdateItem { list, item } => {
+                    tx.prepare_cached("UPDATE items SET data = ?3 WHERE list_name = ?1 AND id = ?2")?
+                        .execute(params![list, item.id, serde_json::to_string(item)?])?;
+                }
+                Operation::MoveItem { list, id, after } => {
+                    let position = Self::position_after(&tx, list, after.as_deref())?;
+                    tx.prepare_cached("UPDATE items SET position = ?3 WHERE list_name = ?1 AND id = ?2")?
+                        .execute(params![list, id, position])?;
+                }
+                Operation::RemoveItem { list, id } => {
+                    tx.prepare_cached("DELETE FROM items WHERE list_name = ?1 AND id = ?2")?
+                        .execute(params![list, id])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_storage() {
+        let storage = SqliteStorage::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        super::super::tests::exercise_storage(&storage).await;
+    }
+
+    #[tokio::test]
+    async fn test_adds_list_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE lists (name TEXT PRIMARY KEY); INSERT INTO lists (name) VALUES ('Old');").unwrap();
+
+        let storage = SqliteStorage::from_connection(conn).unwrap();
+        assert_eq!(storage.get_list("Old").await.unwrap().trashed, None);
+        assert_eq!(storage.get_list("Old").await.unwrap().created_at, None);
+        let mut old = storage.get_list("Old").await.unwrap();
+        old.created_at = Some(chrono::Utc::now());
+        super::super::tests::put_lists(&storage, vec![old.clone()]).await;
+        assert_eq!(storage.get_list("Old").await.unwrap(), old);
+    }
+}
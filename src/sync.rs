@@ -1,92 +1,407 @@
 use crate::db::Database;
-use crate::error::TodoResult;
-use crate::models::List;
+use crate::error::{TodoError, TodoResult};
+use crate::files;
+use crate::google::GoogleRemote;
+use crate::merge::{self, Conflict, Resolution};
+use crate::models::{Item, List};
+use async_trait::async_trait;
+use clap::ValueEnum;
 use futures::TryStreamExt;
+use mongodb::bson::{self, doc, Document};
+use mongodb::options::ReplaceOptions;
+use std::collections::HashSet;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
-pub async fn push(db: &Database) -> TodoResult<()> {
+/// Somewhere the local lists can be synced with.
+#[async_trait]
+pub trait Remote: Send {
+    /// Short identifier, used to keep a separate sync base per remote.
+    fn name(&self) -> &str;
+    fn describe(&self) -> String;
+    /// Reads every list. `base` is the state after the last sync, for remotes
+    /// that need it to fill in item fields they cannot store.
+    async fn fetch(&mut self, base: &[List]) -> TodoResult<Vec<List>>;
+    /// Makes the remote hold exactly `lists`.
+    async fn store(&mut self, lists: &[List]) -> TodoResult<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum SyncTarget {
+    /// The `lists` collection of the MongoDB database at `MONGODB_URI`
+    #[default]
+    Mongo,
+    /// Google Tasks, using the account from `todo login`
+    Google,
+}
+
+impl SyncTarget {
+    pub const ALL: [SyncTarget; 2] = [SyncTarget::Mongo, SyncTarget::Google];
+
+    /// Matches `Remote::name` of the target's remote.
+    pub fn name(self) -> &'static str {
+        match self {
+            SyncTarget::Mongo => "mongo",
+            SyncTarget::Google => "google",
+        }
+    }
+}
+
+/// How to settle items that were changed on both sides since the last sync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStrategy {
+    /// Keep the local version, printing the remote one that is discarded.
+    Ours,
+    /// Keep the remote version, printing the local one that is discarded.
+    Theirs,
+    /// Prompt for every conflict; fails if stdin is not a terminal.
+    Ask,
+    /// Fail, listing every conflict, before anything is written.
+    Fail,
+}
+
+/// Pushes local changes. Conflicting items are settled by `strategy`.
+pub async fn push(db: &Database, target: SyncTarget, strategy: ConflictStrategy) -> TodoResult<()> {
     println!("Initiating push process...");
-    let local_db = db.get_local_db().await?;
+    sync_with(db, target, strategy).await
+}
 
-    let collection = db.remote_db.collection::<List>("lists");
+/// Pulls remote changes. Conflicting items are settled by `strategy`.
+pub async fn pull(db: &Database, target: SyncTarget, strategy: ConflictStrategy) -> TodoResult<()> {
+    sync_with(db, target, strategy).await
+}
+
+pub async fn sync_with(db: &Database, target: SyncTarget, strategy: ConflictStrategy) -> TodoResult<()> {
+    match target {
+        SyncTarget::Mongo => sync(db, &mut MongoRemote { db }, strategy).await,
+        SyncTarget::Google => sync(db, &mut GoogleRemote::for_database(db).await?, strategy).await,
+    }
+}
 
-    // Clear existing data in the collection
-    collection.drop(None).await?;
+/// Three-way merges the local database with `remote` and writes the result
+/// to both. The database stays locked throughout, so that changes made
+/// meanwhile are not lost.
+pub async fn sync(db: &Database, remote: &mut dyn Remote, strategy: ConflictStrategy) -> TodoResult<()> {
+    let lock = db.lock().await?;
+    let base_path = base_path(db, remote.name());
+    let base = load_base(&base_path).await?;
+    let local = db.get_lists_with_trash().await?;
+    let remote_lists = remote.fetch(&base).await?;
 
-    // Insert each list separately
-    for (name, items) in local_db.as_object().unwrap() {
-        let list = List {
-            name: name.clone(),
-            items: serde_json::from_value(items.clone())?,
-        };
-        collection.insert_one(list, None).await?;
+    let mut prompt_error = None;
+    let mut unresolved = Vec::new();
+    let (merged, stats) = merge::merge(&base, &local, &remote_lists, |conflict| match strategy {
+        ConflictStrategy::Ours => {
+            println!("Conflict in list '{}': kept local {}, discarded remote {}",
+                conflict.list, describe(conflict.ours.as_ref()), describe(conflict.theirs.as_ref()));
+            Resolution::Ours
+        }
+        ConflictStrategy::Theirs => {
+            println!("Conflict in list '{}': kept remote {}, discarded local {}",
+                conflict.list, describe(conflict.theirs.as_ref()), describe(conflict.ours.as_ref()));
+            Resolution::Theirs
+        }
+        ConflictStrategy::Ask => ask(conflict).unwrap_or_else(|e| {
+            prompt_error.get_or_insert(e);
+            Resolution::Ours
+        }),
+        ConflictStrategy::Fail => {
+            unresolved.push(format!("  list '{}': local {}, remote {}",
+                conflict.list, describe(conflict.ours.as_ref()), describe(conflict.theirs.as_ref())));
+            Resolution::Ours
+        }
+    });
+    if let Some(e) = prompt_error {
+        return Err(e);
+    }
+    if !unresolved.is_empty() {
+        return Err(TodoError::SyncConflict(format!(
+            "items changed on both sides, rerun with --ours or --theirs:\n{}",
+            unresolved.join("\n")
+        )));
     }
 
-    println!("Local data successfully pushed to 'lists' collection in 'todo_app' database.");
-    db.set_dirty(false).await;
-    db.update_last_modified().await;
+    remote.store(&merged).await?;
+    db.update_local_db(&lock, merged.clone()).await?;
+    save_base(&base_path, &merged).await?;
+
+    println!(
+        "Synced with {}: {} local and {} remote changes applied, {} conflicts resolved.",
+        remote.describe(), stats.from_ours, stats.from_theirs, stats.conflicts
+    );
 
     Ok(())
 }
 
+/// Whether the local lists changed since the last sync with `target`, or
+/// `None` if it was never synced.
+pub async fn has_unsynced_changes(db: &Database, target: SyncTarget) -> TodoResult<Option<bool>> {
+    let path = base_path(db, target.name());
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(load_base(&path).await? != db.get_lists_with_trash().await?))
+}
 
+fn base_path(db: &Database, remote_name: &str) -> PathBuf {
+    db.state_path(&format!("sync_base_{}.json", remote_name))
+}
+
+/// The `lists` collection in the `todo_app` database.
+struct MongoRemote<'a> {
+    db: &'a Database,
+}
+
+#[async_trait]
+impl Remote for MongoRemote<'_> {
+    fn name(&self) -> &str {
+        SyncTarget::Mongo.name()
+    }
+
+    fn describe(&self) -> String {
+        "'lists' collection in 'todo_app' database".to_string()
+    }
+
+    async fn fetch(&mut self, _base: &[List]) -> TodoResult<Vec<List>> {
+        let collection = self.db.remote_db().await?.collection::<Document>("lists");
+        let mut cursor = collection.find(None, None).await?;
 
-pub async fn pull(db: &Database) -> TodoResult<()> {
-    let collection = db.remote_db.collection::<List>("lists");
-    let mut cursor = collection.find(None, None).await?;
+        let mut documents = Vec::new();
 
-    let mut new_local_db = serde_json::Map::new();
+        while let Some(document) = cursor.try_next().await? {
+            documents.push(document);
+        }
 
-    while let Some(list) = cursor.try_next().await? {
-        new_local_db.insert(list.name.clone(), serde_json::to_value(list.items)?);
+        let local = self.db.get_lists_with_trash().await?;
+        documents.into_iter().map(|document| from_document(document, &local)).collect()
     }
 
-    db.update_local_db(serde_json::Value::Object(new_local_db)).await?;
-    db.set_dirty(false).await;
-    println!("Changes pulled successfully");
+    /// Writes each list in place instead of dropping the collection, so a
+    /// failure halfway never leaves the remote empty.
+    async fn store(&mut self, lists: &[List]) -> TodoResult<()> {
+        let collection = self.db.remote_db().await?.collection::<List>("lists");
+        let options = ReplaceOptions::builder().upsert(true).build();
+
+        for list in lists {
+            collection.replace_one(doc! { "name": &list.name }, list, options.clone()).await?;
+        }
+
+        let names: Vec<&str> = lists.iter().map(|list| list.name.as_str()).collect();
+        collection.delete_many(doc! { "name": { "$nin": names } }, None).await?;
+        Ok(())
+    }
+}
+
+/// Reads a list stored in MongoDB. Older versions pushed items without an
+/// ID; those take the ID of an unclaimed local item with the same
+/// description in the same list, so that the first sync does not see them
+/// as new on both sides. Items without a match get a new ID, which the sync
+/// then writes back.
+fn from_document(mut document: Document, local: &[List]) -> TodoResult<List> {
+    let name = document.get_str("name").unwrap_or_default().to_string();
+    if let Ok(items) = document.get_array_mut("items") {
+        let items: Vec<&mut Document> = items.iter_mut().filter_map(|item| item.as_document_mut()).collect();
+        let remote_ids: HashSet<String> = items.iter().filter_map(|item| item.get_str("id").ok()).map(str::to_string).collect();
+        let mut unclaimed: Vec<&Item> = local.iter()
+            .filter(|list| list.name == name)
+            .flat_map(|list| &list.items)
+            .filter(|item| !remote_ids.contains(&item.id))
+            .collect();
+        for item in items.into_iter().filter(|item| !item.contains_key("id")) {
+            let description = item.get_str("description").unwrap_or_default();
+            let id = match unclaimed.iter().position(|local| local.description == description) {
+                Some(index) => unclaimed.remove(index).id.clone(),
+                None => Item::generate_id(),
+            };
+            item.insert("id", id);
+        }
+    }
+    bson::from_document(document).map_err(|e| TodoError::RemoteError(format!("Invalid list in MongoDB: {}", e)))
+}
+
+async fn load_base(path: &Path) -> TodoResult<Vec<List>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn save_base(path: &Path, lists: &[List]) -> TodoResult<()> {
+    files::write_atomic(path, serde_json::to_string_pretty(lists)?.as_bytes())?;
     Ok(())
 }
 
+fn ask(conflict: &Conflict) -> TodoResult<Resolution> {
+    if !std::io::stdin().is_terminal() {
+        return Err(TodoError::SyncConflict(format!(
+            "item changed on both sides in list '{}', rerun with --ours or --theirs",
+            conflict.list
+        )));
+    }
+
+    println!("Conflict in list '{}':", conflict.list);
+    println!("  base:   {}", describe(conflict.base.as_ref()));
+    println!("  local:  {}", describe(conflict.ours.as_ref()));
+    println!("  remote: {}", describe(conflict.theirs.as_ref()));
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("Keep [l]ocal, [r]emote or [b]oth? ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            return Err(TodoError::SyncConflict("no answer given".to_string()));
+        }
+        match answer.trim() {
+            "l" | "local" => return Ok(Resolution::Ours),
+            "r" | "remote" => return Ok(Resolution::Theirs),
+            "b" | "both" => return Ok(Resolution::Both),
+            _ => continue,
+        }
+    }
+}
+
+fn describe(item: Option<&Item>) -> String {
+    match item {
+        Some(item) => format!("[{}] {}", if item.completed { "x" } else { " " }, item.description),
+        None => "(removed)".to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::Item;
-    use mongodb::bson::doc;
+
+    /// Lists kept as MongoDB documents in memory.
+    struct Documents<'a> {
+        db: &'a Database,
+        documents: Vec<Document>,
+    }
+
+    #[async_trait]
+    impl Remote for Documents<'_> {
+        fn name(&self) -> &str {
+            "documents"
+        }
+
+        fn describe(&self) -> String {
+            "documents".to_string()
+        }
+
+        async fn fetch(&mut self, _base: &[List]) -> TodoResult<Vec<List>> {
+            // Gives other tasks the chance to run, as a network round trip would.
+            tokio::task::yield_now().await;
+            let local = self.db.get_lists_with_trash().await?;
+            self.documents.iter().map(|document| from_document(document.clone(), &local)).collect()
+        }
+
+        async fn store(&mut self, lists: &[List]) -> TodoResult<()> {
+            self.documents = lists.iter().map(|list| bson::to_document(list).unwrap()).collect();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_legacy_documents() {
+        let (db, _dir) = crate::db::test_database().await;
+        db.create_list("Chores").await.unwrap();
+        db.add_item("Chores", Item::new("Dishes")).await.unwrap();
+        db.add_item("Chores", Item { completed: true, ..Item::new("Laundry") }).await.unwrap();
+        let local = db.get_list("Chores").await.unwrap();
+
+        // Pushed by a version that stored items without IDs, then given an
+        // item from another machine.
+        let mut legacy = bson::to_document(&local).unwrap();
+        let items = legacy.get_array_mut("items").unwrap();
+        items.push(bson::to_bson(&Item::new("Water plants")).unwrap());
+        for item in items.iter_mut() {
+            item.as_document_mut().unwrap().remove("id");
+        }
+        let mut remote = Documents { db: &db, documents: vec![legacy] };
+
+        sync(&db, &mut remote, ConflictStrategy::Ask).await.unwrap();
+        let synced = db.get_list("Chores").await.unwrap();
+        let descriptions: Vec<&str> = synced.items.iter().map(|item| item.description.as_str()).collect();
+        assert_eq!(descriptions, vec!["Dishes", "Laundry", "Water plants"]);
+        assert_eq!(synced.items[..2], local.items[..]);
+        assert!(remote.documents[0].get_array("items").unwrap().iter().all(|item| item.as_document().unwrap().contains_key("id")));
+
+        // The IDs written back keep later syncs from changing anything.
+        sync(&db, &mut remote, ConflictStrategy::Ask).await.unwrap();
+        assert_eq!(db.get_list("Chores").await.unwrap(), synced);
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_concurrent_changes() {
+        let (db, _dir) = crate::db::test_database().await;
+        db.create_list("Chores").await.unwrap();
+        let mut remote = Documents { db: &db, documents: Vec::new() };
+
+        let (synced, added) = tokio::join!(
+            sync(&db, &mut remote, ConflictStrategy::Ask),
+            db.add_item("Chores", Item::new("Dishes")),
+        );
+        synced.unwrap();
+        added.unwrap();
+        assert_eq!(db.get_list("Chores").await.unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_conflicts_fail_unless_resolved() {
+        let (db, _dir) = crate::db::test_database().await;
+        db.create_list("Chores").await.unwrap();
+        let item = Item::new("Dishes");
+        db.add_item("Chores", item.clone()).await.unwrap();
+        let mut remote = Documents { db: &db, documents: Vec::new() };
+        sync(&db, &mut remote, ConflictStrategy::Fail).await.unwrap();
+
+        db.update_item_status("Chores", &item.id, true).await.unwrap();
+        let mut theirs = db.get_list("Chores").await.unwrap();
+        theirs.items[0] = Item { description: "Wash dishes".to_string(), ..item.clone() };
+        remote.documents = vec![bson::to_document(&theirs).unwrap()];
+        let local = db.get_list("Chores").await.unwrap();
+
+        let result = sync(&db, &mut remote, ConflictStrategy::Fail).await;
+        assert!(matches!(result, Err(TodoError::SyncConflict(message)) if message.contains("Wash dishes")));
+        assert_eq!(db.get_list("Chores").await.unwrap(), local);
+
+        sync(&db, &mut remote, ConflictStrategy::Theirs).await.unwrap();
+        assert_eq!(db.get_list("Chores").await.unwrap().items[0].description, "Wash dishes");
+    }
 
     #[tokio::test]
     async fn test_push_and_pull() {
         // Create a test database
-        let db = Database::new().await.unwrap();
+        let (db, _dir) = crate::db::test_database().await;
 
         // Prepare test data
         let test_list = List {
             name: "Test List".to_string(),
             items: vec![
+                Item::new("Task 1"),
                 Item {
-                    description: "Task 1".to_string(),
-                    completed: false,
-                },
-                Item {
-                    description: "Task 2".to_string(),
                     completed: true,
+                    ..Item::new("Task 2")
                 },
             ],
+            trashed: None,
+            created_at: None,
+            updated_at: None,
         };
 
         // Update local database
-        let mut local_db = serde_json::Map::new();
-        local_db.insert(
-            test_list.name.clone(),
-            serde_json::to_value(test_list.items.clone()).unwrap(),
-        );
-        db.update_local_db(serde_json::Value::Object(local_db))
+        let lock = db.lock().await.unwrap();
+        db.update_local_db(&lock, vec![test_list.clone()])
             .await
             .unwrap();
+        drop(lock);
 
         // Test push
-        push(&db).await.unwrap();
+        push(&db, SyncTarget::Mongo, ConflictStrategy::Fail).await.unwrap();
 
         // Verify data in remote database
-        let collection = db.remote_db.collection::<List>("lists");
+        let collection = db.remote_db().await.unwrap().collection::<List>("lists");
         let remote_list = collection
             .find_one(doc! { "name": "Test List" }, None)
             .await
@@ -95,24 +410,32 @@ mod tests {
         assert_eq!(remote_list.name, test_list.name);
         assert_eq!(remote_list.items.len(), test_list.items.len());
         for (remote_item, test_item) in remote_list.items.iter().zip(test_list.items.iter()) {
+            assert_eq!(remote_item.id, test_item.id);
             assert_eq!(remote_item.description, test_item.description);
             assert_eq!(remote_item.completed, test_item.completed);
         }
 
-        // Clear local database
-        db.update_local_db(serde_json::Value::Object(serde_json::Map::new()))
+        // Change the remote as another machine would
+        let remote_item = Item::new("Task 3");
+        let mut remote_version = test_list.clone();
+        remote_version.items.push(remote_item.clone());
+        collection
+            .replace_one(doc! { "name": "Test List" }, &remote_version, None)
+            .await
+            .unwrap();
+
+        // Change the local database independently
+        db.update_item_status("Test List", &test_list.items[0].id, true)
             .await
             .unwrap();
 
         // Test pull
-        pull(&db).await.unwrap();
-
-        // Verify data in local database
-        let local_db = db.get_local_db().await.unwrap();
-        let pulled_list = local_db.get("Test List").unwrap();
-        assert_eq!(
-            pulled_list,
-            &serde_json::to_value(test_list.items).unwrap()
-        );
+        pull(&db, SyncTarget::Mongo, ConflictStrategy::Fail).await.unwrap();
+
+        // Verify both changes survived in the local database
+        let pulled_list = db.get_list("Test List").await.unwrap();
+        assert_eq!(pulled_list.items.len(), 3);
+        assert!(pulled_list.items[0].completed);
+        assert_eq!(pulled_list.items[2].id, remote_item.id);
     }
 }
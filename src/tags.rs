@@ -0,0 +1,51 @@
+use std::collections::BTreeSet;
+
+/// Characters that mark a word in a description as a tag.
+const MARKERS: [char; 3] = ['#', '+', '@'];
+
+/// Canonical form of a tag given by the user, with or without a marker.
+/// Returns `None` for anything that is not a valid tag name.
+pub fn normalize(tag: &str) -> Option<String> {
+    let name = tag.strip_prefix(MARKERS).unwrap_or(tag);
+    let valid = name.starts_with(|c: char| c.is_alphabetic())
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/' | '.'));
+    valid.then(|| name.to_lowercase())
+}
+
+/// Splits `#tag`, `+tag` and `@tag` words off a description, returning the
+/// remaining text and the tags. Words like `#42` or `C#` stay in the text.
+pub fn extract(text: &str) -> (String, BTreeSet<String>) {
+    let mut tags = BTreeSet::new();
+    let mut words = Vec::new();
+    for word in text.split_whitespace() {
+        match normalize(word) {
+            Some(tag) if word.starts_with(MARKERS) => {
+                tags.insert(tag);
+            }
+            _ => words.push(word),
+        }
+    }
+    (words.join(" "), tags)
+}
+
+/// How tags are shown next to a description, e.g. ` #home #release`.
+pub fn format(tags: &BTreeSet<String>) -> String {
+    tags.iter().map(|tag| format!(" #{}", tag)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let (text, tags) = extract("Ship  the fix +Release for #42 @home in C# #back-end");
+        assert_eq!(text, "Ship the fix for #42 in C#");
+        assert_eq!(tags.into_iter().collect::<Vec<_>>(), vec!["back-end", "home", "release"]);
+
+        assert_eq!(normalize("#Work"), Some("work".to_string()));
+        assert_eq!(normalize("work"), Some("work".to_string()));
+        assert_eq!(normalize("+"), None);
+        assert_eq!(normalize("two words"), None);
+    }
+}
@@ -0,0 +1,201 @@
+//! Subtasks. An item whose `parent` is the ID of another item of the same
+//! list is nested under it, to any depth; siblings keep their stored order.
+//! Items are addressed by their path of 1-based positions among siblings,
+//! e.g. `3.2` for the second subtask of the third top-level item.
+//!
+//! An item whose parent is missing from the items given, for example because
+//! the parent is in the trash, counts as a top-level item.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use crate::models::Item;
+
+/// An item with its place in the tree of its list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node<'a> {
+    pub item: &'a Item,
+    /// Index of the item in the items the tree was built from.
+    pub index: usize,
+    /// Positions among siblings from the top level down.
+    pub path: Vec<usize>,
+    /// The parent the item is nested under, if any.
+    pub parent: Option<&'a str>,
+    /// Completed and total descendants, for items that have any.
+    pub progress: Option<(usize, usize)>,
+}
+
+impl Node<'_> {
+    pub fn depth(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    /// The path as typed to address the item, e.g. `3.2`.
+    pub fn reference(&self) -> String {
+        format_path(&self.path)
+    }
+}
+
+pub fn format_path(path: &[usize]) -> String {
+    path.iter().map(usize::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// Parses a path such as `3` or `3.2`.
+pub fn parse_path(reference: &str) -> Option<Vec<usize>> {
+    reference.split('.')
+        .map(|position| position.parse().ok().filter(|&position| position >= 1))
+        .collect()
+}
+
+/// How siblings are ordered for display.
+pub type Compare = dyn Fn(&Item, &Item) -> Ordering;
+
+/// Every item in tree order: each top-level item followed by its subtasks.
+pub fn walk(items: &[Item]) -> Vec<Node<'_>> {
+    walk_by(items, None)
+}
+
+/// Like `walk`, with siblings sorted by `order` if given. Paths stay those
+/// of the stored order so that they can still address items.
+pub fn walk_by<'a>(items: &'a [Item], order: Option<&Compare>) -> Vec<Node<'a>> {
+    let ids: HashSet<&str> = items.iter().map(|item| item.id.as_str()).collect();
+    let mut children: HashMap<Option<&str>, Vec<usize>> = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        let parent = item.parent.as_deref().filter(|parent| ids.contains(parent) && *parent != item.id);
+        children.entry(parent).or_default().push(index);
+    }
+
+    let mut nodes = Vec::with_capacity(items.len());
+    let mut visited = vec![false; items.len()];
+    let roots = children.get(&None).cloned().unwrap_or_default();
+    add_siblings(items, &children, order, &roots, &[], None, &mut visited, &mut nodes);
+    // Items in a cycle of parents have no way up; show them at the top level.
+    let mut position = roots.len();
+    for index in 0..items.len() {
+        if !visited[index] {
+            position += 1;
+            let start = nodes.len();
+            add_siblings(items, &children, order, &[index], &[], None, &mut visited, &mut nodes);
+            for node in &mut nodes[start..] {
+                node.path[0] = position;
+            }
+        }
+    }
+    nodes
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_siblings<'a>(
+    items: &'a [Item],
+    children: &HashMap<Option<&str>, Vec<usize>>,
+    order: Option<&Compare>,
+    siblings: &[usize],
+    path: &[usize],
+    parent: Option<&'a str>,
+    visited: &mut [bool],
+    nodes: &mut Vec<Node<'a>>,
+) -> (usize, usize) {
+    let mut numbered: Vec<(usize, usize)> = siblings.iter().enumerate().map(|(i, &index)| (i + 1, index)).collect();
+    if let Some(order) = order {
+        numbered.sort_by(|(_, a), (_, b)| order(&items[*a], &items[*b]));
+    }
+
+    let (mut done, mut total) = (0, 0);
+    for (position, index) in numbered {
+        if visited[index] {
+            continue;
+        }
+        visited[index] = true;
+        let item = &items[index];
+        let path = [path, &[position]].concat();
+        let at = nodes.len();
+        nodes.push(Node { item, index, path: path.clone(), parent, progress: None });
+
+        let below = children.get(&Some(item.id.as_str())).map(Vec::as_slice).unwrap_or_default();
+        let (below_done, below_total) = add_siblings(items, children, order, below, &path, Some(&item.id), visited, nodes);
+        if below_total > 0 {
+            nodes[at].progress = Some((below_done, below_total));
+        }
+        done += below_done + usize::from(item.completed);
+        total += below_total + 1;
+    }
+    (done, total)
+}
+
+/// Index of the item at `path`.
+pub fn find_path(items: &[Item], path: &[usize]) -> Option<usize> {
+    walk(items).into_iter().find(|node| node.path == path).map(|node| node.index)
+}
+
+/// Indexes of the top-level items, in order.
+pub fn roots(items: &[Item]) -> Vec<usize> {
+    walk(items).into_iter().filter(|node| node.depth() == 0).map(|node| node.index).collect()
+}
+
+/// Indexes of the subtasks of the item at `index`, at any depth, in tree
+/// order.
+pub fn descendants(items: &[Item], index: usize) -> Vec<usize> {
+    let nodes = walk(items);
+    let Some(start) = nodes.iter().position(|node| node.index == index) else {
+        return Vec::new();
+    };
+    let depth = nodes[start].depth();
+    nodes[start + 1..].iter()
+        .take_while(|node| node.depth() > depth)
+        .map(|node| node.index)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn child(description: &str, parent: &Item) -> Item {
+        Item { parent: Some(parent.id.clone()), ..Item::new(description) }
+    }
+
+    #[test]
+    fn test_walk() {
+        let trip = Item::new("Plan trip");
+        let flights = Item { completed: true, ..child("Book flights", &trip) };
+        let hotel = child("Book hotel", &trip);
+        let visa = child("Get visa", &hotel);
+        let orphan = Item { parent: Some("gone".to_string()), ..Item::new("Orphan") };
+        let items = vec![visa.clone(), trip.clone(), Item::new("Call Bob"), hotel.clone(), orphan, flights.clone()];
+
+        let nodes = walk(&items);
+        let outline: Vec<_> = nodes.iter()
+            .map(|node| (node.reference(), node.item.description.as_str(), node.progress))
+            .collect();
+        assert_eq!(outline, vec![
+            ("1".to_string(), "Plan trip", Some((1, 3))),
+            ("1.1".to_string(), "Book hotel", Some((0, 1))),
+            ("1.1.1".to_string(), "Get visa", None),
+            ("1.2".to_string(), "Book flights", None),
+            ("2".to_string(), "Call Bob", None),
+            ("3".to_string(), "Orphan", None),
+        ]);
+        assert_eq!(nodes[1].parent, Some(trip.id.as_str()));
+
+        assert_eq!(parse_path("1.1.1"), Some(vec![1, 1, 1]));
+        assert_eq!(parse_path("1.0"), None);
+        assert_eq!(parse_path("abc"), None);
+        assert_eq!(find_path(&items, &[1, 1, 1]), Some(0));
+        assert_eq!(find_path(&items, &[2, 1]), None);
+        assert_eq!(roots(&items), vec![1, 2, 4]);
+        assert_eq!(descendants(&items, 1), vec![3, 0, 5]);
+        assert_eq!(descendants(&items, 2), Vec::<usize>::new());
+
+        // Sorting reorders siblings but keeps their paths.
+        let by_name = |a: &Item, b: &Item| a.description.cmp(&b.description);
+        let sorted: Vec<String> = walk_by(&items, Some(&by_name)).iter().map(Node::reference).collect();
+        assert_eq!(sorted, vec!["2", "3", "1", "1.2", "1.1", "1.1.1"]);
+
+        // A cycle of parents must not lose items.
+        let mut a = Item::new("a");
+        let b = child("b", &a);
+        a.parent = Some(b.id.clone());
+        let cycle = vec![Item::new("c"), a, b];
+        let paths: Vec<String> = walk(&cycle).iter().map(Node::reference).collect();
+        assert_eq!(paths, vec!["1", "2", "2.1"]);
+    }
+}
@@ -0,0 +1,512 @@
+use std::time::Duration;
+use chrono::Utc;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List as ListWidget, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use crate::db::Database;
+use crate::error::TodoResult;
+use crate::models::{Item, List};
+use crate::output;
+use crate::sync::{self, SyncTarget};
+use crate::tags;
+use crate::tree::{self, Node};
+
+/// How often the lists are reread while idle, to pick up changes made by
+/// other `todo` invocations.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pane {
+    Lists,
+    Items,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Show {
+    All,
+    Incomplete,
+    Completed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Normal,
+    Input { action: InputAction, buffer: String },
+    Confirm(Removal),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum InputAction {
+    AddList,
+    AddItem,
+    /// Change the description of the item with this ID.
+    EditItem(String),
+    Filter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Removal {
+    List(String),
+    Item { list: String, id: String, description: String },
+}
+
+/// State of the terminal interface. Every change goes through `Database`
+/// and is followed by a reload, so the screen always shows what is on disk.
+struct App {
+    lists: Vec<List>,
+    list_index: usize,
+    item_index: usize,
+    focus: Pane,
+    mode: Mode,
+    show: Show,
+    filter: String,
+    message: Option<String>,
+    sync_status: String,
+}
+
+pub async fn run(db: &Database) -> TodoResult<()> {
+    let mut app = App::new(db).await?;
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &mut app, db).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(terminal: &mut DefaultTerminal, app: &mut App, db: &Database) -> TodoResult<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if !event::poll(REFRESH_INTERVAL)? {
+            // The database can be busy, e.g. during a long `todo sync`; the
+            // next refresh tries again.
+            if let Err(e) = app.reload(db).await {
+                app.message = Some(e.to_string());
+            }
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && app.handle_key(db, key).await {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl App {
+    async fn new(db: &Database) -> TodoResult<Self> {
+        let mut app = Self {
+            lists: Vec::new(),
+            list_index: 0,
+            item_index: 0,
+            focus: Pane::Lists,
+            mode: Mode::Normal,
+            show: Show::All,
+            filter: String::new(),
+            message: None,
+            sync_status: String::new(),
+        };
+        app.reload(db).await?;
+        Ok(app)
+    }
+
+    /// Rereads the lists, keeping the selected list by name.
+    async fn reload(&mut self, db: &Database) -> TodoResult<()> {
+        let selected = self.selected_list().map(|list| list.name.clone());
+        self.lists = db.get_lists().await?;
+        if let Some(index) = selected.and_then(|name| self.lists.iter().position(|list| list.name == name)) {
+            self.list_index = index;
+        }
+        self.list_index = self.list_index.min(self.lists.len().saturating_sub(1));
+        self.item_index = self.item_index.min(self.visible_items().len().saturating_sub(1));
+        self.sync_status = sync_status(db).await;
+        Ok(())
+    }
+
+    fn selected_list(&self) -> Option<&List> {
+        self.lists.get(self.list_index)
+    }
+
+    /// Items of the selected list passing the filters, in tree order.
+    fn visible_items(&self) -> Vec<Node<'_>> {
+        let filter = self.filter.to_lowercase();
+        self.selected_list()
+            .map(|list| tree::walk(&list.items).into_iter()
+                .filter(|Node { item, .. }| match self.show {
+                    Show::All => true,
+                    Show::Incomplete => !item.completed,
+                    Show::Completed => item.completed,
+                })
+                .filter(|node| node.item.description.to_lowercase().contains(&filter))
+                .collect())
+            .unwrap_or_default()
+    }
+
+    fn selected_item(&self) -> Option<&Item> {
+        self.visible_items().get(self.item_index).map(|node| node.item)
+    }
+
+    /// Handles one key press and returns whether to quit. Errors are shown
+    /// in the status line rather than ending the session.
+    async fn handle_key(&mut self, db: &Database, key: KeyEvent) -> bool {
+        self.message = None;
+        let result = match self.mode.clone() {
+            Mode::Normal => return self.handle_normal_key(db, key).await,
+            Mode::Input { action, buffer } => self.handle_input_key(db, key, action, buffer).await,
+            Mode::Confirm(removal) => self.handle_confirm_key(db, key, removal).await,
+        };
+        if let Err(e) = result {
+            self.message = Some(e.to_string());
+        }
+        false
+    }
+
+    async fn handle_normal_key(&mut self, db: &Database, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Esc if self.filter.is_empty() => return true,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.item_index = 0;
+            }
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right | KeyCode::Char('h') | KeyCode::Char('l') => {
+                self.focus = match self.focus {
+                    Pane::Lists => Pane::Items,
+                    Pane::Items => Pane::Lists,
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Enter if self.focus == Pane::Lists => self.focus = Pane::Items,
+            KeyCode::Enter | KeyCode::Char(' ') if self.focus == Pane::Items => {
+                if let Some(item) = self.selected_item() {
+                    let (id, completed) = (item.id.clone(), item.completed);
+                    let list = self.lists[self.list_index].name.clone();
+                    let result = db.update_item_status(&list, &id, !completed).await;
+                    self.finish(db, result).await;
+                }
+            }
+            KeyCode::Char('a') => {
+                let action = if self.focus == Pane::Items && self.selected_list().is_some() {
+                    InputAction::AddItem
+                } else {
+                    InputAction::AddList
+                };
+                self.mode = Mode::Input { action, buffer: String::new() };
+            }
+            KeyCode::Char('e') if self.focus == Pane::Items => {
+                if let Some(item) = self.selected_item() {
+                    self.mode = Mode::Input { action: InputAction::EditItem(item.id.clone()), buffer: item.description.clone() };
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Delete => {
+                let removal = match self.focus {
+                    Pane::Lists => self.selected_list().map(|list| Removal::List(list.name.clone())),
+                    Pane::Items => self.selected_item().map(|item| Removal::Item {
+                        list: self.lists[self.list_index].name.clone(),
+                        id: item.id.clone(),
+                        description: item.description.clone(),
+                    }),
+                };
+                if let Some(removal) = removal {
+                    self.mode = Mode::Confirm(removal);
+                }
+            }
+            KeyCode::Char('/') => {
+                self.mode = Mode::Input { action: InputAction::Filter, buffer: self.filter.clone() };
+            }
+            KeyCode::Char('c') => {
+                self.show = match self.show {
+                    Show::All => Show::Incomplete,
+                    Show::Incomplete => Show::Completed,
+                    Show::Completed => Show::All,
+                };
+                self.item_index = 0;
+            }
+            KeyCode::Char('u') => {
+                let result = db.undo().await;
+                self.report_history(db, result, "Undid", "Nothing to undo").await;
+            }
+            KeyCode::Char('U') => {
+                let result = db.redo().await;
+                self.report_history(db, result, "Redid", "Nothing to redo").await;
+            }
+            KeyCode::Char('r') => self.finish(db, Ok(())).await,
+            _ => {}
+        }
+        false
+    }
+
+    async fn handle_input_key(&mut self, db: &Database, key: KeyEvent, action: InputAction, mut buffer: String) -> TodoResult<()> {
+        match key.code {
+            KeyCode::Esc => {
+                if action == InputAction::Filter {
+                    self.filter.clear();
+                }
+                self.mode = Mode::Normal;
+                return Ok(());
+            }
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                return self.submit(db, action, buffer.trim()).await;
+            }
+            KeyCode::Backspace => {
+                buffer.pop();
+            }
+            KeyCode::Char(c) => buffer.push(c),
+            _ => {}
+        }
+        if action == InputAction::Filter {
+            self.filter = buffer.clone();
+            self.item_index = 0;
+        }
+        self.mode = Mode::Input { action, buffer };
+        Ok(())
+    }
+
+    async fn submit(&mut self, db: &Database, action: InputAction, text: &str) -> TodoResult<()> {
+        if text.is_empty() && action != InputAction::Filter {
+            return Ok(());
+        }
+        match action {
+            InputAction::AddList => {
+                db.create_list(text).await?;
+                self.reload(db).await?;
+                if let Some(index) = self.lists.iter().position(|list| list.name == text) {
+                    self.list_index = index;
+                    self.item_index = 0;
+                }
+                self.focus = Pane::Items;
+            }
+            InputAction::AddItem => {
+                let list = self.lists[self.list_index].name.clone();
+                let item = Item::from_input(text);
+                let id = item.id.clone();
+                db.add_item(&list, item).await?;
+                self.reload(db).await?;
+                if let Some(index) = self.visible_items().iter().position(|node| node.item.id == id) {
+                    self.item_index = index;
+                }
+            }
+            InputAction::EditItem(id) => {
+                let list = self.lists[self.list_index].name.clone();
+                db.update_item(&list, &id, |item| item.description = text.to_string()).await?;
+                self.reload(db).await?;
+            }
+            InputAction::Filter => self.filter = text.to_string(),
+        }
+        Ok(())
+    }
+
+    async fn handle_confirm_key(&mut self, db: &Database, key: KeyEvent, removal: Removal) -> TodoResult<()> {
+        self.mode = Mode::Normal;
+        if key.code != KeyCode::Char('y') {
+            return Ok(());
+        }
+        match removal {
+            Removal::List(name) => db.remove_list(&name).await?,
+            Removal::Item { list, id, .. } => db.remove_item(&list, &id).await?,
+        }
+        self.reload(db).await
+    }
+
+    /// Reloads after a change, reporting a failed change or reload.
+    async fn finish(&mut self, db: &Database, result: TodoResult<()>) {
+        if let Err(e) = result.and(self.reload(db).await) {
+            self.message = Some(e.to_string());
+        }
+    }
+
+    /// Reloads after an undo or redo and says what it did.
+    async fn report_history(&mut self, db: &Database, result: TodoResult<Option<String>>, done: &str, nothing: &str) {
+        match result {
+            Ok(Some(action)) => {
+                self.finish(db, Ok(())).await;
+                self.message.get_or_insert(format!("{}: {}", done, action));
+            }
+            Ok(None) => self.message = Some(nothing.to_string()),
+            Err(e) => self.message = Some(e.to_string()),
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let (index, len) = match self.focus {
+            Pane::Lists => (&mut self.list_index, self.lists.len()),
+            Pane::Items => {
+                let len = self.visible_items().len();
+                (&mut self.item_index, len)
+            }
+        };
+        if len > 0 {
+            *index = index.saturating_add_signed(delta).min(len - 1);
+        }
+        if self.focus == Pane::Lists {
+            self.item_index = 0;
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(3), Constraint::Length(2)]).areas(frame.area());
+        let [lists_area, items_area] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(main);
+
+        let pane_block = |title: String, pane: Pane| {
+            let block = Block::bordered().title(title);
+            if self.focus == pane {
+                block.border_style(Style::new().fg(Color::Yellow))
+            } else {
+                block
+            }
+        };
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let lists: Vec<ListItem> = self.lists.iter()
+            .map(|list| {
+                let open = list.items.iter().filter(|item| !item.completed).count();
+                ListItem::new(format!("{} ({}/{})", list.name, open, list.items.len()))
+            })
+            .collect();
+        let mut lists_state = ListState::default().with_selected((!self.lists.is_empty()).then_some(self.list_index));
+        frame.render_stateful_widget(
+            ListWidget::new(lists).block(pane_block(" Lists ".to_string(), Pane::Lists)).highlight_style(highlight),
+            lists_area,
+            &mut lists_state,
+        );
+
+        let now = Utc::now();
+        let visible = self.visible_items();
+        let items: Vec<ListItem> = visible.iter()
+            .map(|node| {
+                let item = node.item;
+                let text = format!(
+                    "{}[{}] {}{}{}{}{}{}",
+                    "  ".repeat(node.depth()), if item.completed { "x" } else { " " }, item.priority.badge(), item.description,
+                    output::progress(node),
+                    tags::format(&item.tags), output::format_dates(item, now), output::format_recurrence(item)
+                );
+                if item.is_overdue(now) {
+                    ListItem::new(text).style(Style::new().fg(Color::Red))
+                } else {
+                    ListItem::new(text)
+                }
+            })
+            .collect();
+        let mut title = format!(" {} ", self.selected_list().map(|list| list.name.as_str()).unwrap_or("Items"));
+        match self.show {
+            Show::All => {}
+            Show::Incomplete => title.push_str("[incomplete] "),
+            Show::Completed => title.push_str("[completed] "),
+        }
+        if !self.filter.is_empty() {
+            title.push_str(&format!("[/{}] ", self.filter));
+        }
+        let mut items_state = ListState::default().with_selected((!visible.is_empty()).then_some(self.item_index));
+        frame.render_stateful_widget(
+            ListWidget::new(items).block(pane_block(title, Pane::Items)).highlight_style(highlight),
+            items_area,
+            &mut items_state,
+        );
+
+        let prompt = match &self.mode {
+            Mode::Input { action, buffer } => {
+                let label = match action {
+                    InputAction::AddList => "New list",
+                    InputAction::AddItem => "New item",
+                    InputAction::EditItem(_) => "Description",
+                    InputAction::Filter => "Filter",
+                };
+                format!("{}: {}_", label, buffer)
+            }
+            Mode::Confirm(Removal::List(name)) => format!("Move list '{}' and all its items to the trash? [y/N]", name),
+            Mode::Confirm(Removal::Item { description, .. }) => format!("Move '{}' to the trash? [y/N]", description),
+            Mode::Normal => self.message.clone().unwrap_or_default(),
+        };
+        let help = format!(
+            "{} | a add  e edit  d remove  space toggle  u/U undo/redo  / filter  c completed  r reload  q quit",
+            self.sync_status
+        );
+        frame.render_widget(
+            Paragraph::new(vec![Line::from(prompt), Line::styled(help, Style::new().fg(Color::DarkGray))]),
+            status,
+        );
+    }
+}
+
+/// Summarises, per remote that was synced before, whether there are local
+/// changes it has not seen.
+async fn sync_status(db: &Database) -> String {
+    let mut parts = Vec::new();
+    for target in SyncTarget::ALL {
+        match sync::has_unsynced_changes(db, target).await {
+            Ok(Some(true)) => parts.push(format!("{}: unsynced changes", target.name())),
+            Ok(Some(false)) => parts.push(format!("{}: synced", target.name())),
+            Ok(None) => {}
+            Err(_) => parts.push(format!("{}: unknown", target.name())),
+        }
+    }
+    if parts.is_empty() {
+        "never synced".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    async fn press(app: &mut App, db: &Database, keys: &[KeyCode]) {
+        for key in keys {
+            assert!(!app.handle_key(db, KeyEvent::from(*key)).await, "unexpected quit on {:?}", key);
+        }
+    }
+
+    async fn type_text(app: &mut App, db: &Database, text: &str) {
+        let keys: Vec<KeyCode> = text.chars().map(KeyCode::Char).chain([KeyCode::Enter]).collect();
+        press(app, db, &keys).await;
+    }
+
+    #[tokio::test]
+    async fn test_tui_edits_database() {
+        let (db, _dir) = test_database().await;
+        let mut app = App::new(&db).await.unwrap();
+        assert_eq!(app.sync_status, "never synced");
+
+        press(&mut app, &db, &[KeyCode::Char('a')]).await;
+        type_text(&mut app, &db, "Shop").await;
+        assert_eq!(app.focus, Pane::Items);
+        for item in ["Milk", "Bread", "Eggs"] {
+            press(&mut app, &db, &[KeyCode::Char('a')]).await;
+            type_text(&mut app, &db, item).await;
+        }
+        assert_eq!(app.selected_item().unwrap().description, "Eggs");
+
+        // Complete "Bread" and rename "Milk"
+        press(&mut app, &db, &[KeyCode::Up, KeyCode::Char(' '), KeyCode::Up, KeyCode::Char('e')]).await;
+        press(&mut app, &db, &[KeyCode::Backspace; 4]).await;
+        type_text(&mut app, &db, "Oat milk").await;
+        let list = db.get_list("Shop").await.unwrap();
+        let descriptions: Vec<&str> = list.items.iter().map(|item| item.description.as_str()).collect();
+        assert_eq!(descriptions, vec!["Oat milk", "Bread", "Eggs"]);
+        assert!(list.items[1].completed);
+
+        // Filters only change what is shown
+        press(&mut app, &db, &[KeyCode::Char('c')]).await;
+        assert_eq!(app.visible_items().len(), 2);
+        press(&mut app, &db, &[KeyCode::Char('/'), KeyCode::Char('e'), KeyCode::Char('g')]).await;
+        assert_eq!(app.visible_items()[0].item.description, "Eggs");
+        press(&mut app, &db, &[KeyCode::Esc, KeyCode::Char('c'), KeyCode::Char('c')]).await;
+        assert_eq!(app.visible_items().len(), 3);
+
+        // Removal needs confirmation
+        press(&mut app, &db, &[KeyCode::Char('d'), KeyCode::Char('n')]).await;
+        assert_eq!(db.get_list("Shop").await.unwrap().items.len(), 3);
+        press(&mut app, &db, &[KeyCode::Char('d'), KeyCode::Char('y')]).await;
+        assert_eq!(db.get_list("Shop").await.unwrap().items.len(), 2);
+
+        press(&mut app, &db, &[KeyCode::Tab, KeyCode::Char('d'), KeyCode::Char('y')]).await;
+        assert!(db.get_lists().await.unwrap().is_empty());
+        assert!(app.handle_key(&db, KeyEvent::from(KeyCode::Char('q'))).await);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::error::{TodoError, TodoResult};
+use crate::{files, paths};
+
+/// Named `--where` filters for `todo show --view`, kept in `views.json` in
+/// the config directory as an object of name to expression. The file can be
+/// edited by hand.
+pub struct Views {
+    path: PathBuf,
+}
+
+impl Views {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf() }
+    }
+
+    pub fn open() -> TodoResult<Self> {
+        Ok(Self::new(&paths::config_dir()?.join("views.json")))
+    }
+
+    fn load(&self) -> TodoResult<BTreeMap<String, String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> TodoResult<String> {
+        let mut views = self.load()?;
+        views.remove(name).ok_or_else(|| {
+            let known: Vec<String> = views.into_keys().collect();
+            TodoError::ConfigError(if known.is_empty() {
+                format!("No view named '{}', save one with `todo show --where ... --save-view {}`", name, name)
+            } else {
+                format!("No view named '{}', known views: {}", name, known.join(", "))
+            })
+        })
+    }
+
+    /// Saves `filter` as `name`, replacing any view of that name.
+    pub fn save(&self, name: &str, filter: &str) -> TodoResult<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let _lock = files::FileLock::acquire(&self.path)?;
+        let mut views = self.load()?;
+        views.insert(name.to_string(), filter.to_string());
+        files::write_atomic(&self.path, serde_json::to_string_pretty(&views)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let views = Views::new(&dir.path().join("config").join("views.json"));
+        assert!(matches!(views.get("work"), Err(TodoError::ConfigError(_))));
+
+        views.save("work", "tag:work and status:open").unwrap();
+        views.save("urgent", "priority>=high").unwrap();
+        views.save("work", "tag:work").unwrap();
+        assert_eq!(views.get("work").unwrap(), "tag:work");
+        assert!(matches!(views.get("home"), Err(TodoError::ConfigError(message)) if message.contains("urgent, work")));
+    }
+}
@@ -0,0 +1,111 @@
+//! Runs many `todo` processes against the same local database at once.
+
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread;
+
+const PROCESSES: usize = 8;
+const COMMANDS_PER_PROCESS: usize = 5;
+
+fn todo(dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_todo"))
+        .args(args)
+        .current_dir(dir)
+        .env("TODO_DB_PATH", dir.join("local_db.json"))
+        .env_remove("TODO_STORAGE")
+        .output()
+        .expect("Failed to run todo");
+    assert!(output.status.success(), "todo {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Runs `command(process, i)` from `PROCESSES` threads at once and returns
+/// everything they printed.
+fn hammer(dir: &Path, command: impl Fn(usize, usize) -> Vec<String> + Send + Sync + Copy + 'static) -> String {
+    let threads: Vec<_> = (0..PROCESSES)
+        .map(|process| {
+            let dir = dir.to_path_buf();
+            thread::spawn(move || {
+                let mut printed = String::new();
+                for i in 0..COMMANDS_PER_PROCESS {
+                    let args = command(process, i);
+                    let output = todo(&dir, &args.iter().map(String::as_str).collect::<Vec<_>>());
+                    printed.push_str(&String::from_utf8_lossy(&output.stdout));
+                }
+                printed
+            })
+        })
+        .collect();
+    threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+}
+
+fn items(dir: &Path) -> Vec<serde_json::Value> {
+    list_items(dir, "Shared")
+}
+
+fn list_items(dir: &Path, list: &str) -> Vec<serde_json::Value> {
+    let output = todo(dir, &["show", list, "--format", "json"]);
+    let shown: serde_json::Value = serde_json::from_slice(&output.stdout).expect("Local database is corrupt");
+    shown["lists"][0]["items"].as_array().unwrap().clone()
+}
+
+#[test]
+fn test_concurrent_processes_keep_every_write() {
+    let dir = tempfile::tempdir().unwrap();
+
+    hammer(dir.path(), |process, i| vec!["add".into(), "Shared".into(), format!("task {}-{}", process, i)]);
+
+    let added = items(dir.path());
+    assert_eq!(added.len(), PROCESSES * COMMANDS_PER_PROCESS);
+    let mut descriptions: Vec<&str> = added.iter().map(|item| item["description"].as_str().unwrap()).collect();
+    descriptions.sort();
+    descriptions.dedup();
+    assert_eq!(descriptions.len(), PROCESSES * COMMANDS_PER_PROCESS);
+
+    // Every process completes its own items while the others do the same.
+    hammer(dir.path(), |process, i| vec!["complete".into(), "Shared".into(), (process * COMMANDS_PER_PROCESS + i + 1).to_string()]);
+
+    assert!(items(dir.path()).iter().all(|item| item["completed"] == true));
+}
+
+#[test]
+fn test_adds_survive_moves_renames_and_undo() {
+    let dir = tempfile::tempdir().unwrap();
+    for process in (1..PROCESSES).step_by(2) {
+        todo(dir.path(), &["add", &format!("Own {}", process), "placeholder"]);
+    }
+    todo(dir.path(), &["add", "Shared", "seed"]);
+    const BIG: usize = 30;
+    for i in 0..BIG {
+        todo(dir.path(), &["add", "Big", &format!("big {}", i)]);
+    }
+
+    // Half the processes add items while the other half rewrite whole
+    // lists and undo whatever happened last.
+    let printed = hammer(dir.path(), |process, i| {
+        let args: Vec<String> = if process % 2 == 0 {
+            vec!["add".into(), "Shared".into(), format!("task {}-{}", process, i)]
+        } else {
+            match i {
+                0 => vec!["rename-list".into(), format!("Own {}", process), format!("Kept {}", process)],
+                1 => vec!["reorder".into(), "Big".into(), "1".into(), "--bottom".into()],
+                2 => vec!["move".into(), "Big".into(), "1".into(), format!("Moved {}", process)],
+                3 => vec!["undo".into()],
+                _ => vec!["copy".into(), "Big".into(), "2".into(), "Big".into(), "--top".into()],
+            }
+        };
+        args
+    });
+
+    let added = PROCESSES / 2 * COMMANDS_PER_PROCESS;
+    let undone = printed.lines().filter(|line| line.starts_with("Undid: add ") && line.ends_with(" to 'Shared'")).count();
+    let shared = list_items(dir.path(), "Shared");
+    assert_eq!(shared.len(), 1 + added - undone, "{}", printed);
+
+    // Moves and copies neither lose nor duplicate anything either.
+    let undone_moves = printed.lines().filter(|line| line.starts_with("Undid: move items from 'Big'")).count();
+    let undone_copies = printed.lines().filter(|line| line.starts_with("Undid: copy items from 'Big'")).count();
+    let movers = PROCESSES / 2;
+    let big = list_items(dir.path(), "Big");
+    assert_eq!(big.len(), BIG - movers + undone_moves + movers - undone_copies, "{}", printed);
+}